@@ -0,0 +1,15 @@
+//! Capture the version of the compiler, for the `process.runtime.*` resource attributes.
+use std::process::Command;
+
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".into());
+    let version = Command::new(rustc)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|version| version.trim().to_owned())
+        .unwrap_or_else(|| "rustc".into());
+    println!("cargo:rustc-env=RUSTC_VERSION={version}");
+    println!("cargo:rerun-if-env-changed=RUSTC");
+}
@@ -0,0 +1,612 @@
+//! Where spans and log records are exported to, configured through the environment variables every
+//! OpenTelemetry SDK understands.
+//!
+//! We support the following [standard variables](https://opentelemetry.io/docs/specs/otel/configuration/sdk-environment-variables/):
+//!
+//! - `OTEL_TRACES_EXPORTER` and `OTEL_LOGS_EXPORTER`: `otlp` or `console` (i.e. OTLP/JSON on
+//!   stdout);
+//! - `OTEL_EXPORTER_OTLP_PROTOCOL`: `grpc`, `http/protobuf` or `http/json`;
+//! - `OTEL_EXPORTER_OTLP_ENDPOINT`, `OTEL_EXPORTER_OTLP_HEADERS`, `OTEL_EXPORTER_OTLP_TIMEOUT`;
+//! - the `OTEL_EXPORTER_OTLP_TRACES_*` and `OTEL_EXPORTER_OTLP_LOGS_*` variants of the
+//!   `OTEL_EXPORTER_OTLP_*` variables, which take precedence for their own signal;
+//! - `OTEL_SERVICE_NAME` and `OTEL_RESOURCE_ATTRIBUTES`;
+//! - `OTEL_TRACES_SAMPLER` and `OTEL_TRACES_SAMPLER_ARG`.
+//!
+//! They override the defaults of a [`Preset`], which captures the settings of a given
+//! backend, and the resource attributes found by the [`resource`](crate::resource) detectors.
+//!
+//! Spans and log records share the same resource.
+//!
+//! Span exports are measured (see [`helpers::self_telemetry`]): failures, timeouts, batch sizes
+//! and latencies are reported as `metrics`, and failures are logged to stderr.
+use crate::exporters::{Encoding, HttpExporter, StdoutExporter};
+use crate::resource;
+use helpers::self_telemetry::{FallbackLog, MeteredSpanExporter};
+use opentelemetry::logs::LogError;
+use opentelemetry::trace::TraceError;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::logs::LoggerProvider;
+use opentelemetry_sdk::resource::ResourceDetector;
+use opentelemetry_sdk::trace::{Config, Sampler, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use std::fmt::{Display, Formatter};
+use std::time::Duration;
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+
+/// The service name used when neither the detectors nor the environment set one.
+const DEFAULT_SERVICE_NAME: &str = "rust-telemetry-workshop";
+
+/// How spans leave the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exporter {
+    Otlp(Protocol),
+    /// Print each batch of spans to stdout, as an OTLP/JSON export request.
+    Stdout,
+}
+
+/// The transports defined by the OTLP specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Grpc,
+    HttpProtobuf,
+    HttpJson,
+}
+
+/// Sensible defaults for a given backend, that the standard environment variables can
+/// override.
+#[derive(Debug, Clone)]
+pub enum Preset {
+    /// An OpenTelemetry collector (or any OTLP-compatible agent, e.g. Jaeger) listening on
+    /// its default ports on `localhost`. These are the defaults mandated by the specification.
+    Local,
+    /// [Honeycomb](https://www.honeycomb.io/), authenticated with an API key.
+    Honeycomb { api_key: String },
+    /// No backend at all: spans are printed to stdout.
+    Stdout,
+}
+
+impl Preset {
+    /// Use Honeycomb if `HONEYCOMB_API_KEY` is set, a local collector otherwise.
+    pub fn from_env() -> Self {
+        Self::from_vars(env_var)
+    }
+
+    fn from_vars(vars: impl Fn(&str) -> Option<String>) -> Self {
+        match vars("HONEYCOMB_API_KEY") {
+            Some(api_key) => Preset::Honeycomb { api_key },
+            None => Preset::Local,
+        }
+    }
+
+    /// The endpoint to send spans to, before the signal-specific path is appended.
+    fn base_endpoint(&self, protocol: Protocol) -> &'static str {
+        match (self, protocol) {
+            (Preset::Honeycomb { .. }, Protocol::Grpc) => "https://api.honeycomb.io:443",
+            (Preset::Honeycomb { .. }, _) => "https://api.honeycomb.io",
+            (_, Protocol::Grpc) => "http://localhost:4317",
+            (_, _) => "http://localhost:4318",
+        }
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        match self {
+            Preset::Honeycomb { api_key } => vec![("x-honeycomb-team".into(), api_key.clone())],
+            Preset::Local | Preset::Stdout => Vec::new(),
+        }
+    }
+
+    fn timeout(&self) -> Duration {
+        match self {
+            Preset::Honeycomb { .. } => Duration::from_secs(5),
+            Preset::Local | Preset::Stdout => Duration::from_secs(10),
+        }
+    }
+}
+
+/// Everything we need to build a tracer provider and a logger provider.
+#[derive(Debug)]
+pub struct ExporterConfig {
+    pub traces: SignalConfig,
+    pub logs: SignalConfig,
+    /// Always includes `service.name`. See [`resource`](crate::resource) for the precedence
+    /// rules.
+    pub resource: Vec<KeyValue>,
+    pub sampler: Sampler,
+}
+
+/// How a signal (spans or log records) is exported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignalConfig {
+    pub exporter: Exporter,
+    /// The full URL the signal is sent to. Ignored by [`Exporter::Stdout`].
+    pub endpoint: String,
+    pub headers: Vec<(String, String)>,
+    pub timeout: Duration,
+}
+
+/// The names of the variables that only apply to one signal.
+struct Signal {
+    exporter: &'static str,
+    protocol: &'static str,
+    endpoint: &'static str,
+    headers: &'static str,
+    timeout: &'static str,
+    /// Appended to the generic endpoint when using HTTP.
+    path: &'static str,
+}
+
+const TRACES: Signal = Signal {
+    exporter: "OTEL_TRACES_EXPORTER",
+    protocol: "OTEL_EXPORTER_OTLP_TRACES_PROTOCOL",
+    endpoint: "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    headers: "OTEL_EXPORTER_OTLP_TRACES_HEADERS",
+    timeout: "OTEL_EXPORTER_OTLP_TRACES_TIMEOUT",
+    path: "/v1/traces",
+};
+
+const LOGS: Signal = Signal {
+    exporter: "OTEL_LOGS_EXPORTER",
+    protocol: "OTEL_EXPORTER_OTLP_LOGS_PROTOCOL",
+    endpoint: "OTEL_EXPORTER_OTLP_LOGS_ENDPOINT",
+    headers: "OTEL_EXPORTER_OTLP_LOGS_HEADERS",
+    timeout: "OTEL_EXPORTER_OTLP_LOGS_TIMEOUT",
+    path: "/v1/logs",
+};
+
+/// The settings shared by all signals: the preset, overridden by the generic
+/// `OTEL_EXPORTER_OTLP_*` variables.
+struct Generic<'a> {
+    preset: &'a Preset,
+    protocol: Option<Protocol>,
+    endpoint: Option<String>,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+}
+
+impl<'a> Generic<'a> {
+    fn from_vars(
+        preset: &'a Preset,
+        vars: &impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let protocol = var(vars, "OTEL_EXPORTER_OTLP_PROTOCOL")
+            .map(|p| parse_protocol("OTEL_EXPORTER_OTLP_PROTOCOL", &p))
+            .transpose()?;
+        let endpoint = var(vars, "OTEL_EXPORTER_OTLP_ENDPOINT");
+        if let Some(endpoint) = &endpoint {
+            check_url("OTEL_EXPORTER_OTLP_ENDPOINT", endpoint)?;
+        }
+        let mut headers = preset.headers();
+        if let Some(value) = var(vars, "OTEL_EXPORTER_OTLP_HEADERS") {
+            set_headers(&mut headers, "OTEL_EXPORTER_OTLP_HEADERS", &value)?;
+        }
+        let timeout = match var(vars, "OTEL_EXPORTER_OTLP_TIMEOUT") {
+            Some(t) => parse_timeout("OTEL_EXPORTER_OTLP_TIMEOUT", &t)?,
+            None => preset.timeout(),
+        };
+        Ok(Self {
+            preset,
+            protocol,
+            endpoint,
+            headers,
+            timeout,
+        })
+    }
+
+    /// Apply the variables specific to `signal`.
+    fn signal(
+        &self,
+        vars: &impl Fn(&str) -> Option<String>,
+        signal: &Signal,
+    ) -> Result<SignalConfig, ConfigError> {
+        let exporter = match var(vars, signal.exporter).as_deref() {
+            None if matches!(self.preset, Preset::Stdout) => Exporter::Stdout,
+            None | Some("otlp") => {
+                let protocol = match var(vars, signal.protocol) {
+                    Some(p) => parse_protocol(signal.protocol, &p)?,
+                    None => self.protocol.unwrap_or(Protocol::Grpc),
+                };
+                Exporter::Otlp(protocol)
+            }
+            Some("console") => Exporter::Stdout,
+            Some(other) => {
+                return Err(ConfigError::invalid(
+                    signal.exporter,
+                    other,
+                    "the supported exporters are `otlp` and `console`",
+                ))
+            }
+        };
+
+        let protocol = match exporter {
+            Exporter::Otlp(protocol) => protocol,
+            Exporter::Stdout => Protocol::Grpc,
+        };
+        // The signal-specific endpoint is used as-is, while the generic one is a base URL to
+        // which the signal's path is appended when using HTTP.
+        let endpoint = match var(vars, signal.endpoint) {
+            Some(endpoint) => {
+                check_url(signal.endpoint, &endpoint)?;
+                endpoint
+            }
+            None => {
+                let base = match &self.endpoint {
+                    Some(endpoint) => endpoint.as_str(),
+                    None => self.preset.base_endpoint(protocol),
+                };
+                match protocol {
+                    Protocol::Grpc => base.to_owned(),
+                    Protocol::HttpProtobuf | Protocol::HttpJson => {
+                        format!("{}{}", base.trim_end_matches('/'), signal.path)
+                    }
+                }
+            }
+        };
+
+        let mut headers = self.headers.clone();
+        if let Some(value) = var(vars, signal.headers) {
+            set_headers(&mut headers, signal.headers, &value)?;
+        }
+
+        let timeout = match var(vars, signal.timeout) {
+            Some(t) => parse_timeout(signal.timeout, &t)?,
+            None => self.timeout,
+        };
+
+        Ok(SignalConfig {
+            exporter,
+            endpoint,
+            headers,
+            timeout,
+        })
+    }
+}
+
+/// Why we couldn't build a tracer or logger provider.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// An environment variable is set to a value we don't understand.
+    InvalidValue {
+        variable: &'static str,
+        value: String,
+        reason: String,
+    },
+    /// The span exporter couldn't be built with this configuration.
+    Exporter(TraceError),
+    /// The log exporter couldn't be built with this configuration.
+    LogExporter(LogError),
+}
+
+impl ConfigError {
+    fn invalid(variable: &'static str, value: &str, reason: impl Into<String>) -> Self {
+        ConfigError::InvalidValue {
+            variable,
+            value: value.to_owned(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::InvalidValue {
+                variable,
+                value,
+                reason,
+            } => write!(f, "`{variable}` is set to `{value}`: {reason}"),
+            ConfigError::Exporter(_) => write!(f, "Failed to build the span exporter"),
+            ConfigError::LogExporter(_) => write!(f, "Failed to build the log exporter"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::InvalidValue { .. } => None,
+            ConfigError::Exporter(e) => Some(e),
+            ConfigError::LogExporter(e) => Some(e),
+        }
+    }
+}
+
+impl ExporterConfig {
+    /// Start from the [`Preset`] picked by [`Preset::from_env`] and the
+    /// [default detectors](resource::default_detectors), then apply the standard environment
+    /// variables.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_env_with_preset(Preset::from_env())
+    }
+
+    /// Start from `preset` and the [default detectors](resource::default_detectors), then
+    /// apply the standard environment variables.
+    pub fn from_env_with_preset(preset: Preset) -> Result<Self, ConfigError> {
+        Self::from_vars_with_detectors(preset, env_var, &resource::default_detectors())
+    }
+
+    /// Start from `preset`, then apply the variables returned by `vars` (`None` when unset).
+    ///
+    /// No resource detector is run: the resource only contains what `vars` sets.
+    pub fn from_vars(
+        preset: Preset,
+        vars: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        Self::from_vars_with_detectors(preset, vars, &[])
+    }
+
+    /// Start from `preset` and the attributes found by `detectors`, then apply the variables
+    /// returned by `vars` (`None` when unset).
+    pub fn from_vars_with_detectors(
+        preset: Preset,
+        vars: impl Fn(&str) -> Option<String>,
+        detectors: &[Box<dyn ResourceDetector>],
+    ) -> Result<Self, ConfigError> {
+        let generic = Generic::from_vars(&preset, &vars)?;
+        let traces = generic.signal(&vars, &TRACES)?;
+        let logs = generic.signal(&vars, &LOGS)?;
+
+        let mut resource = vec![KeyValue::new("service.name", DEFAULT_SERVICE_NAME)];
+        for kv in resource::detect(detectors) {
+            set_attribute(&mut resource, kv);
+        }
+        if let Some(value) = var(&vars, "OTEL_RESOURCE_ATTRIBUTES") {
+            for (key, value) in parse_key_values("OTEL_RESOURCE_ATTRIBUTES", &value)? {
+                set_attribute(&mut resource, KeyValue::new(key, value));
+            }
+        }
+        // `OTEL_SERVICE_NAME` takes precedence over `service.name` in the resource attributes.
+        if let Some(service_name) = var(&vars, "OTEL_SERVICE_NAME") {
+            set_attribute(&mut resource, KeyValue::new("service.name", service_name));
+        }
+
+        let sampler = parse_sampler(
+            var(&vars, "OTEL_TRACES_SAMPLER").as_deref(),
+            var(&vars, "OTEL_TRACES_SAMPLER_ARG").as_deref(),
+        )?;
+
+        Ok(Self {
+            traces,
+            logs,
+            resource,
+            sampler,
+        })
+    }
+
+    /// Build a tracer provider exporting spans in batches, on the Tokio runtime.
+    pub fn build(&self) -> Result<TracerProvider, ConfigError> {
+        let config = Config::default()
+            .with_resource(Resource::new(self.resource.clone()))
+            .with_sampler(self.sampler.clone());
+        let traces = &self.traces;
+        let builder = TracerProvider::builder().with_config(config);
+        let builder = match traces.exporter {
+            Exporter::Otlp(Protocol::Grpc) => {
+                let exporter = opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(&traces.endpoint)
+                    .with_timeout(traces.timeout)
+                    .with_metadata(traces.metadata()?)
+                    .build_span_exporter()
+                    .map_err(ConfigError::Exporter)?;
+                builder.with_batch_exporter(
+                    MeteredSpanExporter::new(exporter, FallbackLog::stderr()),
+                    runtime::Tokio,
+                )
+            }
+            Exporter::Otlp(Protocol::HttpProtobuf | Protocol::HttpJson) => builder
+                .with_batch_exporter(
+                    MeteredSpanExporter::new(traces.http_exporter(), FallbackLog::stderr()),
+                    runtime::Tokio,
+                ),
+            Exporter::Stdout => builder.with_batch_exporter(
+                MeteredSpanExporter::new(StdoutExporter::new(), FallbackLog::stderr()),
+                runtime::Tokio,
+            ),
+        };
+        Ok(builder.build())
+    }
+
+    /// Build a logger provider exporting log records in batches, on the Tokio runtime.
+    ///
+    /// It shares the resource of the tracer provider built by [`ExporterConfig::build`]:
+    /// spans and log records are attributed to the same service.
+    pub fn build_logger_provider(&self) -> Result<LoggerProvider, ConfigError> {
+        let logs = &self.logs;
+        let builder = LoggerProvider::builder().with_resource(Resource::new(self.resource.clone()));
+        let builder = match logs.exporter {
+            Exporter::Otlp(Protocol::Grpc) => {
+                let exporter = opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(&logs.endpoint)
+                    .with_timeout(logs.timeout)
+                    .with_metadata(logs.metadata()?)
+                    .build_log_exporter()
+                    .map_err(ConfigError::LogExporter)?;
+                builder.with_batch_exporter(exporter, runtime::Tokio)
+            }
+            Exporter::Otlp(Protocol::HttpProtobuf | Protocol::HttpJson) => {
+                builder.with_batch_exporter(logs.http_exporter(), runtime::Tokio)
+            }
+            Exporter::Stdout => builder.with_batch_exporter(StdoutExporter::new(), runtime::Tokio),
+        };
+        Ok(builder.build())
+    }
+}
+
+impl SignalConfig {
+    /// The headers, as gRPC metadata.
+    fn metadata(&self) -> Result<MetadataMap, ConfigError> {
+        let mut metadata = MetadataMap::with_capacity(self.headers.len());
+        for (key, value) in &self.headers {
+            let key = MetadataKey::from_bytes(key.as_bytes()).map_err(|_| {
+                ConfigError::invalid("OTEL_EXPORTER_OTLP_HEADERS", key, "invalid key")
+            })?;
+            let value = MetadataValue::try_from(value.as_str()).map_err(|_| {
+                ConfigError::invalid("OTEL_EXPORTER_OTLP_HEADERS", value, "invalid value")
+            })?;
+            metadata.insert(key, value);
+        }
+        Ok(metadata)
+    }
+
+    fn http_exporter(&self) -> HttpExporter {
+        let encoding = match self.exporter {
+            Exporter::Otlp(Protocol::HttpJson) => Encoding::Json,
+            _ => Encoding::Protobuf,
+        };
+        HttpExporter::new(
+            self.endpoint.clone(),
+            self.headers.clone(),
+            self.timeout,
+            encoding,
+        )
+    }
+}
+
+/// Add the headers listed in `value` to `headers`, replacing the previous values of the same
+/// headers.
+fn set_headers(
+    headers: &mut Vec<(String, String)>,
+    variable: &'static str,
+    value: &str,
+) -> Result<(), ConfigError> {
+    for (key, value) in parse_key_values(variable, value)? {
+        let key = key.to_ascii_lowercase();
+        headers.retain(|(k, _)| *k != key);
+        headers.push((key, value));
+    }
+    Ok(())
+}
+
+/// Add `kv` to `resource`, replacing any previous value of the same attribute.
+fn set_attribute(resource: &mut Vec<KeyValue>, kv: KeyValue) {
+    resource.retain(|existing| existing.key != kv.key);
+    resource.push(kv);
+}
+
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// Empty values are treated as unset, as the specification requires.
+fn var(vars: &impl Fn(&str) -> Option<String>, name: &str) -> Option<String> {
+    vars(name)
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty())
+}
+
+fn parse_protocol(variable: &'static str, value: &str) -> Result<Protocol, ConfigError> {
+    match value {
+        "grpc" => Ok(Protocol::Grpc),
+        "http/protobuf" => Ok(Protocol::HttpProtobuf),
+        "http/json" => Ok(Protocol::HttpJson),
+        _ => Err(ConfigError::invalid(
+            variable,
+            value,
+            "expected `grpc`, `http/protobuf` or `http/json`",
+        )),
+    }
+}
+
+fn check_url(variable: &'static str, value: &str) -> Result<(), ConfigError> {
+    if value.starts_with("http://") || value.starts_with("https://") {
+        Ok(())
+    } else {
+        Err(ConfigError::invalid(
+            variable,
+            value,
+            "expected an `http://` or `https://` URL",
+        ))
+    }
+}
+
+fn parse_timeout(variable: &'static str, value: &str) -> Result<Duration, ConfigError> {
+    value
+        .parse()
+        .map(Duration::from_millis)
+        .map_err(|_| ConfigError::invalid(variable, value, "expected a number of milliseconds"))
+}
+
+/// Parse a comma-separated list of `key=value` pairs, with percent-encoded values (the format
+/// of `OTEL_EXPORTER_OTLP_HEADERS` and `OTEL_RESOURCE_ATTRIBUTES`).
+fn parse_key_values(
+    variable: &'static str,
+    value: &str,
+) -> Result<Vec<(String, String)>, ConfigError> {
+    value
+        .split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            let (key, v) = pair
+                .split_once('=')
+                .filter(|(key, _)| !key.trim().is_empty())
+                .ok_or_else(|| {
+                    ConfigError::invalid(
+                        variable,
+                        value,
+                        format!("`{pair}` isn't a `key=value` pair"),
+                    )
+                })?;
+            let v = percent_decode(v.trim()).ok_or_else(|| {
+                ConfigError::invalid(
+                    variable,
+                    value,
+                    format!("`{v}` isn't properly percent-encoded"),
+                )
+            })?;
+            Ok((key.trim().to_owned(), v))
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut iter = value.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn parse_sampler(sampler: Option<&str>, arg: Option<&str>) -> Result<Sampler, ConfigError> {
+    let ratio = || -> Result<f64, ConfigError> {
+        let Some(arg) = arg else {
+            return Ok(1.0);
+        };
+        arg.parse()
+            .ok()
+            .filter(|ratio| (0.0..=1.0).contains(ratio))
+            .ok_or_else(|| {
+                ConfigError::invalid(
+                    "OTEL_TRACES_SAMPLER_ARG",
+                    arg,
+                    "expected a ratio between 0 and 1",
+                )
+            })
+    };
+    let sampler = match sampler.unwrap_or("parentbased_always_on") {
+        "always_on" => Sampler::AlwaysOn,
+        "always_off" => Sampler::AlwaysOff,
+        "traceidratio" => Sampler::TraceIdRatioBased(ratio()?),
+        "parentbased_always_on" => Sampler::ParentBased(Box::new(Sampler::AlwaysOn)),
+        "parentbased_always_off" => Sampler::ParentBased(Box::new(Sampler::AlwaysOff)),
+        "parentbased_traceidratio" => {
+            Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(ratio()?)))
+        }
+        other => {
+            return Err(ConfigError::invalid(
+                "OTEL_TRACES_SAMPLER",
+                other,
+                "the supported samplers are `always_on`, `always_off`, `traceidratio`, \
+                `parentbased_always_on`, `parentbased_always_off` and `parentbased_traceidratio`",
+            ))
+        }
+    };
+    Ok(sampler)
+}
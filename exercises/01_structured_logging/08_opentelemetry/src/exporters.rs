@@ -0,0 +1,179 @@
+//! The exporters `opentelemetry-otlp` doesn't provide out of the box: OTLP over HTTP and
+//! OTLP/JSON on stdout, for both spans and log records.
+use opentelemetry::logs::LogError;
+use opentelemetry::trace::TraceError;
+use opentelemetry_proto::tonic::collector::logs::v1::ExportLogsServiceRequest;
+use opentelemetry_proto::tonic::collector::trace::v1::ExportTraceServiceRequest;
+use opentelemetry_proto::transform::common::tonic::ResourceAttributesWithSchema;
+use opentelemetry_proto::transform::logs::tonic::group_logs_by_resource_and_scope;
+use opentelemetry_proto::transform::trace::tonic::group_spans_by_resource_and_scope;
+use opentelemetry_sdk::export::logs::{LogData, LogExporter};
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::Resource;
+use prost::Message;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// How export requests are serialized over HTTP.
+#[derive(Debug, Clone, Copy)]
+pub enum Encoding {
+    Protobuf,
+    Json,
+}
+
+/// Sends spans or log records to an OTLP/HTTP endpoint (e.g. `http://localhost:4318/v1/traces`
+/// or `http://localhost:4318/v1/logs`).
+#[derive(Debug)]
+pub struct HttpExporter {
+    endpoint: String,
+    headers: Vec<(String, String)>,
+    encoding: Encoding,
+    agent: ureq::Agent,
+    resource: ResourceAttributesWithSchema,
+}
+
+impl HttpExporter {
+    pub fn new(
+        endpoint: String,
+        headers: Vec<(String, String)>,
+        timeout: Duration,
+        encoding: Encoding,
+    ) -> Self {
+        Self {
+            endpoint,
+            headers,
+            encoding,
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+            resource: (&Resource::empty()).into(),
+        }
+    }
+
+    /// Serialize `request` and post it to the endpoint.
+    fn send<R>(&self, request: &R) -> impl Future<Output = Result<(), String>> + Send
+    where
+        R: Message + Serialize,
+    {
+        let encoded = match self.encoding {
+            Encoding::Protobuf => Ok(("application/x-protobuf", request.encode_to_vec())),
+            Encoding::Json => serde_json::to_vec(request)
+                .map(|body| ("application/json", body))
+                .map_err(|e| e.to_string()),
+        };
+        let mut http_request = self.agent.post(&self.endpoint);
+        for (key, value) in &self.headers {
+            http_request = http_request.set(key, value);
+        }
+        async move {
+            let (content_type, body) = encoded?;
+            let http_request = http_request.set("Content-Type", content_type);
+            // `ureq` is blocking: keep it off the async worker threads.
+            tokio::task::spawn_blocking(move || {
+                http_request
+                    .send_bytes(&body)
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            })
+            .await
+            .map_err(|e| e.to_string())?
+        }
+    }
+}
+
+impl SpanExporter for HttpExporter {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send>> {
+        let request = ExportTraceServiceRequest {
+            resource_spans: group_spans_by_resource_and_scope(batch, &self.resource),
+        };
+        let sent = self.send(&request);
+        Box::pin(async move { sent.await.map_err(TraceError::from) })
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.resource = resource.into();
+    }
+}
+
+#[async_trait::async_trait]
+impl LogExporter for HttpExporter {
+    async fn export<'a>(&mut self, batch: Vec<Cow<'a, LogData>>) -> Result<(), LogError> {
+        let request = ExportLogsServiceRequest {
+            resource_logs: group_logs_by_resource_and_scope(
+                batch.into_iter().map(Cow::into_owned).collect(),
+                &self.resource,
+            ),
+        };
+        self.send(&request).await.map_err(LogError::from)
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.resource = resource.into();
+    }
+}
+
+/// Prints each batch of spans or log records to stdout, as an OTLP/JSON export request on a
+/// single line.
+#[derive(Debug)]
+pub struct StdoutExporter {
+    resource: ResourceAttributesWithSchema,
+}
+
+impl Default for StdoutExporter {
+    fn default() -> Self {
+        Self {
+            resource: (&Resource::empty()).into(),
+        }
+    }
+}
+
+impl StdoutExporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+fn print_json(request: &impl Serialize) -> Result<(), String> {
+    let json = serde_json::to_string(request).map_err(|e| e.to_string())?;
+    writeln!(std::io::stdout().lock(), "{json}").map_err(|e| e.to_string())
+}
+
+impl SpanExporter for StdoutExporter {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send>> {
+        let request = ExportTraceServiceRequest {
+            resource_spans: group_spans_by_resource_and_scope(batch, &self.resource),
+        };
+        Box::pin(std::future::ready(
+            print_json(&request).map_err(TraceError::from),
+        ))
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.resource = resource.into();
+    }
+}
+
+#[async_trait::async_trait]
+impl LogExporter for StdoutExporter {
+    async fn export<'a>(&mut self, batch: Vec<Cow<'a, LogData>>) -> Result<(), LogError> {
+        let request = ExportLogsServiceRequest {
+            resource_logs: group_logs_by_resource_and_scope(
+                batch.into_iter().map(Cow::into_owned).collect(),
+                &self.resource,
+            ),
+        };
+        print_json(&request).map_err(LogError::from)
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.resource = resource.into();
+    }
+}
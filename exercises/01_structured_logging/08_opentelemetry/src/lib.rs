@@ -6,9 +6,19 @@
 //! collecting thanks to the `tracing` crate.
 //!
 //! You'll need to sign up for a free account and grab an API key—no credit card is required.
+//! Set it as `HONEYCOMB_API_KEY` before running the tests.
+//!
+//! Prefer another backend? The exporter follows the standard `OTEL_*` environment variables:
+//! see the [`config`] module.
+pub mod config;
+mod exporters;
+pub mod logs;
+pub mod propagation;
+pub mod resource;
+pub mod service;
 mod subscriber;
 
-pub use subscriber::init_test_subscriber;
+pub use subscriber::{init_test_subscriber, init_tracer};
 use tracing::{instrument, Span};
 
 /// Given a list of order numbers, compute the total price.
@@ -33,7 +43,7 @@ pub struct OrderDetails {
 
 /// A dummy function to simulate what would normally be a database query.
 #[instrument("retrieve order", skip_all, fields(outcome))]
-fn get_order_details(order_number: u64) -> Result<OrderDetails, anyhow::Error> {
+pub(crate) fn get_order_details(order_number: u64) -> Result<OrderDetails, anyhow::Error> {
     if order_number % 4 == 0 {
         Span::current().record("outcome", "failure");
         Err(anyhow::anyhow!("Failed to talk to the database"))
@@ -0,0 +1,124 @@
+//! Export `tracing` events as OpenTelemetry log records.
+//!
+//! `tracing-opentelemetry` only turns events into span events: an event emitted outside of
+//! any span (e.g. a panic report) never reaches the backend, and span events are only shipped
+//! once their span closes. [`OtelLogLayer`] emits every event as a log record instead, with:
+//!
+//! - the level as severity (text and number, see the
+//!   [log data model](https://opentelemetry.io/docs/specs/otel/logs/data-model/#severity-fields));
+//! - the `message` field as body;
+//! - the other fields, as well as `code.namespace`, `code.filepath` and `code.lineno`, as
+//!   attributes;
+//! - the trace context of the span the event belongs to, as seen by the
+//!   `tracing-opentelemetry` layer, so that the backend can correlate the two. The sampling
+//!   decision of the span is carried over too.
+use helpers::event_fields::{FieldValue, FieldVisitor};
+use opentelemetry::logs::{AnyValue, LogRecord, Logger, LoggerProvider as _, Severity};
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry::Key;
+use opentelemetry_sdk::logs::{LoggerProvider, TraceContext};
+use opentelemetry_sdk::trace::Tracer;
+use std::time::SystemTime;
+use tracing::field::Field;
+use tracing::{Event, Level, Subscriber};
+use tracing_opentelemetry::{OtelData, PreSampledTracer};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// A `tracing` layer emitting each event as a log record, through a logger of `provider`.
+///
+/// The records are exported by the provider, in batches if it was built with
+/// [`ExporterConfig::build_logger_provider`](crate::config::ExporterConfig::build_logger_provider).
+pub struct OtelLogLayer {
+    logger: opentelemetry_sdk::logs::Logger,
+    tracer: Tracer,
+}
+
+impl OtelLogLayer {
+    /// `tracer` must be the one given to the `tracing-opentelemetry` layer: it makes the
+    /// sampling decision of the spans, if it hasn't been made yet when an event is emitted.
+    pub fn new(provider: &LoggerProvider, tracer: Tracer) -> Self {
+        Self {
+            logger: provider.logger("rust-telemetry-workshop"),
+            tracer,
+        }
+    }
+}
+
+impl<S> Layer<S> for OtelLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut record = self.logger.create_log_record();
+        record.set_timestamp(SystemTime::now());
+        record.set_target(metadata.target());
+        record.set_severity_text(metadata.level().as_str().into());
+        record.set_severity_number(severity(metadata.level()));
+
+        let mut message = None;
+        let mut attributes = Vec::new();
+        event.record(&mut FieldVisitor(|field: &Field, value| match value {
+            FieldValue::Str(v) | FieldValue::Debug(v) if field.name() == "message" => {
+                message = Some(v)
+            }
+            value => attributes.push((Key::from(field.name()), any_value(value))),
+        }));
+        if let Some(message) = message {
+            record.set_body(message.into());
+        }
+        record.add_attributes(attributes);
+        record.add_attribute("code.namespace", metadata.target());
+        if let Some(file) = metadata.file() {
+            record.add_attribute("code.filepath", file);
+        }
+        if let Some(line) = metadata.line() {
+            record.add_attribute("code.lineno", i64::from(line));
+        }
+
+        if let Some(span) = ctx.event_span(event) {
+            let mut extensions = span.extensions_mut();
+            if let Some(data) = extensions.get_mut::<OtelData>() {
+                // The same context `tracing-opentelemetry` gives to the span's children.
+                let cx = self.tracer.sampled_context(data);
+                record.trace_context = Some(TraceContext::from(cx.span().span_context()));
+            }
+        }
+        self.logger.emit(record);
+    }
+}
+
+fn severity(level: &Level) -> Severity {
+    match *level {
+        Level::TRACE => Severity::Trace,
+        Level::DEBUG => Severity::Debug,
+        Level::INFO => Severity::Info,
+        Level::WARN => Severity::Warn,
+        Level::ERROR => Severity::Error,
+    }
+}
+
+/// OpenTelemetry attribute values, for the values recorded by `tracing`.
+fn any_value(value: FieldValue) -> AnyValue {
+    match value {
+        FieldValue::Bool(v) => v.into(),
+        FieldValue::I64(v) => v.into(),
+        // OpenTelemetry integers are signed.
+        FieldValue::U64(v) => match i64::try_from(v) {
+            Ok(v) => v.into(),
+            Err(_) => v.to_string().into(),
+        },
+        FieldValue::I128(v) => match i64::try_from(v) {
+            Ok(v) => v.into(),
+            Err(_) => v.to_string().into(),
+        },
+        FieldValue::U128(v) => match i64::try_from(v) {
+            Ok(v) => v.into(),
+            Err(_) => v.to_string().into(),
+        },
+        FieldValue::F64(v) => v.into(),
+        FieldValue::Str(v) | FieldValue::Debug(v) => v.into(),
+    }
+}
@@ -0,0 +1,56 @@
+//! Carry the trace context across process boundaries, using
+//! [W3C Trace Context](https://www.w3.org/TR/trace-context/) headers.
+//!
+//! The caller [`inject`]s the context of its current span into the `traceparent` and
+//! `tracestate` headers of the outgoing request.
+//! The callee [`extract`]s it, and uses it as the parent of the span it opens for the request:
+//! both sides end up in the same trace.
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::HeaderMap;
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::Context;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// Add the trace context of `span` to `headers`.
+///
+/// Nothing is added if `span` isn't known to the `tracing-opentelemetry` layer (e.g. if it's
+/// disabled).
+pub fn inject(span: &Span, headers: &mut HeaderMap) {
+    TraceContextPropagator::new().inject_context(&span.context(), &mut HeaderInjector(headers));
+}
+
+/// The trace context found in `headers`, to be set as the parent of the server span with
+/// [`OpenTelemetrySpanExt::set_parent`].
+///
+/// The context is empty (i.e. the server span starts a new trace) if the headers are missing
+/// or invalid.
+pub fn extract(headers: &HeaderMap) -> Context {
+    TraceContextPropagator::new().extract(&HeaderExtractor(headers))
+}
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
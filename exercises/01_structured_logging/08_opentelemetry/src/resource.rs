@@ -0,0 +1,250 @@
+//! Describe the entity producing telemetry: which service, running where.
+//!
+//! Each [`ResourceDetector`] contributes a few attributes, following the OpenTelemetry
+//! [resource semantic conventions](https://opentelemetry.io/docs/specs/semconv/resource/):
+//!
+//! - [`PackageDetector`]: `service.version` (and, if asked to, `service.name`), from the Cargo
+//!   package metadata;
+//! - [`HostDetector`]: `host.name` and `os.type`;
+//! - [`ProcessDetector`]: `process.pid`, `process.executable.name`, `process.executable.path`,
+//!   `process.command_line` and the `process.runtime.*` attributes;
+//! - [`ContainerDetector`]: `container.id`, when running in a container.
+//!
+//! When several sources set the same attribute, the last one wins. From lowest to highest
+//! precedence (see [`ExporterConfig`](crate::config::ExporterConfig)):
+//!
+//! 1. `service.name = rust-telemetry-workshop`;
+//! 2. the detectors, in the order they're given (see [`default_detectors`]);
+//! 3. `OTEL_RESOURCE_ATTRIBUTES`;
+//! 4. `OTEL_SERVICE_NAME`.
+//!
+//! Detectors reading from `/proc` can be pointed at another directory, with fake content, in
+//! tests.
+use opentelemetry::{KeyValue, StringValue};
+use opentelemetry_sdk::resource::ResourceDetector;
+use opentelemetry_sdk::Resource;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Our detectors don't do any I/O that could hang: the timeout is only there to satisfy
+/// [`ResourceDetector::detect`].
+const DETECTION_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// All our detectors, in increasing order of precedence, describing this package.
+///
+/// The service is named after the package, unless `OTEL_SERVICE_NAME` (or
+/// `OTEL_RESOURCE_ATTRIBUTES`) says otherwise. Binaries should replace [`PackageDetector`]
+/// with one describing their own package.
+pub fn default_detectors() -> Vec<Box<dyn ResourceDetector>> {
+    vec![
+        Box::new(
+            PackageDetector::new(env!("CARGO_PKG_VERSION"))
+                .with_service_name(env!("CARGO_PKG_NAME")),
+        ),
+        Box::new(HostDetector::new()),
+        Box::new(ProcessDetector),
+        Box::new(ContainerDetector::new()),
+    ]
+}
+
+/// Run `detectors`, in order: when two of them set the same attribute, the last one wins.
+///
+/// Attributes are sorted by key, to keep the result stable.
+pub fn detect(detectors: &[Box<dyn ResourceDetector>]) -> Vec<KeyValue> {
+    let mut attributes: Vec<KeyValue> = Vec::new();
+    for detector in detectors {
+        for (key, value) in detector.detect(DETECTION_TIMEOUT).iter() {
+            attributes.retain(|kv| kv.key != *key);
+            attributes.push(KeyValue::new(key.clone(), value.clone()));
+        }
+    }
+    attributes.sort_by(|a, b| a.key.as_str().cmp(b.key.as_str()));
+    attributes
+}
+
+/// `service.version`, and optionally `service.name`, from the metadata of a Cargo package.
+///
+/// They're captured at build time: use `PackageDetector::new(env!("CARGO_PKG_VERSION"))` in
+/// the package you want to describe.
+#[derive(Debug, Clone)]
+pub struct PackageDetector {
+    name: Option<&'static str>,
+    version: &'static str,
+}
+
+impl PackageDetector {
+    pub const fn new(version: &'static str) -> Self {
+        Self {
+            name: None,
+            version,
+        }
+    }
+
+    /// Set `service.name` too, e.g. to `env!("CARGO_PKG_NAME")`, replacing the default name.
+    pub const fn with_service_name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+}
+
+impl ResourceDetector for PackageDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        let mut attributes = vec![KeyValue::new("service.version", self.version)];
+        if let Some(name) = self.name {
+            attributes.push(KeyValue::new("service.name", name));
+        }
+        Resource::new(attributes)
+    }
+}
+
+/// `host.name` and `os.type`.
+///
+/// The host name is read from `/proc/sys/kernel/hostname` on Linux, from the `HOSTNAME` (or
+/// `COMPUTERNAME`, on Windows) environment variable otherwise.
+#[derive(Debug, Clone)]
+pub struct HostDetector {
+    proc_root: PathBuf,
+}
+
+impl Default for HostDetector {
+    fn default() -> Self {
+        Self {
+            proc_root: PathBuf::from("/proc"),
+        }
+    }
+}
+
+impl HostDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read from `root` instead of `/proc`.
+    pub fn with_proc_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.proc_root = root.into();
+        self
+    }
+}
+
+impl ResourceDetector for HostDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        let mut attributes = vec![KeyValue::new("os.type", os_type())];
+        let host_name = read_trimmed(&self.proc_root.join("sys/kernel/hostname"))
+            .or_else(|| std::env::var("HOSTNAME").ok())
+            .or_else(|| std::env::var("COMPUTERNAME").ok())
+            .filter(|name| !name.is_empty());
+        if let Some(host_name) = host_name {
+            attributes.push(KeyValue::new("host.name", host_name));
+        }
+        Resource::new(attributes)
+    }
+}
+
+/// The `os.type` values defined by the semantic conventions mostly match Rust's names.
+fn os_type() -> &'static str {
+    match std::env::consts::OS {
+        "macos" => "darwin",
+        "dragonfly" => "dragonflybsd",
+        "illumos" => "solaris",
+        os => os,
+    }
+}
+
+/// `process.pid`, `process.executable.name`, `process.executable.path`, `process.command_line`,
+/// and the name and version of the compiler this binary was built with as
+/// `process.runtime.*`: Rust doesn't have a runtime in the sense of the JVM or Node.js.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessDetector;
+
+impl ResourceDetector for ProcessDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        let mut attributes = vec![
+            KeyValue::new("process.pid", i64::from(std::process::id())),
+            KeyValue::new(
+                "process.command_line",
+                std::env::args().collect::<Vec<_>>().join(" "),
+            ),
+            KeyValue::new("process.runtime.name", "rustc"),
+            KeyValue::new("process.runtime.description", env!("RUSTC_VERSION")),
+        ];
+        // e.g. `rustc 1.80.0 (051478957 2024-07-21)`
+        if let Some(version) = env!("RUSTC_VERSION").split_whitespace().nth(1) {
+            attributes.push(KeyValue::new("process.runtime.version", version));
+        }
+        if let Ok(path) = std::env::current_exe() {
+            if let Some(name) = path.file_name() {
+                attributes.push(KeyValue::new(
+                    "process.executable.name",
+                    name.to_string_lossy().into_owned(),
+                ));
+            }
+            attributes.push(KeyValue::new(
+                "process.executable.path",
+                path.to_string_lossy().into_owned(),
+            ));
+        }
+        Resource::new(attributes)
+    }
+}
+
+/// `container.id`, found in `/proc/self/cgroup` when running in a container (Docker,
+/// containerd, Podman, Kubernetes...).
+///
+/// Nothing is detected outside of a container, or on platforms without `/proc`.
+#[derive(Debug, Clone)]
+pub struct ContainerDetector {
+    proc_root: PathBuf,
+}
+
+impl Default for ContainerDetector {
+    fn default() -> Self {
+        Self {
+            proc_root: PathBuf::from("/proc"),
+        }
+    }
+}
+
+impl ContainerDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read from `root` instead of `/proc`.
+    pub fn with_proc_root(mut self, root: impl Into<PathBuf>) -> Self {
+        self.proc_root = root.into();
+        self
+    }
+}
+
+impl ResourceDetector for ContainerDetector {
+    fn detect(&self, _timeout: Duration) -> Resource {
+        let container_id = std::fs::read_to_string(self.proc_root.join("self/cgroup"))
+            .ok()
+            .and_then(|cgroup| cgroup.lines().find_map(container_id));
+        match container_id {
+            Some(id) => Resource::new([KeyValue::new("container.id", StringValue::from(id))]),
+            None => Resource::empty(),
+        }
+    }
+}
+
+/// The container id in a line of `/proc/self/cgroup`: the 64 hexadecimal characters at the end
+/// of the cgroup path, e.g.
+///
+/// - `12:memory:/docker/<id>` (cgroup v1);
+/// - `0::/system.slice/docker-<id>.scope` (cgroup v2, systemd driver);
+/// - `0::/kubepods/besteffort/pod<uid>/<id>` (Kubernetes).
+fn container_id(line: &str) -> Option<String> {
+    // `hierarchy-ID:controller-list:cgroup-path`
+    let path = line.splitn(3, ':').nth(2)?;
+    let segment = path.rsplit('/').next()?;
+    let segment = segment.strip_suffix(".scope").unwrap_or(segment);
+    let id = segment.rsplit(['-', ':']).next()?;
+    (id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit())).then(|| id.to_owned())
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|content| content.trim().to_owned())
+}
@@ -0,0 +1,140 @@
+//! The order lookup, as an HTTP service: [`OrdersServer`] exposes `GET /orders/<number>`,
+//! returning the price of the order, and [`OrdersClient`] calls it.
+//!
+//! The client propagates the trace context of its span to the server (see
+//! [`propagation`](crate::propagation)): the spans of both sides belong to the same trace,
+//! as they would if they were running in different processes.
+use crate::get_order_details;
+use crate::propagation;
+use anyhow::Context as _;
+use http_body_util::{BodyExt, Empty, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{header, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::convert::Infallible;
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+use tracing::field::Empty as EmptyField;
+use tracing::{instrument, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// The orders service, listening on a random local port.
+///
+/// It runs as a background task on the runtime it was started from, and it is shut down when
+/// dropped.
+pub struct OrdersServer {
+    addr: SocketAddr,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl OrdersServer {
+    pub async fn start() -> std::io::Result<Self> {
+        let listener = TcpListener::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0)).await?;
+        let addr = listener.local_addr()?;
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            loop {
+                let stream = tokio::select! {
+                    _ = &mut shutdown_rx => break,
+                    accepted = listener.accept() => match accepted {
+                        Ok((stream, _)) => stream,
+                        Err(_) => continue,
+                    },
+                };
+                tokio::spawn(async move {
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service_fn(handle))
+                        .await;
+                });
+            }
+        });
+        Ok(Self {
+            addr,
+            _shutdown: shutdown_tx,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+async fn handle(request: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+    // A root span as far as this process is concerned: its parent is whatever the caller
+    // told us in the request headers.
+    let span = tracing::info_span!(
+        parent: None,
+        "HTTP request",
+        otel.kind = "server",
+        http.request.method = %request.method(),
+        url.path = request.uri().path(),
+        http.response.status_code = EmptyField,
+    );
+    span.set_parent(propagation::extract(request.headers()));
+    let response = route(&request).instrument(span.clone()).await;
+    span.record("http.response.status_code", response.status().as_u16());
+    Ok(response)
+}
+
+async fn route(request: &Request<Incoming>) -> Response<Full<Bytes>> {
+    let order_number = request
+        .uri()
+        .path()
+        .strip_prefix("/orders/")
+        .and_then(|n| n.parse().ok());
+    match (request.method(), order_number) {
+        (&Method::GET, Some(order_number)) => match get_order_details(order_number) {
+            Ok(details) => respond(StatusCode::OK, details.price.to_string()),
+            Err(e) => respond(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        },
+        _ => respond(StatusCode::NOT_FOUND, "Unknown route"),
+    }
+}
+
+fn respond(status: StatusCode, body: impl Into<Bytes>) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(body.into()));
+    *response.status_mut() = status;
+    response
+}
+
+/// A client for [`OrdersServer`].
+pub struct OrdersClient {
+    addr: SocketAddr,
+}
+
+impl OrdersClient {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self { addr }
+    }
+
+    /// Retrieve the price of an order, over a new HTTP/1.1 connection.
+    #[instrument(
+        "GET /orders",
+        skip(self),
+        fields(otel.kind = "client", http.response.status_code)
+    )]
+    pub async fn get_price(&self, order_number: u64) -> Result<u64, anyhow::Error> {
+        let stream = TcpStream::connect(self.addr).await?;
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(connection);
+
+        let mut request = Request::get(format!("/orders/{order_number}"))
+            .header(header::HOST, self.addr.to_string())
+            .body(Empty::<Bytes>::new())?;
+        propagation::inject(&Span::current(), request.headers_mut());
+        let response = sender.send_request(request).await?;
+        let status = response.status();
+        Span::current().record("http.response.status_code", status.as_u16());
+        let body = response.into_body().collect().await?.to_bytes();
+        let body = String::from_utf8_lossy(&body);
+        if !status.is_success() {
+            anyhow::bail!("The orders service returned {status}: {body}");
+        }
+        body.parse()
+            .with_context(|| format!("The orders service returned an invalid price: {body}"))
+    }
+}
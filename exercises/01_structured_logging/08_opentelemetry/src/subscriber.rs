@@ -1,51 +1,74 @@
-use opentelemetry::KeyValue;
+use crate::config::{ConfigError, ExporterConfig};
+use crate::logs::OtelLogLayer;
+use helpers::guard::TelemetryGuard;
+use helpers::self_telemetry::{install_error_handler, FallbackLog};
 use opentelemetry::trace::TracerProvider;
-use opentelemetry_otlp::WithExportConfig;
 use opentelemetry_sdk::trace::Tracer;
-use opentelemetry_sdk::{runtime, Resource};
-use tonic::metadata::MetadataMap;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::Registry;
 
-pub fn init_test_subscriber() {
-    let tracer = init_tracer();
-    let otel = tracing_opentelemetry::layer().with_tracer(tracer);
+/// Install a subscriber exporting spans and log records to the backend described by the
+/// environment (see [`ExporterConfig::from_env`]).
+///
+/// Keep the returned guard around: spans and log records are exported in batches, and the
+/// ones that are still buffered are only exported once it's shut down (or dropped).
+/// The providers run on a runtime owned by the guard, so dropping it works on a
+/// current-thread runtime too (e.g. at the end of a `#[tokio::test]`).
+///
+/// Problems with the export pipeline itself (failed exports, dropped spans) are reported as
+/// `metrics` and logged to stderr, once per distinct message.
+#[must_use = "Telemetry is flushed when the guard is dropped"]
+pub fn init_test_subscriber() -> TelemetryGuard {
+    let config = ExporterConfig::from_env().expect("Failed to read the exporter configuration");
+    // The batch processors export from a task spawned on the runtime they're built on: if it
+    // were the caller's, dropping the guard on a current-thread runtime would block the only
+    // thread that can run it.
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("telemetry-export")
+        .enable_all()
+        .build()
+        .expect("Failed to build the telemetry runtime");
+    let (tracer_provider, logger_provider) = {
+        let _entered = runtime.enter();
+        let tracer_provider = config
+            .build()
+            .expect("Failed to configure the span exporter");
+        let logger_provider = config
+            .build_logger_provider()
+            .expect("Failed to configure the log exporter");
+        (tracer_provider, logger_provider)
+    };
+    install_error_handler(FallbackLog::stderr());
+    let tracer = tracer_provider.tracer("rust-telemetry-workshop");
+    let otel = tracing_opentelemetry::layer().with_tracer(tracer.clone());
+    let logs = OtelLogLayer::new(&logger_provider, tracer);
+    opentelemetry::global::set_tracer_provider(tracer_provider.clone());
 
     // Here we are using the `Layer` trait from the `tracing-subscriber` crate to combine together
     // multiple pieces of functionality into a single subscriber.
     // We'll talk more about layers later in the workshop.
-    Registry::default().with(otel).init()
-}
+    Registry::default().with(otel).with(logs).init();
 
-pub fn init_tracer() -> Tracer {
-    let honeycomb_key =
-        std::env::var("HONEYCOMB_API_KEY").expect("`HONEYCOMB_API_KEY` must be set");
-    let mut map = MetadataMap::with_capacity(1);
-    map.insert("x-honeycomb-team", honeycomb_key.try_into().unwrap());
+    TelemetryGuard::new()
+        .with_runtime(runtime)
+        .with_tracer_provider(tracer_provider)
+        .with_logger_provider(logger_provider)
+}
 
+/// Build a tracer exporting spans to the backend described by the environment
+/// (see [`ExporterConfig::from_env`]) and install its provider as the global one.
+pub fn init_tracer() -> Result<Tracer, ConfigError> {
     // Correctly configuring your exporter is a bit of a black art and highly-dependent on the
     // specifics of your deployment environment.
     // We won't go into the details here, but you can read more about it in the OpenTelemetry
     // documentation (or grab me after the workshop to talk about it).
     // At a super high-level: you want batching and you want a sensible sampling strategy,
     // but beyond that it's hard to give general advice.
-    opentelemetry_otlp::new_pipeline()
-        .tracing()
-        .with_trace_config(opentelemetry_sdk::trace::Config::default().with_resource(
-            Resource::new(vec![KeyValue::new(
-                "service.name",
-                "rust-telemetry-workshop",
-            )]),
-        ))
-        .with_exporter(
-            opentelemetry_otlp::new_exporter()
-                .tonic()
-                .with_endpoint("https://api.honeycomb.io/api/traces")
-                .with_timeout(std::time::Duration::from_secs(5))
-                .with_metadata(map),
-        )
-        .install_batch(runtime::Tokio)
-        .unwrap()
-        .tracer("rust-telemetry-workshop")
+    let provider = ExporterConfig::from_env()?.build()?;
+    install_error_handler(FallbackLog::stderr());
+    let tracer = provider.tracer("rust-telemetry-workshop");
+    opentelemetry::global::set_tracer_provider(provider);
+    Ok(tracer)
 }
@@ -0,0 +1,261 @@
+use opentelemetry::trace::{Tracer, TracerProvider};
+use opentelemetry_training::config::{
+    ConfigError, Exporter, ExporterConfig, Preset, Protocol, SignalConfig,
+};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::time::Duration;
+
+fn vars(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    move |name| vars.get(name).cloned()
+}
+
+fn resource(config: &ExporterConfig) -> Vec<(String, String)> {
+    config
+        .resource
+        .iter()
+        .map(|kv| (kv.key.to_string(), kv.value.to_string()))
+        .collect()
+}
+
+#[test]
+fn defaults_follow_the_specification() {
+    let config = ExporterConfig::from_vars(Preset::Local, vars(&[])).unwrap();
+    assert_eq!(config.traces.exporter, Exporter::Otlp(Protocol::Grpc));
+    assert_eq!(config.traces.endpoint, "http://localhost:4317");
+    assert_eq!(config.logs.endpoint, "http://localhost:4317");
+    assert!(config.traces.headers.is_empty());
+    assert_eq!(config.traces.timeout, Duration::from_secs(10));
+    assert_eq!(
+        resource(&config),
+        [("service.name".into(), "rust-telemetry-workshop".into())]
+    );
+}
+
+#[test]
+fn environment_variables_override_the_preset() {
+    let preset = Preset::Honeycomb {
+        api_key: "secret".into(),
+    };
+    let config = ExporterConfig::from_vars(preset.clone(), vars(&[])).unwrap();
+    assert_eq!(config.traces.endpoint, "https://api.honeycomb.io:443");
+    assert_eq!(
+        config.traces.headers,
+        [("x-honeycomb-team".into(), "secret".into())]
+    );
+
+    let config = ExporterConfig::from_vars(
+        preset,
+        vars(&[
+            ("OTEL_EXPORTER_OTLP_PROTOCOL", "http/json"),
+            (
+                "OTEL_EXPORTER_OTLP_HEADERS",
+                "x-honeycomb-dataset=orders,x-honeycomb-team=other",
+            ),
+            ("OTEL_EXPORTER_OTLP_TRACES_TIMEOUT", "250"),
+            ("OTEL_SERVICE_NAME", "orders-api"),
+            (
+                "OTEL_RESOURCE_ATTRIBUTES",
+                "service.name=ignored,deployment.environment=dev%20eu",
+            ),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(config.traces.exporter, Exporter::Otlp(Protocol::HttpJson));
+    // The generic endpoint is a base URL when using HTTP.
+    assert_eq!(config.traces.endpoint, "https://api.honeycomb.io/v1/traces");
+    assert_eq!(config.logs.endpoint, "https://api.honeycomb.io/v1/logs");
+    assert_eq!(
+        config.traces.headers,
+        [
+            ("x-honeycomb-dataset".into(), "orders".into()),
+            ("x-honeycomb-team".into(), "other".into())
+        ]
+    );
+    assert_eq!(config.traces.timeout, Duration::from_millis(250));
+    assert_eq!(
+        resource(&config),
+        [
+            ("deployment.environment".into(), "dev eu".into()),
+            ("service.name".into(), "orders-api".into())
+        ]
+    );
+
+    let config =
+        ExporterConfig::from_vars(Preset::Local, vars(&[("OTEL_TRACES_EXPORTER", "console")]))
+            .unwrap();
+    assert_eq!(config.traces.exporter, Exporter::Stdout);
+    assert_eq!(config.logs.exporter, Exporter::Otlp(Protocol::Grpc));
+}
+
+#[test]
+fn signal_specific_variables_only_apply_to_their_signal() {
+    let config = ExporterConfig::from_vars(
+        Preset::Local,
+        vars(&[
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", "http://collector:4318"),
+            ("OTEL_EXPORTER_OTLP_HEADERS", "x-tenant=orders"),
+            ("OTEL_EXPORTER_OTLP_TIMEOUT", "1000"),
+            ("OTEL_EXPORTER_OTLP_TRACES_PROTOCOL", "http/json"),
+            ("OTEL_EXPORTER_OTLP_TRACES_HEADERS", "x-tenant=traces"),
+            ("OTEL_EXPORTER_OTLP_TRACES_TIMEOUT", "250"),
+            ("OTEL_EXPORTER_OTLP_LOGS_PROTOCOL", "http/protobuf"),
+            (
+                "OTEL_EXPORTER_OTLP_LOGS_HEADERS",
+                "x-tenant=logs,x-priority=low",
+            ),
+            ("OTEL_EXPORTER_OTLP_LOGS_TIMEOUT", "500"),
+        ]),
+    )
+    .unwrap();
+    assert_eq!(
+        config.traces,
+        SignalConfig {
+            exporter: Exporter::Otlp(Protocol::HttpJson),
+            endpoint: "http://collector:4318/v1/traces".into(),
+            headers: vec![("x-tenant".into(), "traces".into())],
+            timeout: Duration::from_millis(250),
+        }
+    );
+    assert_eq!(
+        config.logs,
+        SignalConfig {
+            exporter: Exporter::Otlp(Protocol::HttpProtobuf),
+            endpoint: "http://collector:4318/v1/logs".into(),
+            headers: vec![
+                ("x-tenant".into(), "logs".into()),
+                ("x-priority".into(), "low".into())
+            ],
+            timeout: Duration::from_millis(500),
+        }
+    );
+
+    let config =
+        ExporterConfig::from_vars(Preset::Local, vars(&[("OTEL_LOGS_EXPORTER", "console")]))
+            .unwrap();
+    assert_eq!(config.traces.exporter, Exporter::Otlp(Protocol::Grpc));
+    assert_eq!(config.logs.exporter, Exporter::Stdout);
+}
+
+#[test]
+fn invalid_values_are_reported() {
+    let cases = [
+        ("OTEL_TRACES_EXPORTER", "zipkin"),
+        ("OTEL_LOGS_EXPORTER", "syslog"),
+        ("OTEL_EXPORTER_OTLP_LOGS_PROTOCOL", "thrift"),
+        ("OTEL_EXPORTER_OTLP_LOGS_TIMEOUT", "5s"),
+        ("OTEL_EXPORTER_OTLP_PROTOCOL", "thrift"),
+        ("OTEL_EXPORTER_OTLP_ENDPOINT", "localhost:4317"),
+        ("OTEL_EXPORTER_OTLP_HEADERS", "x-honeycomb-team"),
+        ("OTEL_EXPORTER_OTLP_TIMEOUT", "5s"),
+        ("OTEL_TRACES_SAMPLER", "sometimes"),
+    ];
+    for (variable, value) in cases {
+        let error =
+            ExporterConfig::from_vars(Preset::Local, vars(&[(variable, value)])).unwrap_err();
+        let ConfigError::InvalidValue {
+            variable: v,
+            value: actual,
+            ..
+        } = &error
+        else {
+            panic!("Unexpected error: {error}")
+        };
+        assert_eq!((*v, actual.as_str()), (variable, value));
+        assert!(error
+            .to_string()
+            .starts_with(&format!("`{variable}` is set to")));
+    }
+
+    let error = ExporterConfig::from_vars(
+        Preset::Local,
+        vars(&[
+            ("OTEL_TRACES_SAMPLER", "traceidratio"),
+            ("OTEL_TRACES_SAMPLER_ARG", "2"),
+        ]),
+    )
+    .unwrap_err();
+    assert!(matches!(
+        error,
+        ConfigError::InvalidValue {
+            variable: "OTEL_TRACES_SAMPLER_ARG",
+            ..
+        }
+    ));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn spans_are_exported_as_otlp_json_over_http() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    // A minimal HTTP server, returning the first request it receives.
+    let server = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut head = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            head.push(line.trim_end().to_owned());
+        }
+        let length: usize = head
+            .iter()
+            .find_map(|h| {
+                h.to_ascii_lowercase()
+                    .strip_prefix("content-length: ")?
+                    .parse()
+                    .ok()
+            })
+            .unwrap();
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        (head, body)
+    });
+
+    let endpoint = format!("http://{addr}");
+    let config = ExporterConfig::from_vars(
+        Preset::Local,
+        vars(&[
+            ("OTEL_EXPORTER_OTLP_PROTOCOL", "http/json"),
+            ("OTEL_EXPORTER_OTLP_ENDPOINT", &endpoint),
+            ("OTEL_EXPORTER_OTLP_HEADERS", "authorization=Bearer%20token"),
+            ("OTEL_SERVICE_NAME", "orders-api"),
+        ]),
+    )
+    .unwrap();
+    let provider = config.build().unwrap();
+    provider
+        .tracer("config")
+        .in_span("process total price", |_| {});
+    tokio::task::spawn_blocking(move || provider.shutdown())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let (head, body) = server.join().unwrap();
+    assert_eq!(head[0], "POST /v1/traces HTTP/1.1");
+    let headers: Vec<_> = head.iter().map(|h| h.to_ascii_lowercase()).collect();
+    assert!(headers.contains(&"content-type: application/json".to_owned()));
+    assert!(headers.contains(&"authorization: bearer token".to_owned()));
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let resource_spans = &body["resourceSpans"][0];
+    assert_eq!(
+        resource_spans["resource"]["attributes"][0]["key"],
+        "service.name"
+    );
+    assert_eq!(
+        resource_spans["scopeSpans"][0]["spans"][0]["name"],
+        "process total price"
+    );
+}
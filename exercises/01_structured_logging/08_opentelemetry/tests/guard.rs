@@ -0,0 +1,24 @@
+use helpers::otlp::MockCollector;
+use opentelemetry_training::init_test_subscriber;
+
+#[tokio::test]
+async fn dropping_the_guard_on_a_current_thread_runtime_exports_spans() {
+    // The test runtime is blocked while the guard is dropped: the collector standing for the
+    // backend runs on another one.
+    let collector_runtime = tokio::runtime::Runtime::new().unwrap();
+    let collector = collector_runtime
+        .spawn(MockCollector::start())
+        .await
+        .unwrap();
+    std::env::set_var("OTEL_EXPORTER_OTLP_ENDPOINT", collector.endpoint());
+    std::env::remove_var("OTEL_TRACES_EXPORTER");
+
+    let telemetry = init_test_subscriber();
+    tracing::info_span!("retrieve order").in_scope(|| {});
+    drop(telemetry);
+
+    // The subscriber has no filter: the spans of the exporter's own HTTP/2 client are there too.
+    let spans = collector.spans();
+    assert!(spans.iter().any(|s| s.name == "retrieve order"));
+    collector_runtime.shutdown_background();
+}
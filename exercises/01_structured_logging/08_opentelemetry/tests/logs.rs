@@ -0,0 +1,89 @@
+use helpers::otlp::{attribute, MockCollector};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_proto::tonic::common::v1::any_value::Value;
+use opentelemetry_proto::tonic::logs::v1::SeverityNumber;
+use opentelemetry_training::config::{ExporterConfig, Preset};
+use opentelemetry_training::logs::OtelLogLayer;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn events_are_exported_as_log_records_correlated_with_spans() {
+    let collector = MockCollector::start().await;
+    let endpoint = collector.endpoint();
+    let config = ExporterConfig::from_vars(Preset::Local, |name| match name {
+        "OTEL_EXPORTER_OTLP_ENDPOINT" => Some(endpoint.clone()),
+        "OTEL_SERVICE_NAME" => Some("orders-api".into()),
+        _ => None,
+    })
+    .unwrap();
+    let tracer_provider = config.build().unwrap();
+    let logger_provider = config.build_logger_provider().unwrap();
+    let tracer = tracer_provider.tracer("logs");
+    let subscriber = Registry::default()
+        .with(tracing_opentelemetry::layer().with_tracer(tracer.clone()))
+        .with(OtelLogLayer::new(&logger_provider, tracer));
+
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info_span!("process total price").in_scope(|| {
+            tracing::info!(order_number = 1, cached = false, "Retrieved order");
+        });
+        tracing::error!("The process panicked");
+    });
+    tokio::task::spawn_blocking(move || {
+        tracer_provider.shutdown().unwrap();
+        logger_provider.shutdown().unwrap();
+    })
+    .await
+    .unwrap();
+
+    // Spans and log records are attributed to the same service.
+    let resource_logs = collector.resource_logs();
+    let resource = resource_logs[0].resource.as_ref().unwrap();
+    assert_eq!(
+        attribute(&resource.attributes, "service.name").as_deref(),
+        Some("orders-api")
+    );
+    assert_eq!(
+        collector.resource_spans()[0].resource,
+        resource_logs[0].resource
+    );
+
+    let span = collector.spans().pop().unwrap();
+    let records = collector.log_records();
+    assert_eq!(records.len(), 2);
+    let body = |i: usize| match records[i].body.as_ref().and_then(|b| b.value.as_ref()) {
+        Some(Value::StringValue(body)) => body.clone(),
+        other => panic!("Unexpected body: {other:?}"),
+    };
+
+    assert_eq!(body(0), "Retrieved order");
+    assert_eq!(records[0].severity_text, "INFO");
+    assert_eq!(records[0].severity_number, SeverityNumber::Info as i32);
+    assert_eq!(
+        attribute(&records[0].attributes, "order_number").as_deref(),
+        Some("1")
+    );
+    assert_eq!(
+        attribute(&records[0].attributes, "cached").as_deref(),
+        Some("false")
+    );
+    assert_eq!(
+        attribute(&records[0].attributes, "code.namespace").as_deref(),
+        Some("logs")
+    );
+    assert_eq!(hex(&records[0].trace_id), hex(&span.trace_id));
+    assert_eq!(hex(&records[0].span_id), hex(&span.span_id));
+    // The span was sampled: so is the record.
+    assert_eq!(records[0].flags & 0xff, 1);
+
+    // Events outside of any span aren't lost, they just aren't correlated with a trace.
+    assert_eq!(body(1), "The process panicked");
+    assert_eq!(records[1].severity_number, SeverityNumber::Error as i32);
+    assert!(records[1].trace_id.is_empty());
+    assert!(records[1].span_id.is_empty());
+}
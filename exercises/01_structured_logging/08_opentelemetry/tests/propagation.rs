@@ -0,0 +1,104 @@
+use helpers::otlp::{attribute, MockCollector};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_proto::tonic::trace::v1::span::SpanKind;
+use opentelemetry_sdk::runtime;
+use opentelemetry_training::service::{OrdersClient, OrdersServer};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use tracing::Instrument;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn client_and_server_spans_share_the_trace() {
+    let collector = MockCollector::start().await;
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(collector.endpoint()),
+        )
+        .install_batch(runtime::Tokio)
+        .unwrap();
+    let otel = tracing_opentelemetry::layer().with_tracer(provider.tracer("propagation"));
+    // The server handles requests on tasks of its own: the subscriber must be global.
+    tracing::subscriber::set_global_default(Registry::default().with(otel)).unwrap();
+
+    let server = OrdersServer::start().await.unwrap();
+    let client = OrdersClient::new(server.addr());
+    let price = client
+        .get_price(1)
+        .instrument(tracing::info_span!("process total price"))
+        .await
+        .unwrap();
+    assert_eq!(price, 1089);
+    // A request without trace context starts a new trace on the server side, while a request
+    // from another (non-Rust) service carries its vendor-specific `tracestate` along.
+    let response = send_raw(server.addr(), 2, "");
+    assert!(response.ends_with("\r\n\r\n1029"), "{response}");
+    let response = send_raw(
+        server.addr(),
+        3,
+        "traceparent: 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01\r\n\
+        tracestate: vendor=opaque\r\n",
+    );
+    assert!(response.ends_with("\r\n\r\n999"), "{response}");
+
+    drop(server);
+    tokio::task::spawn_blocking(move || provider.shutdown())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let spans = collector.spans();
+    let span = |name: &str| spans.iter().find(|s| s.name == name).unwrap();
+    let client_span = span("GET /orders");
+    let request_span = |path: &str| {
+        spans
+            .iter()
+            .find(|s| attribute(&s.attributes, "url.path").as_deref() == Some(path))
+            .unwrap()
+    };
+    let (server_span, unrelated_span) = (request_span("/orders/1"), request_span("/orders/2"));
+    assert_eq!(client_span.kind, SpanKind::Client as i32);
+    assert_eq!(server_span.kind, SpanKind::Server as i32);
+    assert_eq!(server_span.trace_id, client_span.trace_id);
+    assert_eq!(server_span.parent_span_id, client_span.span_id);
+    assert_eq!(client_span.trace_id, span("process total price").trace_id);
+    // The server's own spans are children of the request span.
+    let order_span = spans
+        .iter()
+        .find(|s| s.name == "retrieve order" && s.trace_id == client_span.trace_id)
+        .unwrap();
+    assert_eq!(order_span.parent_span_id, server_span.span_id);
+
+    assert_ne!(unrelated_span.trace_id, client_span.trace_id);
+    assert!(unrelated_span.parent_span_id.is_empty());
+
+    let remote_span = request_span("/orders/3");
+    assert_eq!(
+        hex(&remote_span.trace_id),
+        "4bf92f3577b34da6a3ce929d0e0e4736"
+    );
+    assert_eq!(hex(&remote_span.parent_span_id), "00f067aa0ba902b7");
+    assert_eq!(remote_span.trace_state, "vendor=opaque");
+}
+
+/// Send a request for `order_number`, with the given extra header lines.
+fn send_raw(addr: SocketAddr, order_number: u64, headers: &str) -> String {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    write!(
+        stream,
+        "GET /orders/{order_number} HTTP/1.1\r\nHost: orders\r\nConnection: close\r\n{headers}\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
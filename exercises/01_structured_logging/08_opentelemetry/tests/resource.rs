@@ -0,0 +1,188 @@
+use opentelemetry_sdk::resource::ResourceDetector;
+use opentelemetry_training::config::{ExporterConfig, Preset};
+use opentelemetry_training::resource::{
+    default_detectors, detect, ContainerDetector, HostDetector, PackageDetector, ProcessDetector,
+};
+use std::path::Path;
+
+const CONTAINER_ID: &str = "3f4e2c1d8b7a69584736251403f2e1d0c9b8a7968574635241302f1e0d9c8b7a";
+
+/// A fake `/proc`, with the given files.
+fn proc_root(files: &[(&str, &str)]) -> tempfile::TempDir {
+    let root = tempfile::tempdir().unwrap();
+    for (path, content) in files {
+        let path = root.path().join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+    root
+}
+
+fn attributes(detectors: Vec<Box<dyn ResourceDetector>>) -> Vec<(String, String)> {
+    detect(&detectors)
+        .into_iter()
+        .map(|kv| (kv.key.to_string(), kv.value.to_string()))
+        .collect()
+}
+
+fn container_id(root: &Path) -> Option<String> {
+    attributes(vec![Box::new(
+        ContainerDetector::new().with_proc_root(root),
+    )])
+    .into_iter()
+    .find_map(|(key, value)| (key == "container.id").then_some(value))
+}
+
+#[test]
+fn the_container_id_is_read_from_the_cgroup_file() {
+    let cgroup_v1 =
+        format!("12:pids:/docker/{CONTAINER_ID}\n11:memory:/docker/{CONTAINER_ID}\n0::/\n");
+    let cgroup_v2 = format!("0::/system.slice/docker-{CONTAINER_ID}.scope\n");
+    let kubernetes = format!("0::/kubepods/besteffort/pod0a1b2c3d/{CONTAINER_ID}\n");
+    for cgroup in [cgroup_v1, cgroup_v2, kubernetes] {
+        let root = proc_root(&[("self/cgroup", &cgroup)]);
+        assert_eq!(
+            container_id(root.path()).as_deref(),
+            Some(CONTAINER_ID),
+            "{cgroup}"
+        );
+    }
+
+    // Not in a container, or no `/proc` at all.
+    let root = proc_root(&[(
+        "self/cgroup",
+        "0::/user.slice/user-1000.slice/session-2.scope\n",
+    )]);
+    assert_eq!(container_id(root.path()), None);
+    let root = proc_root(&[]);
+    assert_eq!(container_id(root.path()), None);
+}
+
+#[test]
+fn host_and_process_are_detected() {
+    let root = proc_root(&[("sys/kernel/hostname", "orders-7f9c\n")]);
+    let attributes = attributes(vec![
+        Box::new(HostDetector::new().with_proc_root(root.path())),
+        Box::new(ProcessDetector),
+    ]);
+    let get = |key: &str| {
+        attributes
+            .iter()
+            .find_map(|(k, v)| (k == key).then_some(v.as_str()))
+    };
+    assert_eq!(get("host.name"), Some("orders-7f9c"));
+    let os_type = if cfg!(target_os = "macos") {
+        "darwin"
+    } else {
+        std::env::consts::OS
+    };
+    assert_eq!(get("os.type"), Some(os_type));
+    assert_eq!(
+        get("process.pid"),
+        Some(std::process::id().to_string().as_str())
+    );
+    assert_eq!(get("process.runtime.name"), Some("rustc"));
+    assert!(get("process.runtime.version").is_some());
+    assert!(get("process.executable.name")
+        .unwrap()
+        .starts_with("resource"));
+    assert!(get("process.command_line").is_some());
+}
+
+#[test]
+fn the_default_detectors_name_the_service_after_the_package() {
+    let get = |service_name: Option<&str>, key: &str| {
+        let config = ExporterConfig::from_vars_with_detectors(
+            Preset::Local,
+            |name| match name {
+                "OTEL_SERVICE_NAME" => service_name.map(str::to_owned),
+                _ => None,
+            },
+            &default_detectors(),
+        )
+        .unwrap();
+        config
+            .resource
+            .iter()
+            .find_map(|kv| (kv.key.as_str() == key).then(|| kv.value.to_string()))
+    };
+    assert_eq!(
+        get(None, "service.name").as_deref(),
+        Some(env!("CARGO_PKG_NAME"))
+    );
+    assert_eq!(
+        get(None, "service.version").as_deref(),
+        Some(env!("CARGO_PKG_VERSION"))
+    );
+    assert_eq!(
+        get(Some("orders-api"), "service.name").as_deref(),
+        Some("orders-api")
+    );
+}
+
+#[test]
+fn the_environment_takes_precedence_over_detectors() {
+    let root = proc_root(&[("sys/kernel/hostname", "orders-7f9c\n")]);
+    let detectors = || -> Vec<Box<dyn ResourceDetector>> {
+        vec![
+            Box::new(PackageDetector::new("1.2.3").with_service_name("orders-api")),
+            Box::new(HostDetector::new().with_proc_root(root.path())),
+            // Later detectors win.
+            Box::new(PackageDetector::new("1.2.4").with_service_name("orders-api")),
+        ]
+    };
+    let resource = |vars: &'static [(&'static str, &'static str)]| {
+        let config = ExporterConfig::from_vars_with_detectors(
+            Preset::Local,
+            |name| {
+                vars.iter()
+                    .find_map(|(k, v)| (*k == name).then(|| v.to_string()))
+            },
+            &detectors(),
+        )
+        .unwrap();
+        config
+            .resource
+            .iter()
+            .filter(|kv| kv.key.as_str() != "os.type")
+            .map(|kv| (kv.key.to_string(), kv.value.to_string()))
+            .collect::<Vec<_>>()
+    };
+    let pairs = |pairs: &[(&str, &str)]| -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    };
+
+    assert_eq!(
+        resource(&[]),
+        pairs(&[
+            ("host.name", "orders-7f9c"),
+            ("service.name", "orders-api"),
+            ("service.version", "1.2.4"),
+        ])
+    );
+    assert_eq!(
+        resource(&[(
+            "OTEL_RESOURCE_ATTRIBUTES",
+            "host.name=edge-1,service.name=checkout"
+        )]),
+        pairs(&[
+            ("service.version", "1.2.4"),
+            ("host.name", "edge-1"),
+            ("service.name", "checkout"),
+        ])
+    );
+    assert_eq!(
+        resource(&[
+            ("OTEL_RESOURCE_ATTRIBUTES", "service.name=checkout"),
+            ("OTEL_SERVICE_NAME", "payments"),
+        ]),
+        pairs(&[
+            ("host.name", "orders-7f9c"),
+            ("service.version", "1.2.4"),
+            ("service.name", "payments"),
+        ])
+    );
+}
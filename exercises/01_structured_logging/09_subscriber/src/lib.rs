@@ -2,14 +2,18 @@
 //!
 //! Build a `tracing` subscriber that:
 //!
-//! - Emits JSON-structured logs to an in-memory buffer
-//! - Exports telemetry data in OpenTelemetry format to Honeycomb
-//! - Only captures spans that are level `INFO` or above
+//! - Emits JSON-structured logs to an in-memory buffer, only for spans that are level `INFO`
+//!   or above
+//! - Exports telemetry data in OpenTelemetry format to Honeycomb, including `TRACE`-level spans
+//! - Reports warnings and errors, in a human-readable format, to a separate buffer
+//! - Lets you change the filters of each of the above at runtime, via a local admin endpoint
 //!
 //! You can look at the subscribers we built in the previous exercises for inspiration!
+//! The admin endpoint is already taken care of: wire the filters into
+//! [`helpers::admin::AdminServer`].
 mod subscriber;
 
-pub use subscriber::init_test_subscriber;
+pub use subscriber::{init_test_subscriber, Telemetry, TelemetryBuilder};
 use tracing::{instrument, Span};
 
 /// Given a list of order numbers, compute the total price.
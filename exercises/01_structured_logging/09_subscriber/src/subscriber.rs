@@ -1,5 +1,80 @@
+use helpers::admin::AdminServer;
+use helpers::guard::TelemetryGuard;
 use helpers::MockWriter;
+use opentelemetry_sdk::trace::TracerProvider as SdkTracerProvider;
+use tracing_subscriber::EnvFilter;
 
-pub fn init_test_subscriber() -> MockWriter {
+/// Install the subscriber with the default filters, exporting spans to Honeycomb.
+pub fn init_test_subscriber() -> Telemetry {
+    // Tip: the tests run on a current-thread runtime, which is blocked while the guard is
+    // dropped. Build the provider on a runtime of its own (see `TelemetryGuard::with_runtime`)
+    // or the spans still buffered at the end of a test are lost.
     todo!()
 }
+
+/// Where our telemetry data ends up, with a filter for each output.
+///
+/// By default:
+///
+/// - JSON logs only get `INFO` and above, to keep their volume in check
+/// - the OTLP exporter gets everything, down to `TRACE`-level spans like `retrieve order`
+/// - the error-reporting sink only gets `WARN` and above
+pub struct TelemetryBuilder {
+    provider: SdkTracerProvider,
+    json_filter: EnvFilter,
+    otlp_filter: EnvFilter,
+    errors_filter: EnvFilter,
+}
+
+/// The outputs of the subscriber installed by [`TelemetryBuilder::init`].
+pub struct Telemetry {
+    /// The JSON logs.
+    pub logs: MockWriter,
+    /// The human-readable error reports.
+    pub errors: MockWriter,
+    /// Change any of the filters at runtime, under `/filters/json`, `/filters/otlp` and
+    /// `/filters/errors`.
+    pub admin: AdminServer,
+    /// Flushes the tracer provider and both writers, when shut down or dropped.
+    pub guard: TelemetryGuard,
+}
+
+impl TelemetryBuilder {
+    /// Export spans through the given provider, using the default filters.
+    ///
+    /// We need the provider rather than a tracer: it's the one that can be flushed.
+    pub fn new(provider: SdkTracerProvider) -> Self {
+        Self {
+            provider,
+            json_filter: EnvFilter::new("info"),
+            otlp_filter: EnvFilter::new("trace"),
+            errors_filter: EnvFilter::new("warn"),
+        }
+    }
+
+    /// Set the filter for the JSON logs.
+    pub fn json_filter(mut self, filter: EnvFilter) -> Self {
+        self.json_filter = filter;
+        self
+    }
+
+    /// Set the filter for the OTLP exporter.
+    pub fn otlp_filter(mut self, filter: EnvFilter) -> Self {
+        self.otlp_filter = filter;
+        self
+    }
+
+    /// Set the filter for the error-reporting sink.
+    pub fn errors_filter(mut self, filter: EnvFilter) -> Self {
+        self.errors_filter = filter;
+        self
+    }
+
+    /// Install the subscriber as the global default and start the admin server.
+    pub fn init(self) -> Telemetry {
+        // Tip: each filter can be attached to its own layer with `Layer::with_filter`.
+        // Wrap them in a `tracing_subscriber::reload::Layer` first, and hand the handles over
+        // to `helpers::admin::AdminServer` to change them at runtime.
+        todo!()
+    }
+}
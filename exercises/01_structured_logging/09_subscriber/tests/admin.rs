@@ -0,0 +1,55 @@
+use helpers::admin::INVALID_DIRECTIVE;
+use subscriber::TelemetryBuilder;
+
+fn get(url: &str) -> String {
+    ureq::get(url).call().unwrap().into_string().unwrap()
+}
+
+#[test]
+fn filters_can_be_changed_through_the_admin_server() {
+    // We don't care about the exported spans here.
+    let provider = opentelemetry_sdk::trace::TracerProvider::default();
+    let telemetry = TelemetryBuilder::new(provider).init();
+    let (logging_buffer, admin) = (telemetry.logs, telemetry.admin);
+    let json_filter = format!("{}/filters/json", admin.url());
+    let otlp_filter = format!("{}/filters/otlp", admin.url());
+
+    assert_eq!(get(&json_filter), "info");
+    subscriber::get_total(&[1, 2, 3]).unwrap();
+    assert!(!logging_buffer
+        .log_output()
+        .unwrap()
+        .text()
+        .contains("retrieve order"));
+
+    // Bump the verbosity of the JSON logs, and nothing else.
+    let response = ureq::put(&json_filter).send_string("trace").unwrap();
+    assert_eq!(response.into_string().unwrap(), "trace");
+    assert_eq!(get(&json_filter), "trace");
+    assert_eq!(get(&otlp_filter), "trace");
+
+    subscriber::get_total(&[1, 2, 3]).unwrap();
+    assert!(logging_buffer
+        .log_output()
+        .unwrap()
+        .text()
+        .contains("retrieve order"));
+
+    // Invalid directives are rejected, with the parse error (whose wording depends on the
+    // version of `tracing-subscriber`).
+    let Err(ureq::Error::Status(400, response)) =
+        ureq::put(&otlp_filter).send_string("subscriber=loud")
+    else {
+        panic!("An invalid directive should be rejected with a 400");
+    };
+    let error = response.into_string().unwrap();
+    assert!(error.starts_with(INVALID_DIRECTIVE), "{error}");
+    // The previous directive is still in place.
+    assert_eq!(get(&otlp_filter), "trace");
+
+    let Err(ureq::Error::Status(404, _)) =
+        ureq::get(&format!("{}/filters/fmt", admin.url())).call()
+    else {
+        panic!("Unknown filters should return a 404");
+    };
+}
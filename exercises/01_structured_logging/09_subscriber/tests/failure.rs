@@ -1,17 +1,16 @@
-use opentelemetry::global::shutdown_tracer_provider;
 use serde_json::json;
 use subscriber::init_test_subscriber;
 
 #[tokio::test]
 async fn failure() {
-    let logging_buffer = init_test_subscriber();
+    let telemetry = init_test_subscriber();
     let order_numbers = vec![3, 4, 5];
 
     subscriber::get_total(&order_numbers).unwrap_err();
 
     // Check that the log output matches what we expect.
     // Check that the log output matches what we expect.
-    let logging_output = logging_buffer.log_output().unwrap();
+    let logging_output = telemetry.logs.log_output().unwrap();
     let mut log_lines = logging_output.lines();
 
     log_lines.next_some().assert_json_include(
@@ -23,7 +22,5 @@ async fn failure() {
     log_lines.end();
 
     // Ensure all spans are exported
-    tokio::task::spawn_blocking(|| shutdown_tracer_provider())
-        .await
-        .unwrap();
+    telemetry.guard.shutdown().await;
 }
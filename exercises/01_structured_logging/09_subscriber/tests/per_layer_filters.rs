@@ -0,0 +1,49 @@
+use helpers::otlp::MockCollector;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::runtime;
+use subscriber::TelemetryBuilder;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn each_output_has_its_own_filter() {
+    let collector = MockCollector::start().await;
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(collector.endpoint()),
+        )
+        .install_batch(runtime::Tokio)
+        .unwrap();
+    let telemetry = TelemetryBuilder::new(provider).init();
+
+    subscriber::get_total(&[1, 2, 3]).unwrap();
+    tracing::info!("Shipping order");
+    tracing::error!("Failed to ship order");
+    let report = telemetry.guard.shutdown().await;
+    assert!(report.is_ok(), "{report}");
+
+    // `retrieve order` is a `TRACE`-level span: it's exported...
+    let span_names: Vec<_> = collector.spans().into_iter().map(|s| s.name).collect();
+    assert_eq!(
+        span_names
+            .iter()
+            .filter(|name| *name == "retrieve order")
+            .count(),
+        3
+    );
+    assert!(span_names.contains(&"process total price".to_string()));
+    // ...but it doesn't show up in the JSON logs.
+    let logs = telemetry.logs.log_output().unwrap();
+    assert!(logs.text().contains("process total price"));
+    assert!(!logs.text().contains("retrieve order"), "{}", logs.text());
+    assert!(logs.text().contains("Shipping order"));
+
+    // The error-reporting sink only cares about errors.
+    let errors = telemetry.errors.log_output().unwrap();
+    let mut lines = errors.lines();
+    let report = lines.next_some();
+    assert!(report.text().contains("ERROR"), "{}", report.text());
+    assert!(report.text().contains("Failed to ship order"));
+    lines.end();
+}
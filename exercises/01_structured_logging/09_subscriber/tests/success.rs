@@ -1,10 +1,9 @@
-use opentelemetry::global::shutdown_tracer_provider;
 use serde_json::json;
 use subscriber::init_test_subscriber;
 
 #[tokio::test]
 async fn success() {
-    let logging_buffer = init_test_subscriber();
+    let telemetry = init_test_subscriber();
     let order_numbers = vec![1, 2, 3];
 
     let total = subscriber::get_total(&order_numbers).unwrap();
@@ -13,7 +12,7 @@ async fn success() {
     assert_eq!(total, 3117);
 
     // Check that the log output matches what we expect.
-    let logging_output = logging_buffer.log_output().unwrap();
+    let logging_output = telemetry.logs.log_output().unwrap();
     let mut log_lines = logging_output.lines();
 
     log_lines.next_some().assert_json_include(
@@ -24,8 +23,9 @@ async fn success() {
     );
     log_lines.end();
 
+    // Nothing went wrong, so there is nothing to report.
+    telemetry.errors.log_output().unwrap().lines().end();
+
     // Ensure all spans are exported
-    tokio::task::spawn_blocking(|| shutdown_tracer_provider())
-        .await
-        .unwrap();
+    telemetry.guard.shutdown().await;
 }
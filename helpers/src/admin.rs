@@ -0,0 +1,150 @@
+//! A tiny admin HTTP server to change filters while the application is running.
+//!
+//! Restarting a process with a different `RUST_LOG` is often not an option when you are trying
+//! to debug a live issue: by the time it's back up, the issue might be gone.
+//! Each filter registered with [`AdminServer::start`] is exposed under `/filters/<name>`:
+//!
+//! - `GET` returns the current directive
+//! - `PUT` replaces it with the directive in the request body
+//!   (or returns a `400 Bad Request` if it isn't valid, with a body starting with
+//!   [`INVALID_DIRECTIVE`] followed by the parse error).
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tokio::sync::oneshot;
+use tracing_subscriber::{reload, EnvFilter};
+
+/// The start of the body of the response to a `PUT` with an invalid directive.
+pub const INVALID_DIRECTIVE: &str = "Invalid filter directive: ";
+
+/// A handle to an [`EnvFilter`] that can be swapped at runtime.
+pub struct ReloadableFilter {
+    current: Box<dyn Fn() -> Option<String> + Send + Sync>,
+    replace: Box<dyn Fn(EnvFilter) -> Result<(), reload::Error> + Send + Sync>,
+}
+
+impl ReloadableFilter {
+    /// Wrap the handle returned by [`reload::Layer::new`].
+    pub fn new<S: 'static>(handle: reload::Handle<EnvFilter, S>) -> Self {
+        let handle2 = handle.clone();
+        Self {
+            current: Box::new(move || handle.with_current(|f| f.to_string()).ok()),
+            replace: Box::new(move |filter| handle2.reload(filter)),
+        }
+    }
+}
+
+/// The admin server, listening on a random local port.
+///
+/// It runs on a dedicated thread, with its own runtime, so that it keeps answering even if the
+/// application's runtime is busy (or blocked).
+/// It is shut down when dropped.
+pub struct AdminServer {
+    addr: SocketAddr,
+    _shutdown: oneshot::Sender<()>,
+}
+
+impl AdminServer {
+    /// Start serving the given filters, each under `/filters/<name>`.
+    pub fn start(
+        filters: impl IntoIterator<Item = (&'static str, ReloadableFilter)>,
+    ) -> std::io::Result<Self> {
+        let addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0);
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let filters: Arc<HashMap<_, _>> = Arc::new(filters.into_iter().collect());
+        let (shutdown_tx, mut shutdown_rx) = oneshot::channel::<()>();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()?;
+
+        std::thread::Builder::new()
+            .name("admin-server".into())
+            .spawn(move || {
+                runtime.block_on(async move {
+                    let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                    loop {
+                        let stream = tokio::select! {
+                            _ = &mut shutdown_rx => break,
+                            accepted = listener.accept() => match accepted {
+                                Ok((stream, _)) => stream,
+                                Err(_) => continue,
+                            },
+                        };
+                        let filters = filters.clone();
+                        let service = service_fn(move |request| handle(request, filters.clone()));
+                        tokio::spawn(async move {
+                            let _ = http1::Builder::new()
+                                .serve_connection(TokioIo::new(stream), service)
+                                .await;
+                        });
+                    }
+                })
+            })?;
+
+        Ok(Self {
+            addr,
+            _shutdown: shutdown_tx,
+        })
+    }
+
+    /// The base URL of the admin server, e.g. `http://127.0.0.1:43127`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+}
+
+async fn handle(
+    request: Request<Incoming>,
+    filters: Arc<HashMap<&'static str, ReloadableFilter>>,
+) -> Result<Response<Full<Bytes>>, hyper::Error> {
+    let Some(filter) = request
+        .uri()
+        .path()
+        .strip_prefix("/filters/")
+        .and_then(|name| filters.get(name))
+    else {
+        return Ok(respond(StatusCode::NOT_FOUND, "Unknown filter"));
+    };
+
+    let response = match *request.method() {
+        Method::GET => match (filter.current)() {
+            Some(directive) => respond(StatusCode::OK, directive),
+            None => respond(StatusCode::GONE, "The subscriber has been dropped"),
+        },
+        Method::PUT => {
+            let body = request.into_body().collect().await?.to_bytes();
+            let Ok(directive) = std::str::from_utf8(&body) else {
+                return Ok(respond(
+                    StatusCode::BAD_REQUEST,
+                    "The body must be valid UTF-8",
+                ));
+            };
+            match EnvFilter::builder().parse(directive.trim()) {
+                Ok(new_filter) => match (filter.replace)(new_filter) {
+                    Ok(()) => respond(StatusCode::OK, (filter.current)().unwrap_or_default()),
+                    Err(e) => respond(StatusCode::GONE, e.to_string()),
+                },
+                Err(e) => respond(StatusCode::BAD_REQUEST, format!("{INVALID_DIRECTIVE}{e}")),
+            }
+        }
+        _ => respond(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Only GET and PUT are supported",
+        ),
+    };
+    Ok(response)
+}
+
+fn respond(status: StatusCode, body: impl Into<Bytes>) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::new(body.into()));
+    *response.status_mut() = status;
+    response
+}
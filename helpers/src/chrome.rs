@@ -0,0 +1,341 @@
+//! Export spans and events in the [Chrome Trace Event format].
+//!
+//! Reading interleaved log lines to understand how futures are scheduled is painful.
+//! The file produced by [`ChromeLayer`] can instead be opened in `chrome://tracing` or in
+//! [Perfetto](https://ui.perfetto.dev) to *see* when each span was entered and exited,
+//! on which thread, and how the different tasks interleave at their `.await` points.
+//!
+//! [Chrome Trace Event format]: https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+use crate::event_fields::{json_visitor, take_message};
+use serde_json::{json, Map, Value};
+use std::cell::Cell;
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+
+/// A `tracing` layer that records spans and events as Chrome trace events.
+///
+/// - Every time a span is entered and then exited, we get a slice on the track of the thread
+///   it was entered on.
+/// - `tracing` events become instant events on the track of the thread they were emitted on.
+/// - `follows_from` relationships become flow arrows, going from the point where the
+///   relationship was recorded to the first time the follower span is entered.
+/// - Optionally, each task gets a track of its own (see [`ChromeLayer::with_task_tracks`]).
+///
+/// The trace is written out when the [`ChromeGuard`] returned by [`ChromeLayer::new`] is
+/// dropped (or [`finish`](ChromeGuard::finish)ed).
+pub struct ChromeLayer {
+    trace: Arc<Trace>,
+    task_tracks: bool,
+}
+
+/// Writes the collected trace when dropped.
+#[must_use = "The trace is written out when the guard is dropped"]
+pub struct ChromeGuard {
+    trace: Arc<Trace>,
+    writer: Option<Box<dyn Write + Send>>,
+}
+
+struct Trace {
+    start: Instant,
+    pid: u32,
+    events: Mutex<Vec<Value>>,
+    /// The threads we have already emitted a `thread_name` metadata event for.
+    named_threads: Mutex<HashSet<u64>>,
+    next_flow_id: AtomicU64,
+    next_track_id: AtomicU64,
+}
+
+impl Trace {
+    /// Microseconds elapsed since the layer was created.
+    fn timestamp(&self) -> f64 {
+        self.start.elapsed().as_nanos() as f64 / 1000.0
+    }
+
+    fn push(&self, mut event: Map<String, Value>) {
+        event.insert("pid".into(), self.pid.into());
+        self.events.lock().unwrap().push(Value::Object(event));
+    }
+
+    /// Record an event on the track of the current thread.
+    fn push_on_thread(&self, mut event: Map<String, Value>) {
+        let tid = current_thread_id();
+        if self.named_threads.lock().unwrap().insert(tid) {
+            let name = std::thread::current()
+                .name()
+                .map(ToOwned::to_owned)
+                .unwrap_or_else(|| format!("thread {tid}"));
+            self.push(as_object(json!({
+                "name": "thread_name",
+                "ph": "M",
+                "tid": tid,
+                "args": {"name": name},
+            })));
+        }
+        event.insert("tid".into(), tid.into());
+        self.push(event);
+    }
+}
+
+impl ChromeLayer {
+    /// Create a new layer that will write its trace to `writer` once the returned guard
+    /// is dropped.
+    pub fn new(writer: impl Write + Send + 'static) -> (Self, ChromeGuard) {
+        let trace = Arc::new(Trace {
+            start: Instant::now(),
+            pid: std::process::id(),
+            events: Mutex::new(Vec::new()),
+            named_threads: Mutex::new(HashSet::new()),
+            next_flow_id: AtomicU64::new(1),
+            next_track_id: AtomicU64::new(1),
+        });
+        let layer = Self {
+            trace: trace.clone(),
+            task_tracks: false,
+        };
+        let guard = ChromeGuard {
+            trace,
+            writer: Some(Box::new(writer)),
+        };
+        (layer, guard)
+    }
+
+    /// Give each task a track of its own, on top of the per-thread ones.
+    ///
+    /// A task is identified by the root of the span tree: the root span and all its
+    /// descendants show up, properly nested, on the same track, no matter which threads they
+    /// were polled on.
+    /// Perfetto displays these tracks next to the thread ones.
+    pub fn with_task_tracks(mut self, task_tracks: bool) -> Self {
+        self.task_tracks = task_tracks;
+        self
+    }
+}
+
+impl ChromeGuard {
+    /// Write the trace collected so far and stop recording.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.write()
+    }
+
+    fn write(&mut self) -> std::io::Result<()> {
+        let Some(mut writer) = self.writer.take() else {
+            return Ok(());
+        };
+        let events = std::mem::take(&mut *self.trace.events.lock().unwrap());
+        let trace = json!({
+            "traceEvents": events,
+            "displayTimeUnit": "ms",
+        });
+        serde_json::to_writer(&mut writer, &trace)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+}
+
+impl Drop for ChromeGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.write() {
+            eprintln!("Failed to write the Chrome trace: {e}");
+        }
+    }
+}
+
+/// Fields recorded on a span so far, stored in its extensions.
+struct SpanFields(Map<String, Value>);
+
+/// Flow arrows pointing at a span that hasn't been entered yet.
+struct PendingFlows(Vec<u64>);
+
+/// The id of the task track a root span (and its descendants) is drawn on, stored in the
+/// root's extensions.
+///
+/// We can't use the root's span id: the registry reuses the ids of closed spans, which would
+/// merge unrelated tasks into the same track.
+#[derive(Clone, Copy)]
+struct TaskTrack(u64);
+
+impl ChromeLayer {
+    /// The task track of the tree `span` belongs to, assigning a new one to its root if needed.
+    fn task_track<S>(&self, span: &SpanRef<'_, S>) -> String
+    where
+        S: for<'span> LookupSpan<'span>,
+    {
+        let root = span.scope().from_root().next().unwrap();
+        let mut extensions = root.extensions_mut();
+        let TaskTrack(track) = match extensions.get_mut::<TaskTrack>() {
+            Some(track) => *track,
+            None => {
+                let track = TaskTrack(self.trace.next_track_id.fetch_add(1, Ordering::Relaxed));
+                extensions.insert(track);
+                track
+            }
+        };
+        format!("{track:#x}")
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for ChromeLayer
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let mut fields = Map::new();
+        attrs.record(&mut json_visitor(&mut fields));
+
+        if self.task_tracks {
+            self.trace.push(as_object(json!({
+                "name": span.name(),
+                "cat": span.metadata().target(),
+                "ph": "b",
+                "ts": self.trace.timestamp(),
+                "id": self.task_track(&span),
+                "args": fields,
+            })));
+        }
+        span.extensions_mut().insert(SpanFields(fields));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let mut extensions = span.extensions_mut();
+        if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+            values.record(&mut json_visitor(fields));
+        }
+    }
+
+    fn on_follows_from(&self, id: &Id, follows: &Id, ctx: Context<'_, S>) {
+        let flow_id = self.trace.next_flow_id.fetch_add(1, Ordering::Relaxed);
+        let (Some(span), Some(follows)) = (ctx.span(id), ctx.span(follows)) else {
+            return;
+        };
+        self.trace.push_on_thread(as_object(json!({
+            "name": follows.name(),
+            "cat": "follows_from",
+            "ph": "s",
+            "ts": self.trace.timestamp(),
+            "id": flow_id,
+        })));
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<PendingFlows>() {
+            Some(PendingFlows(flows)) => flows.push(flow_id),
+            None => extensions.insert(PendingFlows(vec![flow_id])),
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut fields = Map::new();
+        event.record(&mut json_visitor(&mut fields));
+        let name = take_message(&mut fields).unwrap_or_else(|| event.metadata().name().to_owned());
+        let mut instant = as_object(json!({
+            "name": name,
+            "cat": event.metadata().target(),
+            "ph": "i",
+            "s": "t",
+            "ts": self.trace.timestamp(),
+            "args": fields,
+        }));
+        self.trace.push_on_thread(instant.clone());
+
+        if self.task_tracks {
+            if let Some(span) = ctx.event_span(event) {
+                instant.insert("ph".into(), "n".into());
+                instant.insert("id".into(), self.task_track(&span).into());
+                instant.remove("s");
+                self.trace.push(instant);
+            }
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let ts = self.trace.timestamp();
+        let args = span
+            .extensions()
+            .get::<SpanFields>()
+            .map(|f| f.0.clone())
+            .unwrap_or_default();
+        self.trace.push_on_thread(as_object(json!({
+            "name": span.name(),
+            "cat": span.metadata().target(),
+            "ph": "B",
+            "ts": ts,
+            "args": args,
+        })));
+        // Close any flow arrow pointing at this span, now that it has a slice to bind to.
+        let pending = span.extensions_mut().remove::<PendingFlows>();
+        if let Some(PendingFlows(flows)) = pending {
+            for flow_id in flows {
+                self.trace.push_on_thread(as_object(json!({
+                    "name": span.name(),
+                    "cat": "follows_from",
+                    "ph": "f",
+                    "bp": "e",
+                    "ts": ts,
+                    "id": flow_id,
+                })));
+            }
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        self.trace.push_on_thread(as_object(json!({
+            "name": span.name(),
+            "cat": span.metadata().target(),
+            "ph": "E",
+            "ts": self.trace.timestamp(),
+        })));
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        if !self.task_tracks {
+            return;
+        }
+        let span = ctx.span(&id).unwrap();
+        let args = span
+            .extensions()
+            .get::<SpanFields>()
+            .map(|f| f.0.clone())
+            .unwrap_or_default();
+        self.trace.push(as_object(json!({
+            "name": span.name(),
+            "cat": span.metadata().target(),
+            "ph": "e",
+            "ts": self.trace.timestamp(),
+            "id": self.task_track(&span),
+            "args": args,
+        })));
+    }
+}
+
+fn as_object(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(map) => map,
+        _ => unreachable!(),
+    }
+}
+
+/// Chrome traces identify threads using integers, while `std::thread::ThreadId` is opaque.
+/// We assign our own ids, in the order we first see each thread.
+fn current_thread_id() -> u64 {
+    static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+    thread_local! {
+        static THREAD_ID: Cell<Option<u64>> = const { Cell::new(None) };
+    }
+    THREAD_ID.with(|id| match id.get() {
+        Some(id) => id,
+        None => {
+            let new_id = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+            id.set(Some(new_id));
+            new_id
+        }
+    })
+}
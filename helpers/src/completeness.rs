@@ -0,0 +1,174 @@
+//! Check that every field declared on a span gets a value before the span closes.
+//!
+//! `#[instrument(fields(outcome))]` or `fields(error.msg = tracing::field::Empty)` only pay off
+//! if every code path remembers to record them. When one doesn't, nothing fails: the field is
+//! silently missing from the telemetry data and we find out when we need it the most.
+//! [`FieldCompletenessLayer`] keeps track of the fields that were declared but never recorded
+//! and reports them when the span closes.
+use std::collections::HashSet;
+use tracing::callsite::{Callsite, DefaultCallsite, Identifier};
+use tracing::field::{Field, FieldSet, Value, Visit};
+use tracing::metadata::Kind;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+/// The target used for the diagnostic events emitted by [`FieldCompletenessLayer`].
+pub const DIAGNOSTIC_TARGET: &str = module_path!();
+
+/// A `tracing` layer that reports declared span fields that were never recorded.
+///
+/// By default, it emits a `WARN` diagnostic event for each missing field, with the name of the
+/// span, the name of the field and the location of the span callsite.
+/// The diagnostics are dispatched to the layers *below* this one: add it last, so that it wraps
+/// all the others.
+///
+/// In [strict mode](FieldCompletenessLayer::strict) it panics instead, which is what you want
+/// in tests.
+#[derive(Default)]
+pub struct FieldCompletenessLayer {
+    strict: bool,
+    allowed: Vec<String>,
+}
+
+/// The fields of a span that haven't been recorded yet, stored in its extensions.
+struct Unrecorded(HashSet<&'static str>);
+
+impl Visit for Unrecorded {
+    fn record_debug(&mut self, field: &Field, _value: &dyn std::fmt::Debug) {
+        self.0.remove(field.name());
+    }
+}
+
+impl FieldCompletenessLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Panic, rather than emitting a diagnostic event, when a declared field is never recorded.
+    pub fn strict(mut self) -> Self {
+        self.strict = true;
+        self
+    }
+
+    /// Don't complain if fields matching `pattern` are never recorded.
+    ///
+    /// `pattern` is either a field name or a prefix followed by `*` (e.g. `error.*`), for fields
+    /// that are only recorded on some code paths by design.
+    pub fn allow_unrecorded(mut self, pattern: impl Into<String>) -> Self {
+        self.allowed.push(pattern.into());
+        self
+    }
+
+    fn is_allowed(&self, field: &str) -> bool {
+        self.allowed
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => field.starts_with(prefix),
+                None => field == pattern,
+            })
+    }
+
+    fn report<S>(&self, span: &Metadata<'static>, field: &str, ctx: &Context<'_, S>)
+    where
+        S: Subscriber,
+    {
+        let callsite = match (span.file(), span.line()) {
+            (Some(file), Some(line)) => format!("{file}:{line}"),
+            _ => span.module_path().unwrap_or(span.target()).to_owned(),
+        };
+        if self.strict {
+            if !std::thread::panicking() {
+                panic!(
+                    "The `{field}` field of the `{}` span ({callsite}) was never recorded",
+                    span.name()
+                );
+            }
+            return;
+        }
+
+        let metadata = DIAGNOSTIC_CALLSITE.metadata();
+        if DIAGNOSTIC_CALLSITE.interest().is_never() || !ctx.enabled(metadata) {
+            return;
+        }
+        let fields = metadata.fields();
+        let message = "declared field was never recorded";
+        let span_name = span.name();
+        let values = [
+            (
+                &fields.field("message").unwrap(),
+                Some(&message as &dyn Value),
+            ),
+            (
+                &fields.field("span").unwrap(),
+                Some(&span_name as &dyn Value),
+            ),
+            (&fields.field("field").unwrap(), Some(&field as &dyn Value)),
+            (
+                &fields.field("callsite").unwrap(),
+                Some(&callsite.as_str() as &dyn Value),
+            ),
+        ];
+        let values = fields.value_set(&values);
+        // The span is closing: the diagnostic shouldn't be attached to whatever span happens to
+        // be current at this point.
+        ctx.event(&Event::new_child_of(None, metadata, &values));
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for FieldCompletenessLayer
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let mut unrecorded = Unrecorded(
+            attrs
+                .metadata()
+                .fields()
+                .iter()
+                .map(|f| f.name())
+                .filter(|name| !self.is_allowed(name))
+                .collect(),
+        );
+        attrs.record(&mut unrecorded);
+        span.extensions_mut().insert(unrecorded);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let mut extensions = span.extensions_mut();
+        if let Some(unrecorded) = extensions.get_mut::<Unrecorded>() {
+            values.record(unrecorded);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = ctx.span(&id).unwrap();
+        let Some(Unrecorded(unrecorded)) = span.extensions_mut().remove::<Unrecorded>() else {
+            return;
+        };
+        // Report the fields in the order they were declared in.
+        for field in span.metadata().fields().iter() {
+            if unrecorded.contains(field.name()) {
+                self.report(span.metadata(), field.name(), &ctx);
+            }
+        }
+    }
+}
+
+static DIAGNOSTIC_CALLSITE: DefaultCallsite = DefaultCallsite::new(&DIAGNOSTIC_METADATA);
+static DIAGNOSTIC_METADATA: Metadata<'static> = Metadata::new(
+    "declared field was never recorded",
+    DIAGNOSTIC_TARGET,
+    Level::WARN,
+    Some(file!()),
+    Some(line!()),
+    Some(module_path!()),
+    FieldSet::new(
+        &["message", "span", "field", "callsite"],
+        Identifier(&DIAGNOSTIC_CALLSITE),
+    ),
+    Kind::EVENT,
+);
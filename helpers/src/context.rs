@@ -0,0 +1,338 @@
+//! Attach request-wide fields (e.g. `request_id`) to everything that happens within a request.
+//!
+//! Formatters don't agree on how to show the fields of the spans an event was emitted in: the
+//! compact formatter prints them next to the event, the JSON formatter nests them under
+//! `span`/`spans`, OTLP exporters don't attach them to events at all.
+//! [`ContextLayer`] wraps the layer writing to your pipeline and copies the fields you marked
+//! as inherited into every event and span emitted within the span that set them, as if they
+//! had been passed explicitly:
+//!
+//! ```rust
+//! use helpers::context::ContextLayer;
+//! use tracing_subscriber::layer::SubscriberExt;
+//!
+//! let layer = ContextLayer::new(tracing_subscriber::fmt::layer().json())
+//!     .inherit("request_id")
+//!     .inherit("customer_id");
+//! let subscriber = tracing_subscriber::registry().with(layer);
+//! ```
+//!
+//! Events coming from the `log` crate through `log_bridge::TracingLogger` get them too.
+use crate::event_fields::{with_value_set, FieldValue, RecordedEvent};
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use tracing::callsite::{Callsite, Identifier};
+use tracing::field::FieldSet;
+use tracing::metadata::{Kind, LevelFilter};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Dispatch, Event, Metadata, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::{LookupSpan, Scope};
+use tracing_subscriber::Layer;
+
+/// `tracing` doesn't allow more fields than this on a single callsite.
+const MAX_FIELDS: usize = 32;
+
+/// A layer that forwards everything to the layer it wraps, adding the inherited fields of the
+/// enclosing spans to each event and span.
+///
+/// When several enclosing spans set the same inherited field, the innermost value wins.
+/// Values set explicitly on an event or a child span take precedence over inherited ones.
+///
+/// Spans only get the inherited values known when they are created, while events get the
+/// latest ones: record inherited fields on the root span before creating its children.
+pub struct ContextLayer<L> {
+    inner: L,
+    inherited: Vec<&'static str>,
+    /// `tracing` needs a `'static` callsite for each event and span: we create (and leak) one
+    /// for each callsite that gets inherited fields, and each set of inherited fields.
+    callsites: Mutex<HashMap<CallsiteKey, &'static ContextCallsite>>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct CallsiteKey {
+    original: Identifier,
+    inherited: Vec<&'static str>,
+}
+
+/// The values of the inherited fields set by a span, stored in its extensions.
+#[derive(Default)]
+struct OwnContext(Vec<(&'static str, FieldValue)>);
+
+impl OwnContext {
+    fn set(&mut self, name: &'static str, value: FieldValue) {
+        match self.0.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => self.0.push((name, value)),
+        }
+    }
+}
+
+impl<L> ContextLayer<L> {
+    pub fn new(inner: L) -> Self {
+        Self {
+            inner,
+            inherited: Vec::new(),
+            callsites: Mutex::default(),
+        }
+    }
+
+    /// Copy the value of `field`, when a span sets it, to all the events and spans emitted
+    /// within that span.
+    pub fn inherit(mut self, field: &'static str) -> Self {
+        if !self.inherited.contains(&field) {
+            self.inherited.push(field);
+        }
+        self
+    }
+
+    /// Store the inherited fields found in `recorded`, the values of span `id`.
+    fn store<S>(&self, id: &Id, recorded: &RecordedEvent, ctx: &Context<'_, S>)
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let fields = recorded.metadata.fields().iter();
+        let values: Vec<_> = fields
+            .zip(&recorded.values)
+            .filter_map(|(field, value)| {
+                let name = self.inherited.iter().find(|n| **n == field.name())?;
+                Some((*name, value.clone()?))
+            })
+            .collect();
+        let mut extensions = span.extensions_mut();
+        if extensions.get_mut::<OwnContext>().is_none() {
+            if values.is_empty() {
+                return;
+            }
+            extensions.insert(OwnContext::default());
+        }
+        let own = extensions.get_mut::<OwnContext>().unwrap();
+        for (name, value) in values {
+            own.set(name, value);
+        }
+    }
+
+    /// The inherited values set by the spans of `scope`, minus the fields of `metadata`.
+    fn collect<S>(
+        &self,
+        scope: Option<Scope<'_, S>>,
+        metadata: &Metadata<'_>,
+    ) -> Vec<(&'static str, FieldValue)>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let mut context = OwnContext::default();
+        for span in scope.into_iter().flat_map(Scope::from_root) {
+            if let Some(own) = span.extensions().get::<OwnContext>() {
+                for (name, value) in &own.0 {
+                    context.set(name, value.clone());
+                }
+            }
+        }
+        context
+            .0
+            .retain(|(name, _)| metadata.fields().field(name).is_none());
+        // Keep the order stable, so that we reuse the same callsites.
+        context
+            .0
+            .sort_by_key(|(name, _)| self.inherited.iter().position(|n| n == name));
+        context.0
+    }
+
+    /// The callsite of `original`, with `inherited` appended to its fields.
+    fn callsite(
+        &self,
+        original: &'static Metadata<'static>,
+        inherited: Vec<&'static str>,
+    ) -> &'static Metadata<'static> {
+        let key = CallsiteKey {
+            original: original.callsite(),
+            inherited,
+        };
+        let mut callsites = self.callsites.lock().unwrap();
+        if let Some(callsite) = callsites.get(&key) {
+            return callsite.metadata.get().unwrap();
+        }
+
+        let field_names: Vec<&'static str> = original
+            .fields()
+            .iter()
+            .map(|field| field.name())
+            .chain(key.inherited.iter().copied())
+            .collect();
+        let callsite: &'static ContextCallsite = Box::leak(Box::new(ContextCallsite {
+            metadata: OnceLock::new(),
+        }));
+        let metadata = Metadata::new(
+            original.name(),
+            original.target(),
+            *original.level(),
+            original.file(),
+            original.line(),
+            original.module_path(),
+            FieldSet::new(field_names.leak(), Identifier(callsite)),
+            if original.is_span() {
+                Kind::SPAN
+            } else {
+                Kind::EVENT
+            },
+        );
+        let _ = callsite.metadata.set(metadata);
+        tracing::callsite::register(callsite);
+        callsites.insert(key, callsite);
+        callsite.metadata.get().unwrap()
+    }
+
+    /// Append the `inherited` values to `recorded`, and pass the result to `f` along with the
+    /// metadata of the extended callsite.
+    ///
+    /// Returns `None`, without calling `f`, if there are too many fields to fit in a callsite.
+    fn extend<R>(
+        &self,
+        mut recorded: RecordedEvent,
+        inherited: Vec<(&'static str, FieldValue)>,
+        f: impl FnOnce(&'static Metadata<'static>, &tracing::field::ValueSet<'_>) -> R,
+    ) -> Option<R> {
+        if recorded.values.len() + inherited.len() > MAX_FIELDS {
+            return None;
+        }
+        let (names, values): (Vec<_>, Vec<_>) = inherited.into_iter().unzip();
+        let metadata = self.callsite(recorded.metadata, names);
+        recorded.values.extend(values.into_iter().map(Some));
+        Some(with_value_set(
+            metadata.fields(),
+            &recorded.values,
+            |values| f(metadata, values),
+        ))
+    }
+}
+
+impl<S, L> Layer<S> for ContextLayer<L>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    L: Layer<S>,
+{
+    fn on_register_dispatch(&self, subscriber: &Dispatch) {
+        self.inner.on_register_dispatch(subscriber);
+    }
+
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.inner.on_layer(subscriber);
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.enabled(metadata, ctx)
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let recorded = RecordedEvent::from_attributes(attrs);
+        let parent = ctx.span(id).and_then(|span| span.parent());
+        let inherited = self.collect(parent.map(|p| p.scope()), attrs.metadata());
+        self.store(id, &recorded, &ctx);
+        if inherited.is_empty() {
+            return self.inner.on_new_span(attrs, id, ctx);
+        }
+        let forwarded = self.extend(recorded, inherited, |metadata, values| {
+            let attrs = if let Some(parent) = attrs.parent() {
+                Attributes::child_of(parent.clone(), metadata, values)
+            } else if attrs.is_root() {
+                Attributes::new_root(metadata, values)
+            } else {
+                Attributes::new(metadata, values)
+            };
+            self.inner.on_new_span(&attrs, id, ctx.clone());
+        });
+        if forwarded.is_none() {
+            self.inner.on_new_span(attrs, id, ctx);
+        }
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.inner.max_level_hint()
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            self.store(
+                id,
+                &RecordedEvent::from_record(span.metadata(), values),
+                &ctx,
+            );
+        }
+        self.inner.on_record(id, values, ctx);
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.inner.on_follows_from(span, follows, ctx);
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.event_enabled(event, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let inherited = self.collect(ctx.event_scope(event), event.metadata());
+        if inherited.is_empty() {
+            return self.inner.on_event(event, ctx);
+        }
+        let forwarded = self.extend(RecordedEvent::new(event), inherited, |metadata, values| {
+            let event = if let Some(parent) = event.parent() {
+                Event::new_child_of(parent.clone(), metadata, values)
+            } else if event.is_root() {
+                Event::new_child_of(None, metadata, values)
+            } else {
+                Event::new(metadata, values)
+            };
+            self.inner.on_event(&event, ctx.clone());
+        });
+        if forwarded.is_none() {
+            self.inner.on_event(event, ctx);
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_enter(id, ctx);
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_exit(id, ctx);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        self.inner.on_close(id, ctx);
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.inner.on_id_change(old, new, ctx);
+    }
+
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const _ as *const ())
+        } else {
+            self.inner.downcast_raw(id)
+        }
+    }
+}
+
+/// A callsite created at runtime, to add inherited fields to an existing callsite.
+struct ContextCallsite {
+    metadata: OnceLock<Metadata<'static>>,
+}
+
+impl Callsite for ContextCallsite {
+    fn set_interest(&self, _interest: Interest) {}
+
+    fn metadata(&self) -> &Metadata<'_> {
+        self.metadata.get().unwrap()
+    }
+}
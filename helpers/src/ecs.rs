@@ -0,0 +1,115 @@
+//! Format `tracing` events as JSON documents following the Elastic Common Schema (ECS).
+//!
+//! [`EcsFormat`] emits one document per line, using the dotted top-level keys of the
+//! [ECS logging specification](https://github.com/elastic/ecs-logging/blob/main/spec/spec.json):
+//!
+//! ```json
+//! {
+//!   "@timestamp": "2024-08-21T09:03:12.512Z",
+//!   "log.level": "error",
+//!   "message": "close",
+//!   "ecs.version": "8.11.0",
+//!   "log.logger": "orders",
+//!   "event.duration": 1230000,
+//!   "error.message": "The service is temporarily unavailable, please try again later",
+//!   "error.stack_trace": "Failed to execute: `SELECT * FROM table`\n...",
+//!   "order_number": 4
+//! }
+//! ```
+//!
+//! The fields of the spans the event was emitted in are included, next to the event fields.
+//! Our error conventions are mapped onto ECS's own error fields: `error.msg` becomes
+//! `error.message` and `error.source_chain` becomes `error.stack_trace`.
+//! `event.duration`, in nanoseconds, is set on the events emitted when a span closes (see
+//! `FmtSpan::CLOSE`), as long as [`SpanStartLayer`](crate::event_fields::SpanStartLayer) is
+//! installed too.
+use crate::event_fields::{rfc3339, EventFields};
+use serde_json::{Map, Value};
+use std::time::SystemTime;
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// The version of the schema our documents conform to.
+pub const ECS_VERSION: &str = "8.11.0";
+
+/// A [`FormatEvent`] implementation emitting ECS documents.
+///
+/// Span fields can only be included if they are formatted as JSON: use it with
+/// `tracing_subscriber::fmt::layer().fmt_fields(JsonFields::new()).event_format(EcsFormat::new())`.
+#[derive(Default)]
+pub struct EcsFormat {
+    service_name: Option<String>,
+}
+
+impl EcsFormat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `service.name` on every document.
+    pub fn with_service_name(mut self, name: impl Into<String>) -> Self {
+        self.service_name = Some(name.into());
+        self
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for EcsFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let metadata = event.metadata();
+        let EventFields {
+            message,
+            mut fields,
+            duration,
+            otel_ids,
+        } = EventFields::new(ctx, event);
+
+        let mut document = Map::new();
+        document.insert("@timestamp".into(), rfc3339(SystemTime::now()).into());
+        document.insert(
+            "log.level".into(),
+            metadata.level().as_str().to_lowercase().into(),
+        );
+        document.insert("message".into(), message.unwrap_or_default().into());
+        document.insert("ecs.version".into(), ECS_VERSION.into());
+        document.insert("log.logger".into(), metadata.target().into());
+        if let Some(file) = metadata.file() {
+            document.insert("log.origin.file.name".into(), file.into());
+        }
+        if let Some(line) = metadata.line() {
+            document.insert("log.origin.file.line".into(), line.into());
+        }
+        if let Some(service_name) = &self.service_name {
+            document.insert("service.name".into(), service_name.as_str().into());
+        }
+        if let Some(duration) = duration {
+            document.insert("event.duration".into(), (duration.as_nanos() as u64).into());
+        }
+        if let Some(error_message) = fields.remove("error.msg") {
+            document.insert("error.message".into(), error_message);
+        }
+        if let Some(source_chain) = fields.remove("error.source_chain") {
+            document.insert("error.stack_trace".into(), source_chain);
+        }
+        if let Some((trace_id, span_id)) = otel_ids {
+            document.insert("trace.id".into(), trace_id.to_string().into());
+            document.insert("span.id".into(), span_id.to_string().into());
+        }
+        // ECS fields take precedence over custom fields with the same name.
+        for (key, value) in fields {
+            document.entry(key).or_insert(value);
+        }
+
+        writeln!(writer, "{}", Value::from(document))
+    }
+}
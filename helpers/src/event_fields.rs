@@ -0,0 +1,327 @@
+//! The raw material shared by our JSON event formatters, the field visitor shared by all our
+//! layers (and by the exercises'), and owned copies of field values, for layers that need to
+//! re-emit spans or events later on (or with different values).
+use opentelemetry::trace::{SpanId, TraceContextExt, TraceId};
+use serde_json::{Map, Value};
+use std::fmt::{Debug, Display, Formatter};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::field::{display, DisplayValue, Field, FieldSet, ValueSet, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::fmt::{FmtContext, FormatFields, FormattedFields};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+use tracing_subscriber::Layer;
+
+/// Everything we know about an event, as JSON values.
+pub(crate) struct EventFields {
+    pub(crate) message: Option<String>,
+    /// The fields of the event, on top of the fields of the spans it was emitted in.
+    /// When the same field is set at several levels, the innermost value wins.
+    pub(crate) fields: Map<String, Value>,
+    /// How long the span was open for, if the event was emitted by the `fmt` layer because the
+    /// span closed (see `FmtSpan::CLOSE`) and [`SpanStartLayer`] is installed.
+    pub(crate) duration: Option<Duration>,
+    /// The ids of the innermost span, if the `tracing-opentelemetry` layer is installed.
+    pub(crate) otel_ids: Option<(TraceId, SpanId)>,
+}
+
+impl EventFields {
+    /// Span fields can only be recovered if they were formatted as JSON, i.e. if the `fmt` layer
+    /// was built with `.fmt_fields(JsonFields::new())`.
+    pub(crate) fn new<S, N>(ctx: &FmtContext<'_, S, N>, event: &Event<'_>) -> Self
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+        N: for<'a> FormatFields<'a> + 'static,
+    {
+        let mut fields = Map::new();
+        let mut otel_ids = None;
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                otel_ids = self::otel_ids(&span);
+                let extensions = span.extensions();
+                let Some(formatted) = extensions.get::<FormattedFields<N>>() else {
+                    continue;
+                };
+                if let Ok(Value::Object(span_fields)) = serde_json::from_str(&formatted.fields) {
+                    fields.extend(span_fields);
+                }
+            }
+        }
+
+        let mut event_fields = Map::new();
+        event.record(&mut json_visitor(&mut event_fields));
+        let message = take_message(&mut event_fields);
+        fields.extend(event_fields);
+        // The `fmt` layer only adds these to the events it emits when a span closes. They are
+        // rounded to three significant digits: we compute the exact duration instead.
+        let (busy, idle) = (fields.remove("time.busy"), fields.remove("time.idle"));
+        let closing = busy.is_some() && idle.is_some();
+        let duration = ctx
+            .parent_span()
+            .filter(|_| closing)
+            .and_then(|span| Some(span.extensions().get::<SpanStart>()?.0.elapsed()));
+        Self {
+            message,
+            fields,
+            duration,
+            otel_ids,
+        }
+    }
+}
+
+/// The ids assigned to `span` by the `tracing-opentelemetry` layer, if it's installed.
+pub(crate) fn otel_ids<S>(span: &SpanRef<'_, S>) -> Option<(TraceId, SpanId)>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let extensions = span.extensions();
+    let data = extensions.get::<OtelData>()?;
+    let span_id = data.builder.span_id?;
+    // Root spans get a fresh trace id, the others inherit the one of their parent.
+    let trace_id = if data.parent_cx.has_active_span() {
+        data.parent_cx.span().span_context().trace_id()
+    } else {
+        data.builder.trace_id?
+    };
+    Some((trace_id, span_id))
+}
+
+/// When a span was created, stored in its extensions by [`SpanStartLayer`].
+struct SpanStart(Instant);
+
+/// Records when each span is created, for the formatters to report how long it was open for
+/// when it closes (e.g. `event.duration` in [`EcsFormat`](crate::ecs::EcsFormat)).
+pub struct SpanStartLayer;
+
+impl<S> Layer<S> for SpanStartLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanStart(Instant::now()));
+        }
+    }
+}
+
+/// Render `time` as an RFC 3339 UTC timestamp, with millisecond precision
+/// (e.g. `2024-08-21T09:03:12.512Z`).
+pub(crate) fn rfc3339(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+    // Howard Hinnant's `civil_from_days` algorithm.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3_600,
+        secs_of_day % 3_600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// A recorded field value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    Bool(bool),
+    I64(i64),
+    U64(u64),
+    I128(i128),
+    U128(u128),
+    F64(f64),
+    Str(String),
+    /// Anything recorded through its `Debug` (or `Display`) representation.
+    Debug(String),
+}
+
+impl Display for FieldValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FieldValue::Bool(v) => write!(f, "{v}"),
+            FieldValue::I64(v) => write!(f, "{v}"),
+            FieldValue::U64(v) => write!(f, "{v}"),
+            FieldValue::I128(v) => write!(f, "{v}"),
+            FieldValue::U128(v) => write!(f, "{v}"),
+            FieldValue::F64(v) => write!(f, "{v}"),
+            FieldValue::Str(v) | FieldValue::Debug(v) => write!(f, "{v}"),
+        }
+    }
+}
+
+/// Hands each recorded field to a closure, along with an owned copy of its value.
+///
+/// Layers that don't need a visitor of their own (e.g. to look at values before they are
+/// formatted) use this one, and turn the values into whatever they need (e.g. JSON, with
+/// [`json_visitor`]).
+pub struct FieldVisitor<F>(pub F);
+
+impl<F: FnMut(&Field, FieldValue)> Visit for FieldVisitor<F> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        (self.0)(field, FieldValue::F64(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        (self.0)(field, FieldValue::I64(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        (self.0)(field, FieldValue::U64(value));
+    }
+
+    fn record_i128(&mut self, field: &Field, value: i128) {
+        (self.0)(field, FieldValue::I128(value));
+    }
+
+    fn record_u128(&mut self, field: &Field, value: u128) {
+        (self.0)(field, FieldValue::U128(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        (self.0)(field, FieldValue::Bool(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        (self.0)(field, FieldValue::Str(value.to_owned()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        (self.0)(field, FieldValue::Debug(format!("{value:?}")));
+    }
+}
+
+/// Record fields into `fields`, as JSON values: values recorded through their `Debug`
+/// representation become strings.
+pub(crate) fn json_visitor(
+    fields: &mut Map<String, Value>,
+) -> FieldVisitor<impl FnMut(&Field, FieldValue) + '_> {
+    FieldVisitor(move |field: &Field, value: FieldValue| {
+        fields.insert(field.name().into(), value.into());
+    })
+}
+
+/// Remove the `message` field from `fields`, if it's a string.
+pub(crate) fn take_message(fields: &mut Map<String, Value>) -> Option<String> {
+    match fields.remove("message")? {
+        Value::String(message) => Some(message),
+        other => {
+            fields.insert("message".into(), other);
+            None
+        }
+    }
+}
+
+impl From<FieldValue> for Value {
+    fn from(value: FieldValue) -> Self {
+        match value {
+            FieldValue::Bool(v) => v.into(),
+            FieldValue::I64(v) => v.into(),
+            FieldValue::U64(v) => v.into(),
+            // JSON numbers are 64 bits wide at most: larger values are kept as strings.
+            FieldValue::I128(v) => {
+                i64::try_from(v).map_or_else(|_| v.to_string().into(), Value::from)
+            }
+            FieldValue::U128(v) => {
+                u64::try_from(v).map_or_else(|_| v.to_string().into(), Value::from)
+            }
+            FieldValue::F64(v) => v.into(),
+            FieldValue::Str(v) | FieldValue::Debug(v) => v.into(),
+        }
+    }
+}
+
+/// All the values of an event (or of a span, when it was created), in the same order as the
+/// fields of its callsite.
+pub(crate) struct RecordedEvent {
+    pub(crate) metadata: &'static Metadata<'static>,
+    pub(crate) values: Vec<Option<FieldValue>>,
+}
+
+impl RecordedEvent {
+    pub(crate) fn new(event: &Event<'_>) -> Self {
+        Self::record(event.metadata(), |visitor| event.record(visitor))
+    }
+
+    pub(crate) fn from_attributes(attrs: &Attributes<'_>) -> Self {
+        Self::record(attrs.metadata(), |visitor| attrs.record(visitor))
+    }
+
+    /// The values recorded for a span after it was created: the others are `None`.
+    pub(crate) fn from_record(metadata: &'static Metadata<'static>, values: &Record<'_>) -> Self {
+        Self::record(metadata, |visitor| values.record(visitor))
+    }
+
+    fn record(metadata: &'static Metadata<'static>, record: impl FnOnce(&mut dyn Visit)) -> Self {
+        let fields: Vec<Field> = metadata.fields().iter().collect();
+        let mut values = vec![None; fields.len()];
+        record(&mut FieldVisitor(|field: &Field, value| {
+            if let Some(i) = fields.iter().position(|f| f == field) {
+                values[i] = Some(value);
+            }
+        }));
+        Self { metadata, values }
+    }
+}
+
+/// Build a `ValueSet` out of recorded values and pass it to `f`.
+///
+/// `values` must be in the same order as `fields`, with `None` for the missing ones.
+pub(crate) fn with_value_set<R>(
+    fields: &FieldSet,
+    values: &[Option<FieldValue>],
+    f: impl FnOnce(&ValueSet<'_>) -> R,
+) -> R {
+    let fields_iter: Vec<Field> = fields.iter().collect();
+    // `tracing::Value` is sealed: values recorded through their `Debug` representation are
+    // re-emitted through `Display`, so that they don't get quoted.
+    let debug: Vec<Option<DisplayValue<&str>>> = values
+        .iter()
+        .map(|value| match value {
+            Some(FieldValue::Debug(v)) => Some(display(v.as_str())),
+            _ => None,
+        })
+        .collect();
+    let pairs: Vec<(&Field, Option<&dyn tracing::Value>)> = fields_iter
+        .iter()
+        .zip(values.iter().zip(&debug))
+        .map(|(field, (value, debug))| {
+            let value: Option<&dyn tracing::Value> = match (value, debug) {
+                (_, Some(debug)) => Some(debug),
+                (Some(FieldValue::Bool(v)), _) => Some(v),
+                (Some(FieldValue::I64(v)), _) => Some(v),
+                (Some(FieldValue::U64(v)), _) => Some(v),
+                (Some(FieldValue::I128(v)), _) => Some(v),
+                (Some(FieldValue::U128(v)), _) => Some(v),
+                (Some(FieldValue::F64(v)), _) => Some(v),
+                (Some(FieldValue::Str(v)), _) => Some(v),
+                (Some(FieldValue::Debug(_)) | None, None) => None,
+            };
+            (field, value)
+        })
+        .collect();
+    // `FieldSet::value_set` only accepts arrays, whose length must be known at compile time.
+    // `tracing`'s macros don't allow more than 32 fields per callsite, so we can enumerate
+    // all possible lengths.
+    macro_rules! value_set {
+        ($($len:literal)*) => {
+            match pairs.len() {
+                $($len => {
+                    let pairs: [_; $len] = pairs.try_into().unwrap_or_else(|_| unreachable!());
+                    f(&fields.value_set(&pairs))
+                })*
+                len => panic!("A callsite can't have more than 32 fields, found {len}"),
+            }
+        };
+    }
+    value_set!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31 32)
+}
@@ -0,0 +1,219 @@
+//! Build a flamegraph out of span busy time, no external profiler required.
+//!
+//! [`FlameLayer`] aggregates, in memory, how much time was spent *inside* each span, keyed by
+//! the names of its ancestors: `process total price;retrieve order 1532000`.
+//! That's the "collapsed" (or "folded") stack format understood by
+//! [`inferno`](https://github.com/jonhoo/inferno) and by the original `flamegraph.pl` script.
+//!
+//! Only time spent with the span entered counts (its *busy* time), and each line gets the
+//! *self* time of the span: the busy time of its children is attributed to the children's own
+//! lines, so that the flamegraph doesn't count it twice.
+//!
+//! Timings change from one run to the next. If you need the exact same output for the same
+//! input (e.g. to diff two runs, or in a test), weigh stacks by the number of times each span
+//! was entered instead: see [`FlameLayer::with_weight`].
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::thread::ThreadId;
+use std::time::{Duration, Instant};
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+/// A `tracing` layer that accumulates the busy time of spans, grouped by stack.
+///
+/// Weights are expressed in nanoseconds, unless configured otherwise with
+/// [`FlameLayer::with_weight`].
+/// Lines are sorted by stack, so two runs going through the same spans produce the same lines
+/// in the same order.
+pub struct FlameLayer {
+    stacks: Stacks,
+    weight: Weight,
+}
+
+/// What the weight of each stack measures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Weight {
+    /// The self time of the span, in nanoseconds.
+    #[default]
+    BusyTime,
+    /// How many times the span was entered: once per call for a synchronous function, once
+    /// per poll for a future.
+    ///
+    /// It doesn't depend on timings, so the same input always produces the same output.
+    Enters,
+}
+
+/// Writes the aggregated stacks when dropped.
+///
+/// It can also be used to take a snapshot of the stacks collected so far.
+#[must_use = "The stacks are written out when the guard is dropped"]
+pub struct FlameGuard {
+    stacks: Stacks,
+    writer: Option<Box<dyn Write + Send>>,
+}
+
+/// Stack -> total self time, in nanoseconds.
+type Stacks = Arc<Mutex<BTreeMap<String, u64>>>;
+
+/// Timing data, stored in the extensions of each span.
+struct Timings {
+    /// The stack of the span, from the root, separated by `;`.
+    stack: String,
+    /// Time spent with the span entered.
+    busy: Duration,
+    /// Time spent with any of the children of the span entered.
+    children_busy: Duration,
+    /// How many times the span was entered.
+    enters: u64,
+    /// When the span was entered, for each time it is currently entered.
+    ///
+    /// A span can be entered on several threads at once, or more than once on the same thread.
+    entered: Vec<(ThreadId, Instant)>,
+}
+
+impl FlameLayer {
+    /// Create a new layer that will write the folded stacks to `writer` once the returned guard
+    /// is dropped.
+    pub fn new(writer: impl Write + Send + 'static) -> (Self, FlameGuard) {
+        let stacks = Stacks::default();
+        let layer = Self {
+            stacks: stacks.clone(),
+            weight: Weight::default(),
+        };
+        let guard = FlameGuard {
+            stacks,
+            writer: Some(Box::new(writer)),
+        };
+        (layer, guard)
+    }
+
+    /// Choose what the weight of each stack measures (busy time, by default).
+    pub fn with_weight(mut self, weight: Weight) -> Self {
+        self.weight = weight;
+        self
+    }
+}
+
+impl FlameGuard {
+    /// The folded stacks aggregated so far, one per line.
+    ///
+    /// Only spans that have been closed are accounted for.
+    pub fn folded(&self) -> String {
+        let stacks = self.stacks.lock().unwrap();
+        let mut folded = String::new();
+        for (stack, nanos) in stacks.iter() {
+            folded.push_str(&format!("{stack} {nanos}\n"));
+        }
+        folded
+    }
+
+    /// Write the folded stacks aggregated so far to `writer`, without waiting for shutdown.
+    pub fn write_to(&self, mut writer: impl Write) -> std::io::Result<()> {
+        writer.write_all(self.folded().as_bytes())?;
+        writer.flush()
+    }
+
+    /// Write the folded stacks aggregated so far to the writer passed to [`FlameLayer::new`].
+    pub fn finish(mut self) -> std::io::Result<()> {
+        self.write()
+    }
+
+    fn write(&mut self) -> std::io::Result<()> {
+        let Some(writer) = self.writer.take() else {
+            return Ok(());
+        };
+        self.write_to(writer)
+    }
+}
+
+impl Drop for FlameGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.write() {
+            eprintln!("Failed to write the folded stacks: {e}");
+        }
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for FlameLayer
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let frame = frame(span.name());
+        let stack = match span.parent() {
+            Some(parent) => match parent.extensions().get::<Timings>() {
+                Some(timings) => format!("{};{frame}", timings.stack),
+                None => frame,
+            },
+            None => frame,
+        };
+        span.extensions_mut().insert(Timings {
+            stack,
+            busy: Duration::ZERO,
+            children_busy: Duration::ZERO,
+            enters: 0,
+            entered: Vec::new(),
+        });
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let mut extensions = span.extensions_mut();
+        if let Some(timings) = extensions.get_mut::<Timings>() {
+            timings.enters += 1;
+            timings
+                .entered
+                .push((std::thread::current().id(), Instant::now()));
+        }
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let mut extensions = span.extensions_mut();
+        if let Some(timings) = extensions.get_mut::<Timings>() {
+            // Exits match the latest enter on the same thread.
+            let thread = std::thread::current().id();
+            if let Some(i) = timings.entered.iter().rposition(|(t, _)| *t == thread) {
+                let (_, entered) = timings.entered.remove(i);
+                timings.busy += entered.elapsed();
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = ctx.span(&id).unwrap();
+        let Some(timings) = span.extensions_mut().remove::<Timings>() else {
+            return;
+        };
+        if let Some(parent) = span.parent() {
+            if let Some(parent_timings) = parent.extensions_mut().get_mut::<Timings>() {
+                parent_timings.children_busy += timings.busy;
+            }
+        }
+        let weight = match self.weight {
+            // Concurrent children (e.g. futures joined together) can be busy for longer than
+            // their parent.
+            Weight::BusyTime => timings
+                .busy
+                .saturating_sub(timings.children_busy)
+                .as_nanos() as u64,
+            Weight::Enters => timings.enters,
+        };
+        *self
+            .stacks
+            .lock()
+            .unwrap()
+            .entry(timings.stack)
+            .or_default() += weight;
+    }
+}
+
+/// `;` separates frames and each stack sits on its own line: neither can appear in a frame
+/// name. Spaces are fine, since the weight is whatever comes after the last one.
+fn frame(name: &str) -> String {
+    name.replace(';', ":").replace(['\n', '\r'], " ")
+}
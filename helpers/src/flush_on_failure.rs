@@ -0,0 +1,439 @@
+//! Keep verbose events only for the requests that failed.
+//!
+//! `DEBUG`-level events are invaluable when troubleshooting a failed request, and pure noise
+//! (and cost) for all the others.
+//! [`FlushOnFailureLayer`] wraps the layer writing to your logging pipeline and holds back
+//! what it writes for verbose events, buffering it per trace (i.e. per root span).
+//! When the root span closes, the buffer is either flushed to the writer, if the request
+//! failed, or discarded.
+//!
+//! Events are formatted when they are emitted, not when they are flushed: they keep their
+//! timestamp and the spans they were emitted in, even if those spans are long gone by the
+//! time the request turns out to have failed.
+use std::any::TypeId;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::io::{self, Write};
+use std::sync::Arc;
+use tracing::callsite::{Callsite, DefaultCallsite, Identifier};
+use tracing::field::{Field, FieldSet, Value, Visit};
+use tracing::metadata::{Kind, LevelFilter};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Dispatch, Event, Level, Metadata, Subscriber};
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// The target used for the event reporting how many buffered events were dropped.
+pub const SUMMARY_TARGET: &str = module_path!();
+
+thread_local! {
+    /// What the wrapped layer writes for the event being held back on this thread, if any.
+    static HELD_BACK: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+/// A layer that holds back verbose events until it knows whether the request they belong to
+/// failed.
+///
+/// A request failed if its root span:
+///
+/// - has its failure field (`outcome` by default) set to the failure value (`failure` by
+///   default) when it closes, or
+/// - saw an `ERROR` event.
+///
+/// Events that are not emitted within a span can't be attributed to a request: they are
+/// written straight away, as are the events at or above the threshold level.
+///
+/// The wrapped layer must write through the [`BufferedWriter`] it's given: whatever it
+/// writes elsewhere isn't held back.
+pub struct FlushOnFailureLayer<L, W> {
+    inner: L,
+    writer: Arc<W>,
+    threshold: Level,
+    failure_field: &'static str,
+    failure_value: String,
+    max_buffered_events: usize,
+}
+
+/// The events held back for a trace, stored in the extensions of its root span.
+#[derive(Default)]
+struct Buffer {
+    events: VecDeque<HeldBackEvent>,
+    dropped: u64,
+    /// Whether the failure field was last recorded with the failure value.
+    failure_recorded: bool,
+    saw_error: bool,
+}
+
+/// What the wrapped layer wrote for an event.
+struct HeldBackEvent {
+    metadata: &'static Metadata<'static>,
+    output: Vec<u8>,
+}
+
+impl<L, W> FlushOnFailureLayer<L, W>
+where
+    W: for<'a> MakeWriter<'a>,
+{
+    /// Buffer `DEBUG` and `TRACE` events, up to 1000 per trace, before writing them to
+    /// `make_writer`.
+    ///
+    /// `layer` builds the wrapped layer, which must write to the [`BufferedWriter`] it's
+    /// given, e.g. `|writer| tracing_subscriber::fmt::layer().with_writer(writer)`.
+    pub fn new(make_writer: W, layer: impl FnOnce(BufferedWriter<W>) -> L) -> Self {
+        let writer = Arc::new(make_writer);
+        Self {
+            inner: layer(BufferedWriter {
+                inner: writer.clone(),
+            }),
+            writer,
+            threshold: Level::INFO,
+            failure_field: "outcome",
+            failure_value: "failure".into(),
+            max_buffered_events: 1000,
+        }
+    }
+
+    /// Buffer events that are more verbose than `threshold`.
+    pub fn with_threshold(mut self, threshold: Level) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Consider a request failed if its root span has `field` set to `value` when it closes.
+    pub fn with_failure_field(mut self, field: &'static str, value: impl Into<String>) -> Self {
+        self.failure_field = field;
+        self.failure_value = value.into();
+        self
+    }
+
+    /// Buffer at most `max` events per trace.
+    ///
+    /// When the buffer is full, the oldest events are dropped to make room for the new ones.
+    /// If the buffer ends up being flushed, the number of dropped events is reported by a
+    /// `WARN` event preceding the buffered ones.
+    pub fn with_max_buffered_events(mut self, max: usize) -> Self {
+        self.max_buffered_events = max;
+        self
+    }
+
+    /// Remember whether the root span recorded the failure value, the last time it recorded
+    /// the failure field.
+    fn check_outcome<S>(&self, root: &Id, values: &Record<'_>, ctx: &Context<'_, S>)
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let mut visitor = FailureVisitor {
+            field: self.failure_field,
+            value: &self.failure_value,
+            failed: None,
+        };
+        values.record(&mut visitor);
+        if let Some(failed) = visitor.failed {
+            if let Some(span) = ctx.span(root) {
+                if let Some(buffer) = span.extensions_mut().get_mut::<Buffer>() {
+                    buffer.failure_recorded = failed;
+                }
+            }
+        }
+    }
+
+    /// The root span of the trace `event` belongs to, if it has a buffer.
+    fn buffered_root<S>(&self, event: &Event<'_>, ctx: &Context<'_, S>) -> Option<Id>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let root = ctx.event_scope(event)?.from_root().next()?;
+        let has_buffer = root.extensions().get::<Buffer>().is_some();
+        has_buffer.then(|| root.id())
+    }
+
+    /// Add what the wrapped layer wrote for an event to the buffer of its trace.
+    fn hold_back<S>(&self, root: &Id, event: HeldBackEvent, ctx: &Context<'_, S>)
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let Some(root) = ctx.span(root) else {
+            return;
+        };
+        let mut extensions = root.extensions_mut();
+        let Some(buffer) = extensions.get_mut::<Buffer>() else {
+            return;
+        };
+        if buffer.events.len() >= self.max_buffered_events {
+            buffer.events.pop_front();
+            buffer.dropped += 1;
+        }
+        if self.max_buffered_events > 0 {
+            buffer.events.push_back(event);
+        }
+    }
+
+    /// Let the wrapped layer know how many events were dropped for the trace rooted at `root`.
+    fn emit_summary<S>(&self, root: &Id, dropped: u64, ctx: Context<'_, S>)
+    where
+        S: Subscriber,
+        L: Layer<S>,
+    {
+        let metadata = SUMMARY_CALLSITE.metadata();
+        let fields = metadata.fields();
+        let message = format!("dropped {dropped} buffered events");
+        let values = [
+            (
+                &fields.field("message").unwrap(),
+                Some(&message.as_str() as &dyn Value),
+            ),
+            (
+                &fields.field("dropped").unwrap(),
+                Some(&dropped as &dyn Value),
+            ),
+        ];
+        let values = fields.value_set(&values);
+        self.inner
+            .on_event(&Event::new_child_of(root.clone(), metadata, &values), ctx);
+    }
+}
+
+impl<S, L, W> Layer<S> for FlushOnFailureLayer<L, W>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    L: Layer<S>,
+    W: for<'a> MakeWriter<'a> + 'static,
+{
+    fn on_register_dispatch(&self, subscriber: &Dispatch) {
+        self.inner.on_register_dispatch(subscriber);
+    }
+
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.inner.on_layer(subscriber);
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.enabled(metadata, ctx)
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if ctx.span(id).is_some_and(|span| span.parent().is_none()) {
+            ctx.span(id)
+                .unwrap()
+                .extensions_mut()
+                .insert(Buffer::default());
+            self.check_outcome(id, &Record::new(attrs.values()), &ctx);
+        }
+        self.inner.on_new_span(attrs, id, ctx);
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.inner.max_level_hint()
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if ctx.span(id).is_some_and(|span| span.parent().is_none()) {
+            self.check_outcome(id, values, &ctx);
+        }
+        self.inner.on_record(id, values, ctx);
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.inner.on_follows_from(span, follows, ctx);
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.event_enabled(event, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let Some(root) = self.buffered_root(event, &ctx) else {
+            self.inner.on_event(event, ctx);
+            return;
+        };
+        let level = *event.metadata().level();
+        if level == Level::ERROR {
+            if let Some(span) = ctx.span(&root) {
+                if let Some(buffer) = span.extensions_mut().get_mut::<Buffer>() {
+                    buffer.saw_error = true;
+                }
+            }
+        }
+        // More verbose levels compare as greater.
+        if level <= self.threshold {
+            self.inner.on_event(event, ctx);
+            return;
+        }
+        let output = held_back(|| self.inner.on_event(event, ctx.clone()));
+        if !output.is_empty() {
+            let event = HeldBackEvent {
+                metadata: event.metadata(),
+                output,
+            };
+            self.hold_back(&root, event, &ctx);
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_enter(id, ctx);
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_exit(id, ctx);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let buffer = ctx
+            .span(&id)
+            .and_then(|span| span.extensions_mut().remove::<Buffer>());
+        if let Some(buffer) = buffer.filter(|b| b.failure_recorded || b.saw_error) {
+            if buffer.dropped > 0 {
+                self.emit_summary(&id, buffer.dropped, ctx.clone());
+            }
+            for event in buffer.events {
+                // Telemetry shouldn't take the application down.
+                let _ = self
+                    .writer
+                    .make_writer_for(event.metadata)
+                    .write_all(&event.output);
+            }
+        }
+        self.inner.on_close(id, ctx);
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.inner.on_id_change(old, new, ctx);
+    }
+
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const _ as *const ())
+        } else {
+            self.inner.downcast_raw(id)
+        }
+    }
+}
+
+/// Run `write`, returning what it wrote to [`BufferedWriter`]s on this thread instead of
+/// letting it through.
+fn held_back(write: impl FnOnce()) -> Vec<u8> {
+    /// Lets the output through again, even if `write` panics.
+    struct Reset;
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            HELD_BACK.with(|held_back| held_back.borrow_mut().take());
+        }
+    }
+
+    HELD_BACK.with(|held_back| *held_back.borrow_mut() = Some(Vec::new()));
+    let reset = Reset;
+    write();
+    let output = HELD_BACK.with(|held_back| held_back.borrow_mut().take());
+    drop(reset);
+    output.unwrap_or_default()
+}
+
+/// The [`MakeWriter`] given to the layer wrapped by a [`FlushOnFailureLayer`].
+///
+/// It writes to the writer passed to [`FlushOnFailureLayer::new`], except for the events
+/// being held back.
+pub struct BufferedWriter<W> {
+    inner: Arc<W>,
+}
+
+impl<W> Clone for BufferedWriter<W> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// Writes to the underlying writer, or to the buffer of the event being held back.
+pub struct BufferedEventWriter<'a, W: MakeWriter<'a>> {
+    /// `None` if the event is held back.
+    inner: Option<W::Writer>,
+}
+
+impl<'a, W: MakeWriter<'a>> BufferedWriter<W> {
+    fn writer(&'a self, make: impl FnOnce(&'a W) -> W::Writer) -> BufferedEventWriter<'a, W> {
+        let held_back = HELD_BACK.with(|held_back| held_back.borrow().is_some());
+        BufferedEventWriter {
+            inner: (!held_back).then(|| make(&self.inner)),
+        }
+    }
+}
+
+impl<'a, W: MakeWriter<'a>> MakeWriter<'a> for BufferedWriter<W> {
+    type Writer = BufferedEventWriter<'a, W>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.writer(|inner| inner.make_writer())
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        self.writer(|inner| inner.make_writer_for(meta))
+    }
+}
+
+impl<'a, W: MakeWriter<'a>> Write for BufferedEventWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            Some(inner) => inner.write(buf),
+            None => {
+                HELD_BACK.with(|held_back| {
+                    if let Some(output) = held_back.borrow_mut().as_mut() {
+                        output.extend_from_slice(buf);
+                    }
+                });
+                Ok(buf.len())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.inner {
+            Some(inner) => inner.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Looks for the failure value in the failure field.
+struct FailureVisitor<'a> {
+    field: &'static str,
+    value: &'a str,
+    /// `None` if the failure field wasn't recorded.
+    failed: Option<bool>,
+}
+
+impl Visit for FailureVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == self.field {
+            self.failed = Some(value == self.value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == self.field {
+            let value = format!("{value:?}");
+            self.failed = Some(value == self.value || value.trim_matches('"') == self.value);
+        }
+    }
+}
+
+static SUMMARY_CALLSITE: DefaultCallsite = DefaultCallsite::new(&SUMMARY_METADATA);
+static SUMMARY_METADATA: Metadata<'static> = Metadata::new(
+    "dropped buffered events",
+    SUMMARY_TARGET,
+    Level::WARN,
+    Some(file!()),
+    Some(line!()),
+    Some(module_path!()),
+    FieldSet::new(&["message", "dropped"], Identifier(&SUMMARY_CALLSITE)),
+    Kind::EVENT,
+);
@@ -0,0 +1,290 @@
+//! Ship `tracing` events to Graylog, using the Graylog Extended Log Format (GELF).
+//!
+//! [`GelfFormat`] renders each event as a [GELF 1.1](https://go2docs.graylog.org/current/getting_in_log_data/gelf.html)
+//! message, and [`GelfSink`] sends them to a GELF input over UDP or TCP:
+//!
+//! ```rust,no_run
+//! use helpers::gelf::{GelfFormat, GelfSink};
+//! use tracing_subscriber::fmt::format::JsonFields;
+//!
+//! let sink = GelfSink::udp("127.0.0.1:12201").unwrap();
+//! tracing_subscriber::fmt()
+//!     .fmt_fields(JsonFields::new())
+//!     .event_format(GelfFormat::new("orders-api-1"))
+//!     .with_writer(sink)
+//!     .init();
+//! ```
+//!
+//! GELF has no dedicated fields for errors: `error.msg` becomes the `full_message`, followed
+//! by `error.source_chain` if it's set. Every other field (including the fields of the spans
+//! the event was emitted in) becomes an additional field, prefixed with `_`.
+//! The events emitted when a span closes (see `FmtSpan::CLOSE`) get a `_duration_ns` field, as
+//! long as [`SpanStartLayer`](crate::event_fields::SpanStartLayer) is installed too.
+use crate::event_fields::EventFields;
+use serde_json::{Map, Value};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::registry::LookupSpan;
+
+/// The largest UDP datagram we send: bigger messages are split into chunks.
+const MAX_DATAGRAM_SIZE: usize = 8192;
+/// The magic bytes, message id, sequence number and sequence count prefixing each chunk.
+const CHUNK_HEADER_SIZE: usize = 12;
+/// GELF inputs drop messages split into more chunks than this.
+const MAX_CHUNKS: usize = 128;
+
+/// A [`FormatEvent`] implementation emitting GELF messages, one per line.
+///
+/// Span fields can only be included if they are formatted as JSON: use it with
+/// `.fmt_fields(JsonFields::new())`.
+pub struct GelfFormat {
+    host: String,
+}
+
+impl GelfFormat {
+    /// `host` identifies the source of the messages (e.g. the hostname).
+    pub fn new(host: impl Into<String>) -> Self {
+        Self { host: host.into() }
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for GelfFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let metadata = event.metadata();
+        let EventFields {
+            message,
+            mut fields,
+            duration,
+            otel_ids,
+        } = EventFields::new(ctx, event);
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as f64
+            / 1000.;
+        let mut message_fields = Map::new();
+        message_fields.insert("version".into(), "1.1".into());
+        message_fields.insert("host".into(), self.host.as_str().into());
+        // `short_message` is mandatory, and it can't be empty.
+        let short_message = message
+            .filter(|m| !m.is_empty())
+            .unwrap_or_else(|| metadata.name().to_owned());
+        message_fields.insert("short_message".into(), short_message.into());
+        let full_message = [
+            fields.remove("error.msg"),
+            fields.remove("error.source_chain"),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|value| match value {
+            Value::String(s) => s,
+            value => value.to_string(),
+        })
+        .collect::<Vec<_>>();
+        if !full_message.is_empty() {
+            message_fields.insert("full_message".into(), full_message.join("\n").into());
+        }
+        message_fields.insert("timestamp".into(), timestamp.into());
+        message_fields.insert("level".into(), syslog_level(metadata.level()).into());
+
+        let mut additional = Map::new();
+        additional.insert("target".into(), metadata.target().into());
+        if let Some(file) = metadata.file() {
+            additional.insert("file".into(), file.into());
+        }
+        if let Some(line) = metadata.line() {
+            additional.insert("line".into(), line.into());
+        }
+        if let Some(duration) = duration {
+            additional.insert("duration_ns".into(), (duration.as_nanos() as u64).into());
+        }
+        if let Some((trace_id, span_id)) = otel_ids {
+            additional.insert("trace_id".into(), trace_id.to_string().into());
+            additional.insert("span_id".into(), span_id.to_string().into());
+        }
+        for (key, value) in fields {
+            additional.entry(key).or_insert(value);
+        }
+        for (key, value) in additional {
+            message_fields.insert(additional_field_name(&key), value);
+        }
+
+        writeln!(writer, "{}", Value::from(message_fields))
+    }
+}
+
+/// The syslog severity GELF expects in `level`.
+fn syslog_level(level: &Level) -> u8 {
+    match *level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        Level::DEBUG | Level::TRACE => 7,
+    }
+}
+
+/// Additional field names must match `^[\w.-]*$`, and `_id` is reserved.
+fn additional_field_name(key: &str) -> String {
+    let key: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if key == "id" {
+        "_id_".into()
+    } else {
+        format!("_{key}")
+    }
+}
+
+/// A [`MakeWriter`] sending each formatted event to a GELF input.
+///
+/// Over UDP, messages bigger than 8 KiB are chunked. Over TCP, messages are null-byte
+/// delimited, and the connection is re-established if it breaks.
+///
+/// Events are sent as soon as they are formatted, from the thread that emitted them.
+/// Failures to send are ignored: telemetry shouldn't take the application down.
+#[derive(Clone)]
+pub struct GelfSink {
+    transport: Arc<Transport>,
+}
+
+enum Transport {
+    Udp(UdpSocket),
+    Tcp {
+        addr: SocketAddr,
+        stream: Mutex<Option<TcpStream>>,
+    },
+}
+
+impl GelfSink {
+    /// Send messages as UDP datagrams to `addr`.
+    pub fn udp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect(addr)?;
+        Ok(Self {
+            transport: Arc::new(Transport::Udp(socket)),
+        })
+    }
+
+    /// Send messages over a TCP connection to `addr`.
+    pub fn tcp(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "No address to connect to")
+        })?;
+        let stream = TcpStream::connect(addr)?;
+        Ok(Self {
+            transport: Arc::new(Transport::Tcp {
+                addr,
+                stream: Mutex::new(Some(stream)),
+            }),
+        })
+    }
+
+    fn send(&self, message: &[u8]) -> io::Result<()> {
+        match &*self.transport {
+            Transport::Udp(socket) => send_udp(socket, message),
+            Transport::Tcp { addr, stream } => {
+                let mut stream = stream.lock().unwrap();
+                let frame = [message, b"\0"].concat();
+                if let Some(connection) = stream.as_mut() {
+                    if connection.write_all(&frame).is_ok() {
+                        return Ok(());
+                    }
+                }
+                // The connection broke: try again, once, with a new one.
+                *stream = None;
+                let mut connection = TcpStream::connect(addr)?;
+                connection.write_all(&frame)?;
+                *stream = Some(connection);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn send_udp(socket: &UdpSocket, message: &[u8]) -> io::Result<()> {
+    if message.len() <= MAX_DATAGRAM_SIZE {
+        socket.send(message)?;
+        return Ok(());
+    }
+    let chunks: Vec<_> = message
+        .chunks(MAX_DATAGRAM_SIZE - CHUNK_HEADER_SIZE)
+        .collect();
+    if chunks.len() > MAX_CHUNKS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The GELF message is too big to be sent over UDP",
+        ));
+    }
+    let message_id = RandomState::new().hash_one(message).to_be_bytes();
+    for (sequence_number, chunk) in chunks.iter().enumerate() {
+        let mut datagram = Vec::with_capacity(CHUNK_HEADER_SIZE + chunk.len());
+        datagram.extend_from_slice(&[0x1e, 0x0f]);
+        datagram.extend_from_slice(&message_id);
+        datagram.push(sequence_number as u8);
+        datagram.push(chunks.len() as u8);
+        datagram.extend_from_slice(chunk);
+        socket.send(&datagram)?;
+    }
+    Ok(())
+}
+
+/// Buffers a formatted event and sends it when dropped.
+pub struct GelfWriter<'a> {
+    sink: &'a GelfSink,
+    buffer: Vec<u8>,
+}
+
+impl Write for GelfWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for GelfWriter<'_> {
+    fn drop(&mut self) {
+        let message = self.buffer.trim_ascii_end();
+        if !message.is_empty() {
+            let _ = self.sink.send(message);
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for GelfSink {
+    type Writer = GelfWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        GelfWriter {
+            sink: self,
+            buffer: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Make sure telemetry is exported before the process (or the test) is over.
+//!
+//! OpenTelemetry providers buffer spans, metrics and log records to export them in batches:
+//! whatever is still buffered when the process exits is lost, silently.
+//! [`TelemetryGuard`] owns the providers (and the writers) and flushes them, then shuts them down:
+//!
+//! - explicitly, with [`TelemetryGuard::shutdown`], which returns a [`ShutdownReport`];
+//! - or when it's dropped, in which case failures are printed to stderr.
+//!
+//! Batch processors running on `opentelemetry_sdk::runtime::Tokio` export from a task on the
+//! runtime they were created on: flushing them blocks until that task has done its job. The
+//! guard takes care of not blocking the runtime while it waits, with one exception: when
+//! dropped on a current-thread runtime (e.g. at the end of a `#[tokio::test]`), there is no
+//! other thread to run the export task. Either call [`TelemetryGuard::shutdown`] there, or
+//! build the providers on a runtime of their own and give it to the guard with
+//! [`TelemetryGuard::with_runtime`].
+use opentelemetry_sdk::logs::LoggerProvider;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::trace::TracerProvider;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::runtime::{Handle, Runtime, RuntimeFlavor};
+
+/// Flushes and shuts down its providers and writers when dropped (or on
+/// [`shutdown`](TelemetryGuard::shutdown)).
+///
+/// Components are flushed in the order they were added, so add writers after the providers
+/// that might write to them.
+pub struct TelemetryGuard {
+    components: Vec<Component>,
+    deadline: Duration,
+    runtime: Option<Runtime>,
+}
+
+/// Something to flush, with the name it's reported under.
+struct Component {
+    name: String,
+    flush: Box<dyn FnOnce() -> Result<(), String> + Send>,
+}
+
+impl Default for TelemetryGuard {
+    fn default() -> Self {
+        Self {
+            components: Vec::new(),
+            deadline: Duration::from_secs(5),
+            runtime: None,
+        }
+    }
+}
+
+impl TelemetryGuard {
+    /// An empty guard, giving up after 5 seconds.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How long flushing everything may take, in total.
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// The runtime the providers were built on, kept alive until they are flushed, then shut
+    /// down.
+    ///
+    /// With a (multi-thread) runtime of their own, the providers can be flushed wherever the
+    /// guard is dropped, including on a current-thread runtime.
+    pub fn with_runtime(mut self, runtime: Runtime) -> Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    pub fn with_tracer_provider(self, provider: TracerProvider) -> Self {
+        self.with_component("tracer provider", move || {
+            let mut errors: Vec<_> = provider
+                .force_flush()
+                .into_iter()
+                .filter_map(|r| r.err().map(|e| e.to_string()))
+                .collect();
+            errors.extend(provider.shutdown().err().map(|e| e.to_string()));
+            join(errors)
+        })
+    }
+
+    pub fn with_meter_provider(self, provider: SdkMeterProvider) -> Self {
+        self.with_component("meter provider", move || {
+            let mut errors: Vec<_> = provider
+                .force_flush()
+                .err()
+                .map(|e| e.to_string())
+                .into_iter()
+                .collect();
+            errors.extend(provider.shutdown().err().map(|e| e.to_string()));
+            join(errors)
+        })
+    }
+
+    pub fn with_logger_provider(self, provider: LoggerProvider) -> Self {
+        self.with_component("logger provider", move || {
+            let mut errors: Vec<_> = provider
+                .force_flush()
+                .into_iter()
+                .filter_map(|r| r.err().map(|e| e.to_string()))
+                .collect();
+            errors.extend(provider.shutdown().err().map(|e| e.to_string()));
+            join(errors)
+        })
+    }
+
+    /// Flush `writer` (e.g. a [`MockWriter`](crate::MockWriter) or a file), reported as
+    /// `name`.
+    pub fn with_writer(self, name: &str, mut writer: impl Write + Send + 'static) -> Self {
+        self.with_component(&format!("writer `{name}`"), move || {
+            writer.flush().map_err(|e| e.to_string())
+        })
+    }
+
+    fn with_component(
+        mut self,
+        name: &str,
+        flush: impl FnOnce() -> Result<(), String> + Send + 'static,
+    ) -> Self {
+        self.components.push(Component {
+            name: name.to_owned(),
+            flush: Box::new(flush),
+        });
+        self
+    }
+
+    /// Flush and shut down everything, without blocking the async runtime.
+    pub async fn shutdown(mut self) -> ShutdownReport {
+        let components = std::mem::take(&mut self.components);
+        let deadline = self.deadline;
+        let report = tokio::task::spawn_blocking(move || flush(components, deadline))
+            .await
+            .unwrap_or_else(|e| ShutdownReport {
+                failures: vec![Failure {
+                    component: "telemetry guard".into(),
+                    error: e.to_string(),
+                }],
+            });
+        self.shut_down_runtime();
+        report
+    }
+
+    /// Runtimes can't be dropped from async code: don't wait for their tasks to complete.
+    fn shut_down_runtime(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if self.components.is_empty() {
+            self.shut_down_runtime();
+            return;
+        }
+        let components = std::mem::take(&mut self.components);
+        let deadline = self.deadline;
+        let report = match Handle::try_current().map(|h| h.runtime_flavor()) {
+            // Let the other worker threads run the export tasks while we wait.
+            Ok(RuntimeFlavor::MultiThread) => {
+                tokio::task::block_in_place(|| flush(components, deadline))
+            }
+            // Outside of a runtime nothing needs us, while on a current-thread runtime
+            // there's nothing better we can do: only the providers built on another runtime
+            // can be flushed.
+            _ => flush(components, deadline),
+        };
+        self.shut_down_runtime();
+        if !report.is_ok() {
+            eprintln!("{report}");
+        }
+    }
+}
+
+/// Flush `components` one after the other, on a dedicated thread, giving up on the ones that
+/// aren't done once `deadline` has elapsed.
+fn flush(components: Vec<Component>, deadline: Duration) -> ShutdownReport {
+    let names: Vec<String> = components.iter().map(|c| c.name.clone()).collect();
+    let (tx, rx) = mpsc::channel();
+    let spawned = std::thread::Builder::new()
+        .name("telemetry-shutdown".into())
+        .spawn(move || {
+            for (i, component) in components.into_iter().enumerate() {
+                if tx.send((i, (component.flush)())).is_err() {
+                    return;
+                }
+            }
+        });
+    let mut results: Vec<Option<Result<(), String>>> = vec![None; names.len()];
+    if let Err(e) = spawned {
+        results.fill(Some(Err(format!(
+            "failed to spawn the shutdown thread: {e}"
+        ))));
+    }
+    let start = Instant::now();
+    while results.iter().any(Option::is_none) {
+        let remaining = deadline.saturating_sub(start.elapsed());
+        match rx.recv_timeout(remaining) {
+            Ok((i, result)) => results[i] = Some(result),
+            Err(_) => break,
+        }
+    }
+
+    let failures = names
+        .into_iter()
+        .zip(results)
+        .filter_map(|(component, result)| {
+            let error = match result {
+                Some(Ok(())) => return None,
+                Some(Err(error)) => error,
+                None => format!("not done after {deadline:?}"),
+            };
+            Some(Failure { component, error })
+        })
+        .collect();
+    ShutdownReport { failures }
+}
+
+fn join(errors: Vec<String>) -> Result<(), String> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+/// What couldn't be flushed or shut down, i.e. telemetry that may have been lost.
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    pub failures: Vec<Failure>,
+}
+
+#[derive(Debug)]
+pub struct Failure {
+    /// e.g. `tracer provider` or ``writer `logs` ``.
+    pub component: String,
+    pub error: String,
+}
+
+impl ShutdownReport {
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Display for ShutdownReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_ok() {
+            return write!(f, "All telemetry was exported");
+        }
+        write!(f, "Some telemetry may not have been exported:")?;
+        for Failure { component, error } in &self.failures {
+            write!(f, "\n- {component}: {error}")?;
+        }
+        Ok(())
+    }
+}
@@ -4,6 +4,31 @@ use metrics_util::debugging::{DebuggingRecorder, Snapshotter};
 use std::str::FromStr;
 use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
 
+pub mod admin;
+pub mod chrome;
+pub mod completeness;
+pub mod context;
+pub mod ecs;
+pub mod event_fields;
+pub mod flame;
+pub mod flush_on_failure;
+pub mod gelf;
+pub mod guard;
+pub mod link_recorder;
+pub mod log_bridge;
+pub mod otel_error;
+pub mod otel_log;
+pub mod otel_metrics;
+pub mod otlp;
+pub mod poll_timing;
+pub mod rate_limit;
+pub mod redact;
+pub mod self_telemetry;
+pub mod span_tree;
+pub mod spawn;
+pub mod store;
+pub mod watchdog;
+
 /// Assert that the right-hand expression matches the regex specified as first argument.
 #[macro_export]
 macro_rules! assert_regex {
@@ -20,7 +45,7 @@ macro_rules! assert_regex {
 
 /// Use a vector of bytes behind a Arc<Mutex> as writer in order to inspect the tracing output
 /// for testing purposes.
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct MockWriter {
     buf: Arc<Mutex<Vec<u8>>>,
 }
@@ -73,7 +98,7 @@ impl LogOutput {
     }
 
     /// Iterator over logging output, line by line.
-    pub fn lines(&self) -> LogLines {
+    pub fn lines(&self) -> LogLines<'_> {
         LogLines {
             output: self.clone(),
             lines: self.0.lines(),
@@ -0,0 +1,71 @@
+//! A bare-bones layer to record how spans are linked together.
+use crate::MockWriter;
+use std::io::Write;
+use tracing::{Id, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Every time a span is entered, write a line to the underlying [`MockWriter`] with its name,
+/// the name of its parent and the names of the spans it follows from (if any):
+///
+/// ```text
+/// spawned1 - parent: spawner
+/// spawned2 - follows_from: spawner
+/// retry - parent: spawner - follows_from: spawned1, spawned2
+/// ```
+///
+/// Use [`SpanTreeLayer`](crate::span_tree::SpanTreeLayer) to see the whole picture at once.
+pub struct LinkRecorder {
+    writer: MockWriter,
+}
+
+impl LinkRecorder {
+    pub fn new(writer: MockWriter) -> Self {
+        Self { writer }
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for LinkRecorder
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let name = span.name();
+        let parent = span
+            .parent()
+            .map(|p| format!(" - parent: {}", p.name()))
+            .unwrap_or_default();
+        let follows_from: Vec<_> = span
+            .extensions()
+            .get::<FollowsFrom>()
+            .map(|links| {
+                links
+                    .0
+                    .iter()
+                    .filter_map(|id| ctx.span(id))
+                    .map(|p| p.name())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let follows_from = if follows_from.is_empty() {
+            String::new()
+        } else {
+            format!(" - follows_from: {}", follows_from.join(", "))
+        };
+        let mut buffer = self.writer.buf().unwrap();
+        writeln!(&mut buffer, "{name}{parent}{follows_from}").unwrap();
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(span).unwrap();
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<FollowsFrom>() {
+            Some(links) => links.0.push(follows.clone()),
+            None => extensions.insert(FollowsFrom(vec![follows.clone()])),
+        }
+    }
+}
+
+/// The spans a span follows from, in the order the links were added.
+struct FollowsFrom(Vec<Id>);
@@ -0,0 +1,338 @@
+//! Route events between `log` and `tracing`, in both directions.
+//!
+//! `tracing-log`'s `LogTracer` turns `log` records into `tracing` events, but it flattens their
+//! key-value pairs into the message. [`TracingLogger`] does the same job while turning each
+//! key-value pair into a proper `tracing` field.
+//!
+//! [`LogLayer`] goes the other way: it forwards `tracing` events to a [`log::Log`]
+//! implementation, for the consumers that only know about `log`. The event fields become
+//! key-value pairs of the log record.
+//!
+//! Both bridges can be installed at the same time: a record that crossed the bridge in one
+//! direction is never sent back in the other.
+use crate::event_fields::{with_value_set, FieldValue, RecordedEvent};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use tracing::callsite::{Callsite, Identifier};
+use tracing::field::FieldSet;
+use tracing::metadata::Kind;
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::layer::Context;
+
+/// The name of the field holding the formatted message of a log record.
+const MESSAGE_FIELD: &str = "message";
+
+/// The field `tracing-log` adds to the events it converts from `log` records.
+const TRACING_LOG_TARGET_FIELD: &str = "log.target";
+
+/// The field a `message` key-value pair is renamed to: `message` is taken by the record's
+/// message.
+const RENAMED_MESSAGE_FIELD: &str = "kv.message";
+
+thread_local! {
+    /// Set while a record is crossing the bridge, in either direction.
+    static BRIDGING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run `f`, unless we're already in the middle of bridging a record on this thread.
+fn bridge(f: impl FnOnce()) {
+    /// Clears the flag once `f` is done, even if it panicked.
+    struct Bridging;
+
+    impl Drop for Bridging {
+        fn drop(&mut self) {
+            BRIDGING.with(|bridging| bridging.set(false));
+        }
+    }
+
+    if BRIDGING.with(|bridging| bridging.replace(true)) {
+        return;
+    }
+    let _bridging = Bridging;
+    f();
+}
+
+type ToLogLevel = dyn Fn(&Level) -> Option<log::Level> + Send + Sync;
+type ToTracingLevel = dyn Fn(log::Level) -> Level + Send + Sync;
+
+/// A `tracing` layer that forwards events to a [`log::Log`] implementation.
+///
+/// Events that were converted from `log` records in the first place (by [`TracingLogger`] or
+/// by `tracing-log`) are skipped.
+pub struct LogLayer<L> {
+    logger: L,
+    level_mapping: Box<ToLogLevel>,
+}
+
+impl<L: log::Log> LogLayer<L> {
+    /// Forward events to `logger`, mapping each `tracing` level to the `log` level with the
+    /// same name.
+    pub fn new(logger: L) -> Self {
+        Self {
+            logger,
+            level_mapping: Box::new(|level| Some(tracing_to_log(level))),
+        }
+    }
+
+    /// Choose the `log` level of the records created for events at each `tracing` level.
+    ///
+    /// Events mapped to `None` are not forwarded.
+    pub fn with_level_mapping(
+        mut self,
+        mapping: impl Fn(&Level) -> Option<log::Level> + Send + Sync + 'static,
+    ) -> Self {
+        self.level_mapping = Box::new(mapping);
+        self
+    }
+}
+
+impl<S, L> tracing_subscriber::Layer<S> for LogLayer<L>
+where
+    S: Subscriber,
+    L: log::Log + 'static,
+{
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if metadata.fields().field(TRACING_LOG_TARGET_FIELD).is_some() {
+            return;
+        }
+        let Some(level) = (self.level_mapping)(metadata.level()) else {
+            return;
+        };
+        let log_metadata = log::Metadata::builder()
+            .level(level)
+            .target(metadata.target())
+            .build();
+        if !self.logger.enabled(&log_metadata) {
+            return;
+        }
+
+        let recorded = RecordedEvent::new(event);
+        let mut message = None;
+        let mut key_values = Vec::new();
+        for (field, value) in metadata.fields().iter().zip(&recorded.values) {
+            let Some(value) = value else {
+                continue;
+            };
+            if field.name() == MESSAGE_FIELD {
+                message = Some(value);
+            } else {
+                key_values.push((field.name(), to_log_value(value)));
+            }
+        }
+        let message = message.map(|value| to_log_value(value).to_string());
+        bridge(|| {
+            self.logger.log(
+                &log::Record::builder()
+                    .metadata(log_metadata)
+                    .args(format_args!("{}", message.as_deref().unwrap_or_default()))
+                    .module_path_static(metadata.module_path())
+                    .file_static(metadata.file())
+                    .line(metadata.line())
+                    .key_values(&key_values)
+                    .build(),
+            );
+        });
+    }
+}
+
+fn to_log_value(value: &FieldValue) -> log::kv::Value<'_> {
+    match value {
+        FieldValue::Str(v) => log::kv::Value::from(v.as_str()),
+        FieldValue::Debug(v) => log::kv::Value::from_display(v),
+        FieldValue::I64(v) => log::kv::Value::from(*v),
+        FieldValue::U64(v) => log::kv::Value::from(*v),
+        FieldValue::I128(v) => log::kv::Value::from(*v),
+        FieldValue::U128(v) => log::kv::Value::from(*v),
+        FieldValue::F64(v) => log::kv::Value::from(*v),
+        FieldValue::Bool(v) => log::kv::Value::from(*v),
+    }
+}
+
+/// A [`log::Log`] implementation that emits a `tracing` event for each record.
+///
+/// The key-value pairs attached to the record become fields of the event, next to `message`.
+/// A `message` key-value pair is recorded as `kv.message`, not to clash with the message.
+/// Records emitted while forwarding a `tracing` event through [`LogLayer`] are dropped.
+pub struct TracingLogger {
+    level_mapping: Box<ToTracingLevel>,
+    /// `tracing` needs a `'static` callsite for each event: we create (and leak) one for each
+    /// distinct `log` call site, the first time we see it.
+    callsites: Mutex<HashMap<CallsiteKey, &'static LogCallsite>>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct CallsiteKey {
+    level: Level,
+    target: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    keys: Vec<String>,
+}
+
+impl Default for TracingLogger {
+    fn default() -> Self {
+        Self {
+            level_mapping: Box::new(log_to_tracing),
+            callsites: Mutex::default(),
+        }
+    }
+}
+
+impl TracingLogger {
+    /// Map each `log` level to the `tracing` level with the same name.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Choose the `tracing` level of the events created for records at each `log` level.
+    pub fn with_level_mapping(
+        mut self,
+        mapping: impl Fn(log::Level) -> Level + Send + Sync + 'static,
+    ) -> Self {
+        self.level_mapping = Box::new(mapping);
+        self
+    }
+
+    /// Install the logger as the global `log` logger.
+    pub fn init(self) -> Result<(), log::SetLoggerError> {
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(log::LevelFilter::Trace);
+        Ok(())
+    }
+
+    fn callsite(&self, record: &log::Record<'_>, keys: Vec<String>) -> &'static LogCallsite {
+        let key = CallsiteKey {
+            level: (self.level_mapping)(record.level()),
+            target: record.target().to_owned(),
+            module_path: record.module_path().map(ToOwned::to_owned),
+            file: record.file().map(ToOwned::to_owned),
+            line: record.line(),
+            keys,
+        };
+        let mut callsites = self.callsites.lock().unwrap();
+        if let Some(callsite) = callsites.get(&key) {
+            return callsite;
+        }
+
+        let leak = |s: &str| -> &'static str { Box::leak(s.to_owned().into_boxed_str()) };
+        let field_names: Vec<&'static str> = std::iter::once(MESSAGE_FIELD)
+            .chain(key.keys.iter().map(|k| leak(k)))
+            .collect();
+        let callsite: &'static LogCallsite = Box::leak(Box::new(LogCallsite {
+            metadata: OnceLock::new(),
+        }));
+        let metadata = Metadata::new(
+            "log event",
+            leak(&key.target),
+            key.level,
+            key.file.as_deref().map(leak),
+            key.line,
+            key.module_path.as_deref().map(leak),
+            FieldSet::new(field_names.leak(), Identifier(callsite)),
+            Kind::EVENT,
+        );
+        let _ = callsite.metadata.set(metadata);
+        tracing::callsite::register(callsite);
+        callsites.insert(key, callsite);
+        callsite
+    }
+}
+
+impl log::Log for TracingLogger {
+    fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+        (self.level_mapping)(metadata.level()) <= tracing::level_filters::LevelFilter::current()
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        bridge(|| {
+            let mut key_values = KeyValues::default();
+            let _ = record.key_values().visit(&mut key_values);
+            let (keys, mut values): (Vec<_>, Vec<_>) = key_values.0.into_iter().unzip();
+            let callsite = self.callsite(record, keys);
+            let metadata = callsite.metadata.get().unwrap();
+            if !tracing::dispatcher::get_default(|dispatch| dispatch.enabled(metadata)) {
+                return;
+            }
+            values.insert(0, Some(FieldValue::Debug(record.args().to_string())));
+            with_value_set(metadata.fields(), &values, |values| {
+                Event::dispatch(metadata, values);
+            });
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Collects the key-value pairs of a record, in order.
+#[derive(Default)]
+struct KeyValues(Vec<(String, Option<FieldValue>)>);
+
+impl<'kvs> log::kv::VisitSource<'kvs> for KeyValues {
+    fn visit_pair(
+        &mut self,
+        key: log::kv::Key<'kvs>,
+        value: log::kv::Value<'kvs>,
+    ) -> Result<(), log::kv::Error> {
+        let key = match key.as_str() {
+            MESSAGE_FIELD => RENAMED_MESSAGE_FIELD,
+            key => key,
+        };
+        // A field can't appear twice in the same event: the last value wins.
+        self.0.retain(|(k, _)| k != key);
+        let value = if let Some(v) = value.to_borrowed_str() {
+            FieldValue::Str(v.to_owned())
+        } else if let Some(v) = value.to_bool() {
+            FieldValue::Bool(v)
+        } else if let Some(v) = value.to_i64() {
+            FieldValue::I64(v)
+        } else if let Some(v) = value.to_u64() {
+            FieldValue::U64(v)
+        } else if let Some(v) = value.to_f64() {
+            FieldValue::F64(v)
+        } else {
+            FieldValue::Debug(value.to_string())
+        };
+        self.0.push((key.to_owned(), Some(value)));
+        Ok(())
+    }
+}
+
+/// A callsite created at runtime for a `log` call site.
+struct LogCallsite {
+    metadata: OnceLock<Metadata<'static>>,
+}
+
+impl Callsite for LogCallsite {
+    fn set_interest(&self, _interest: Interest) {}
+
+    fn metadata(&self) -> &Metadata<'_> {
+        self.metadata.get().unwrap()
+    }
+}
+
+fn tracing_to_log(level: &Level) -> log::Level {
+    match *level {
+        Level::ERROR => log::Level::Error,
+        Level::WARN => log::Level::Warn,
+        Level::INFO => log::Level::Info,
+        Level::DEBUG => log::Level::Debug,
+        Level::TRACE => log::Level::Trace,
+    }
+}
+
+fn log_to_tracing(level: log::Level) -> Level {
+    match level {
+        log::Level::Error => Level::ERROR,
+        log::Level::Warn => Level::WARN,
+        log::Level::Info => Level::INFO,
+        log::Level::Debug => Level::DEBUG,
+        log::Level::Trace => Level::TRACE,
+    }
+}
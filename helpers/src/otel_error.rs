@@ -0,0 +1,272 @@
+//! Report errors on OpenTelemetry spans the way backends expect them.
+//!
+//! Recording `error.msg` or `error.source_chain` as span fields makes them show up as plain
+//! attributes once exported: nothing tells the backend that the span failed.
+//! The OpenTelemetry [semantic conventions](https://opentelemetry.io/docs/specs/semconv/exceptions/exceptions-spans/)
+//! expect:
+//!
+//! - the span status to be set to `Error`, with the error message as description;
+//! - an `exception` event, with `exception.type`, `exception.message` and
+//!   `exception.stacktrace` attributes. We don't capture backtraces: the source chain of the
+//!   error is the closest thing we have, so that's what goes into `exception.stacktrace`.
+//!
+//! [`ExceptionLayer`] does both, either when [`record_error`] (or [`record_dyn_error`]) is
+//! called or when the `error.msg`, `error.source_chain` and `error.type` fields of a span are
+//! recorded.
+//! A span gets at most one `exception` event: recording more error details updates it.
+use opentelemetry::trace::{Event, Status};
+use opentelemetry::KeyValue;
+use std::any::TypeId;
+use std::error::Error;
+use std::fmt::{Debug, Write};
+use std::marker::PhantomData;
+use std::time::SystemTime;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Dispatch, Span, Subscriber};
+use tracing_opentelemetry::OtelData;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::{LookupSpan, SpanRef};
+use tracing_subscriber::Layer;
+
+/// Mark `span` as failed because of `error`: its status is set to `Error` and an `exception`
+/// event is added to it.
+///
+/// `exception.type` is the name of `E`: pass the concrete error type rather than a
+/// `dyn Error` if you can, `exception.type` is left unset otherwise.
+/// Nothing happens if there is no [`ExceptionLayer`] in the subscriber `span` belongs to.
+pub fn record_error<E: Error + ?Sized>(span: &Span, error: &E) {
+    let r#type = std::any::type_name::<E>();
+    let r#type = (!r#type.starts_with("dyn ")).then(|| r#type.to_owned());
+    record(span, r#type, error);
+}
+
+/// [`record_error`], for errors whose concrete type is gone, e.g. an `anyhow::Error`
+/// (`record_dyn_error(&span, error.as_ref())`) or a `Box<dyn Error>`.
+///
+/// `exception.type` is left unset.
+pub fn record_dyn_error(span: &Span, error: &(dyn Error + 'static)) {
+    record(span, None, error);
+}
+
+fn record<E: Error + ?Sized>(span: &Span, r#type: Option<String>, error: &E) {
+    let exception = Exception {
+        r#type,
+        message: Some(error.to_string()),
+        stacktrace: Some(source_chain(error)),
+    };
+    span.with_subscriber(|(id, dispatch)| {
+        if let Some(record) = dispatch.downcast_ref::<RecordException>() {
+            (record.0)(dispatch, id, exception);
+        }
+    });
+}
+
+/// The `Debug` representation of each error in the source chain of `error`, one per line.
+fn source_chain<E: Error + ?Sized>(error: &E) -> String {
+    let mut chain = String::new();
+    let mut source = error.source();
+    while let Some(e) = source {
+        writeln!(&mut chain, "{e:?}").unwrap();
+        source = e.source();
+    }
+    chain
+}
+
+/// A `tracing` layer turning error details into a span status and an `exception` event, for the
+/// `tracing-opentelemetry` layer to export.
+///
+/// Fields are picked up whether they're set when the span is created or recorded later on:
+///
+/// - `error.msg` becomes the status description and `exception.message`;
+/// - `error.source_chain` becomes `exception.stacktrace`;
+/// - `error.type` becomes `exception.type`.
+pub struct ExceptionLayer<S> {
+    record: RecordException,
+    _subscriber: PhantomData<fn(S)>,
+}
+
+/// How [`record_error`] reaches the spans of the subscriber the layer belongs to, without
+/// knowing its type.
+#[derive(Clone, Copy)]
+struct RecordException(fn(&Dispatch, &Id, Exception));
+
+impl<S> Default for ExceptionLayer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn default() -> Self {
+        Self {
+            record: RecordException(Self::record_exception),
+            _subscriber: PhantomData,
+        }
+    }
+}
+
+impl<S> ExceptionLayer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record_exception(dispatch: &Dispatch, id: &Id, exception: Exception) {
+        let Some(subscriber) = dispatch.downcast_ref::<S>() else {
+            return;
+        };
+        if let Some(span) = subscriber.span(id) {
+            update(&span, exception);
+        }
+    }
+}
+
+impl<S> Layer<S> for ExceptionLayer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut visitor = ExceptionVisitor::default();
+        attrs.record(&mut visitor);
+        if let (Some(exception), Some(span)) = (visitor.exception, ctx.span(id)) {
+            update(&span, exception);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let mut visitor = ExceptionVisitor::default();
+        values.record(&mut visitor);
+        if let (Some(exception), Some(span)) = (visitor.exception, ctx.span(id)) {
+            update(&span, exception);
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        // If this layer sits below the `tracing-opentelemetry` one, the OpenTelemetry data
+        // didn't exist yet when the span was created: this is our last chance to add to it.
+        if let Some(span) = ctx.span(&id) {
+            update(&span, Exception::default());
+        }
+    }
+
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const Self as *const ())
+        } else if id == TypeId::of::<RecordException>() {
+            Some(&self.record as *const RecordException as *const ())
+        } else {
+            None
+        }
+    }
+}
+
+/// What we know about the error that made a span fail.
+#[derive(Clone, Default)]
+struct Exception {
+    r#type: Option<String>,
+    message: Option<String>,
+    stacktrace: Option<String>,
+}
+
+impl Exception {
+    /// Take the details `other` knows about, keep the others.
+    fn merge(&mut self, other: Exception) {
+        self.r#type = other.r#type.or(self.r#type.take());
+        self.message = other.message.or(self.message.take());
+        self.stacktrace = other.stacktrace.or(self.stacktrace.take());
+    }
+
+    fn attributes(&self) -> Vec<KeyValue> {
+        [
+            ("exception.type", &self.r#type),
+            ("exception.message", &self.message),
+            ("exception.stacktrace", &self.stacktrace),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some(KeyValue::new(key, value.clone()?)))
+        .collect()
+    }
+}
+
+/// Stored in the extensions of the spans that failed.
+struct SpanException {
+    exception: Exception,
+    timestamp: SystemTime,
+    /// The position of our `exception` event among the events of the OpenTelemetry span,
+    /// once added.
+    event: Option<usize>,
+}
+
+/// Merge `exception` into what we know about the failure of `span`, then reflect it on the
+/// OpenTelemetry data of the span, if it's there.
+fn update<S>(span: &SpanRef<'_, S>, exception: Exception)
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let mut extensions = span.extensions_mut();
+    let mut state = match extensions.remove::<SpanException>() {
+        Some(state) => state,
+        // `on_close` only syncs spans that failed.
+        None if exception.attributes().is_empty() => return,
+        None => SpanException {
+            exception: Exception::default(),
+            timestamp: SystemTime::now(),
+            event: None,
+        },
+    };
+    state.exception.merge(exception);
+    if let Some(data) = extensions.get_mut::<OtelData>() {
+        let message = state.exception.message.clone().unwrap_or_default();
+        data.builder.status = Status::error(message);
+        let event = Event::new(
+            "exception",
+            state.timestamp,
+            state.exception.attributes(),
+            0,
+        );
+        let events = data.builder.events.get_or_insert_with(Vec::new);
+        match state.event {
+            Some(i) if i < events.len() => events[i] = event,
+            _ => {
+                state.event = Some(events.len());
+                events.push(event);
+            }
+        }
+    }
+    extensions.insert(state);
+}
+
+/// Collects the `error.*` fields.
+#[derive(Default)]
+struct ExceptionVisitor {
+    exception: Option<Exception>,
+}
+
+impl Visit for ExceptionVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let exception = match field.name() {
+            "error.msg" => Exception {
+                message: Some(value.to_owned()),
+                ..Default::default()
+            },
+            "error.source_chain" => Exception {
+                stacktrace: Some(value.to_owned()),
+                ..Default::default()
+            },
+            "error.type" => Exception {
+                r#type: Some(value.to_owned()),
+                ..Default::default()
+            },
+            _ => return,
+        };
+        self.exception
+            .get_or_insert_with(Exception::default)
+            .merge(exception);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name().starts_with("error.") {
+            self.record_str(field, &format!("{value:?}"));
+        }
+    }
+}
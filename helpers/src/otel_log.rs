@@ -0,0 +1,134 @@
+//! Format `tracing` events as JSON records following the OpenTelemetry log data model.
+//!
+//! `tracing-subscriber`'s JSON formatter has its own schema (`fields`, `span`, `spans`).
+//! [`OtelLogFormat`] emits the top-level fields defined by the
+//! [OpenTelemetry log data model](https://opentelemetry.io/docs/specs/otel/logs/data-model/)
+//! instead, one record per line:
+//!
+//! ```json
+//! {
+//!   "Timestamp": "1700000000000000000",
+//!   "SeverityText": "INFO",
+//!   "SeverityNumber": 9,
+//!   "Body": "Retrieved order",
+//!   "Attributes": { "order_number": 1, "code.namespace": "orders", ... },
+//!   "Resource": { "service.name": "orders-api" },
+//!   "InstrumentationScope": { "Name": "orders" },
+//!   "TraceId": "5b8aa5a2d2c872e8321cf37308d69df2",
+//!   "SpanId": "051581bf3cb55c13"
+//! }
+//! ```
+//!
+//! `TraceId` and `SpanId` identify the span the event was emitted in, as seen by the
+//! `tracing-opentelemetry` layer. They are omitted if that layer isn't installed or if the
+//! event was emitted outside of any span.
+use crate::event_fields::{json_visitor, otel_ids, take_message};
+use opentelemetry::{Array, KeyValue};
+use serde_json::{Map, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// A [`FormatEvent`] implementation emitting OpenTelemetry log records as JSON.
+///
+/// Use it with `tracing_subscriber::fmt::layer().event_format(OtelLogFormat::new())`.
+#[derive(Default)]
+pub struct OtelLogFormat {
+    resource: Map<String, Value>,
+}
+
+impl OtelLogFormat {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attach these attributes, describing the entity producing the logs (e.g. `service.name`),
+    /// to every record.
+    pub fn with_resource(mut self, attributes: impl IntoIterator<Item = KeyValue>) -> Self {
+        for KeyValue { key, value } in attributes {
+            self.resource.insert(key.to_string(), otel_to_json(value));
+        }
+        self
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for OtelLogFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let metadata = event.metadata();
+        let mut attributes = Map::new();
+        event.record(&mut json_visitor(&mut attributes));
+        let message = take_message(&mut attributes);
+        attributes.insert("code.namespace".into(), metadata.target().into());
+        if let Some(file) = metadata.file() {
+            attributes.insert("code.filepath".into(), file.into());
+        }
+        if let Some(line) = metadata.line() {
+            attributes.insert("code.lineno".into(), line.into());
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let mut record = Map::new();
+        // The log data model uses 64-bit nanosecond timestamps: they don't fit in the numbers
+        // most JSON parsers can represent exactly, hence the string (as in OTLP/JSON).
+        record.insert("Timestamp".into(), timestamp.to_string().into());
+        record.insert("SeverityText".into(), metadata.level().as_str().into());
+        record.insert(
+            "SeverityNumber".into(),
+            severity_number(metadata.level()).into(),
+        );
+        record.insert("Body".into(), message.map_or(Value::Null, Value::from));
+        record.insert("Attributes".into(), attributes.into());
+        record.insert("Resource".into(), self.resource.clone().into());
+        record.insert(
+            "InstrumentationScope".into(),
+            serde_json::json!({ "Name": metadata.target() }),
+        );
+        if let Some((trace_id, span_id)) = ctx.parent_span().as_ref().and_then(otel_ids) {
+            record.insert("TraceId".into(), trace_id.to_string().into());
+            record.insert("SpanId".into(), span_id.to_string().into());
+        }
+
+        writeln!(writer, "{}", Value::from(record))
+    }
+}
+
+/// See the [severity fields](https://opentelemetry.io/docs/specs/otel/logs/data-model/#severity-fields)
+/// of the log data model.
+fn severity_number(level: &Level) -> u8 {
+    match *level {
+        Level::TRACE => 1,
+        Level::DEBUG => 5,
+        Level::INFO => 9,
+        Level::WARN => 13,
+        Level::ERROR => 17,
+    }
+}
+
+fn otel_to_json(value: opentelemetry::Value) -> Value {
+    match value {
+        opentelemetry::Value::Bool(v) => v.into(),
+        opentelemetry::Value::I64(v) => v.into(),
+        opentelemetry::Value::F64(v) => v.into(),
+        opentelemetry::Value::String(v) => v.as_str().into(),
+        opentelemetry::Value::Array(Array::Bool(v)) => v.into(),
+        opentelemetry::Value::Array(Array::I64(v)) => v.into(),
+        opentelemetry::Value::Array(Array::F64(v)) => v.into(),
+        opentelemetry::Value::Array(Array::String(v)) => {
+            v.iter().map(|s| Value::from(s.as_str())).collect()
+        }
+    }
+}
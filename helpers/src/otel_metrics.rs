@@ -0,0 +1,239 @@
+//! Ship `metrics` to an OpenTelemetry `MeterProvider`.
+//!
+//! [`OtelRecorder`] is a `metrics::Recorder` that forwards every counter, gauge and histogram
+//! to the matching OpenTelemetry instrument, created from a `Meter`: whatever exporter the
+//! `MeterProvider` behind it is configured with (e.g. a periodic OTLP exporter) takes care of
+//! the rest.
+//!
+//! - `metrics` labels become OpenTelemetry attributes;
+//! - the units and descriptions given with `describe_*!` are attached to the instruments. Units
+//!   are translated to their [UCUM](https://ucum.org/) code, as OpenTelemetry expects.
+//!
+//! OpenTelemetry instruments can't be changed once created: `describe_*!` must be called before
+//! the metric is first used, otherwise its unit and description are ignored.
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SharedString, Unit,
+};
+use opentelemetry::metrics::Meter;
+use opentelemetry::KeyValue;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A `metrics` recorder backed by an OpenTelemetry [`Meter`].
+///
+/// Install it with `metrics::set_global_recorder`, or scope it with
+/// `metrics::with_local_recorder`.
+pub struct OtelRecorder {
+    meter: Meter,
+    descriptions: Mutex<HashMap<String, Description>>,
+    counters: Mutex<HashMap<Key, Arc<OtelCounter>>>,
+    gauges: Mutex<HashMap<Key, Arc<OtelGauge>>>,
+    histograms: Mutex<HashMap<Key, Arc<OtelHistogram>>>,
+}
+
+#[derive(Clone)]
+struct Description {
+    unit: Option<Unit>,
+    text: SharedString,
+}
+
+impl OtelRecorder {
+    pub fn new(meter: Meter) -> Self {
+        Self {
+            meter,
+            descriptions: Default::default(),
+            counters: Default::default(),
+            gauges: Default::default(),
+            histograms: Default::default(),
+        }
+    }
+
+    fn describe(&self, key: KeyName, unit: Option<Unit>, text: SharedString) {
+        self.descriptions
+            .lock()
+            .unwrap()
+            .insert(key.as_str().to_owned(), Description { unit, text });
+    }
+
+    fn description(&self, name: &str) -> Option<Description> {
+        self.descriptions.lock().unwrap().get(name).cloned()
+    }
+}
+
+// The instruments are built by the same `InstrumentBuilder` dance for each kind, with a
+// different value type: a macro keeps the three of them in sync.
+macro_rules! instrument {
+    ($recorder:expr, $constructor:ident, $name:expr) => {{
+        let mut builder = $recorder.meter.$constructor($name.to_owned());
+        if let Some(description) = $recorder.description($name) {
+            builder = builder.with_description(description.text.into_owned());
+            if let Some(unit) = description.unit {
+                builder = builder.with_unit(ucum(unit));
+            }
+        }
+        builder.init()
+    }};
+}
+
+impl Recorder for OtelRecorder {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.describe(key, unit, description);
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.describe(key, unit, description);
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: SharedString) {
+        self.describe(key, unit, description);
+    }
+
+    fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+        let counter = self
+            .counters
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_insert_with(|| {
+                Arc::new(OtelCounter {
+                    instrument: instrument!(self, u64_counter, key.name()),
+                    attributes: attributes(key),
+                    total: AtomicU64::new(0),
+                })
+            })
+            .clone();
+        Counter::from_arc(counter)
+    }
+
+    fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+        let gauge = self
+            .gauges
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_insert_with(|| {
+                Arc::new(OtelGauge {
+                    instrument: instrument!(self, f64_gauge, key.name()),
+                    attributes: attributes(key),
+                    value: AtomicU64::new(0f64.to_bits()),
+                })
+            })
+            .clone();
+        Gauge::from_arc(gauge)
+    }
+
+    fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+        let histogram = self
+            .histograms
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_insert_with(|| {
+                Arc::new(OtelHistogram {
+                    instrument: instrument!(self, f64_histogram, key.name()),
+                    attributes: attributes(key),
+                })
+            })
+            .clone();
+        Histogram::from_arc(histogram)
+    }
+}
+
+fn attributes(key: &Key) -> Vec<KeyValue> {
+    key.labels()
+        .map(|label| KeyValue::new(label.key().to_owned(), label.value().to_owned()))
+        .collect()
+}
+
+/// The UCUM code OpenTelemetry expects for a `metrics` unit.
+fn ucum(unit: Unit) -> &'static str {
+    match unit {
+        Unit::Count => "1",
+        Unit::Percent => "%",
+        Unit::Seconds => "s",
+        Unit::Milliseconds => "ms",
+        Unit::Microseconds => "us",
+        Unit::Nanoseconds => "ns",
+        Unit::Tebibytes => "TiBy",
+        Unit::Gigibytes => "GiBy",
+        Unit::Mebibytes => "MiBy",
+        Unit::Kibibytes => "KiBy",
+        Unit::Bytes => "By",
+        Unit::TerabitsPerSecond => "Tbit/s",
+        Unit::GigabitsPerSecond => "Gbit/s",
+        Unit::MegabitsPerSecond => "Mbit/s",
+        Unit::KilobitsPerSecond => "kbit/s",
+        Unit::BitsPerSecond => "bit/s",
+        Unit::CountPerSecond => "1/s",
+    }
+}
+
+struct OtelCounter {
+    instrument: opentelemetry::metrics::Counter<u64>,
+    attributes: Vec<KeyValue>,
+    // OpenTelemetry counters only accept increments: keep track of the total to turn
+    // `absolute` into one.
+    total: AtomicU64,
+}
+
+impl CounterFn for OtelCounter {
+    fn increment(&self, value: u64) {
+        self.total.fetch_add(value, Ordering::Relaxed);
+        self.instrument.add(value, &self.attributes);
+    }
+
+    fn absolute(&self, value: u64) {
+        let previous = self.total.fetch_max(value, Ordering::Relaxed);
+        if value > previous {
+            self.instrument.add(value - previous, &self.attributes);
+        }
+    }
+}
+
+struct OtelGauge {
+    instrument: opentelemetry::metrics::Gauge<f64>,
+    attributes: Vec<KeyValue>,
+    // OpenTelemetry gauges only accept the new value: keep track of the current one (as `f64`
+    // bits) to support `increment` and `decrement`.
+    value: AtomicU64,
+}
+
+impl OtelGauge {
+    fn update(&self, f: impl Fn(f64) -> f64) {
+        let previous = self
+            .value
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some(f(f64::from_bits(bits)).to_bits())
+            })
+            .unwrap();
+        self.instrument
+            .record(f(f64::from_bits(previous)), &self.attributes);
+    }
+}
+
+impl GaugeFn for OtelGauge {
+    fn increment(&self, value: f64) {
+        self.update(|current| current + value);
+    }
+
+    fn decrement(&self, value: f64) {
+        self.update(|current| current - value);
+    }
+
+    fn set(&self, value: f64) {
+        self.update(|_| value);
+    }
+}
+
+struct OtelHistogram {
+    instrument: opentelemetry::metrics::Histogram<f64>,
+    attributes: Vec<KeyValue>,
+}
+
+impl HistogramFn for OtelHistogram {
+    fn record(&self, value: f64) {
+        self.instrument.record(value, &self.attributes);
+    }
+}
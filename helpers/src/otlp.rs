@@ -0,0 +1,265 @@
+//! An in-process stand-in for an OpenTelemetry collector.
+//!
+//! It accepts OTLP over gRPC on a random local port and keeps everything it receives in memory,
+//! so that tests can assert on what would have been shipped to Honeycomb (or any other backend).
+//! It can also be told to reject or delay requests, to see how exporters cope with a backend
+//! that is down or slow (see [`Behaviour`]).
+use opentelemetry_proto::tonic::collector::logs::v1::logs_service_server::{
+    LogsService, LogsServiceServer,
+};
+use opentelemetry_proto::tonic::collector::logs::v1::{
+    ExportLogsServiceRequest, ExportLogsServiceResponse,
+};
+use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_server::{
+    MetricsService, MetricsServiceServer,
+};
+use opentelemetry_proto::tonic::collector::metrics::v1::{
+    ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+};
+use opentelemetry_proto::tonic::collector::trace::v1::trace_service_server::{
+    TraceService, TraceServiceServer,
+};
+use opentelemetry_proto::tonic::collector::trace::v1::{
+    ExportTraceServiceRequest, ExportTraceServiceResponse,
+};
+use opentelemetry_proto::tonic::common::v1::any_value::Value;
+use opentelemetry_proto::tonic::common::v1::KeyValue;
+use opentelemetry_proto::tonic::logs::v1::{LogRecord, ResourceLogs};
+use opentelemetry_proto::tonic::metrics::v1::{Metric, ResourceMetrics};
+use opentelemetry_proto::tonic::trace::v1::{ResourceSpans, Span};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+/// A local OTLP/gRPC endpoint that records every export request it receives.
+///
+/// The server is shut down when the collector is dropped.
+pub struct MockCollector {
+    addr: SocketAddr,
+    received: Received,
+    _shutdown: oneshot::Sender<()>,
+}
+
+/// How the collector answers export requests.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Behaviour {
+    /// Record the data and acknowledge it.
+    #[default]
+    Accept,
+    /// Fail with `UNAVAILABLE`, without recording anything.
+    Reject,
+    /// Wait, then accept.
+    Delay(Duration),
+}
+
+#[derive(Clone, Default)]
+struct Received {
+    resource_spans: Arc<Mutex<Vec<ResourceSpans>>>,
+    resource_metrics: Arc<Mutex<Vec<ResourceMetrics>>>,
+    resource_logs: Arc<Mutex<Vec<ResourceLogs>>>,
+    behaviour: Arc<Mutex<Behaviour>>,
+}
+
+impl Received {
+    /// Apply the current [`Behaviour`]: `Ok` if the request should be accepted.
+    async fn respond(&self) -> Result<(), Status> {
+        let behaviour = *self.behaviour.lock().unwrap();
+        match behaviour {
+            Behaviour::Accept => Ok(()),
+            Behaviour::Reject => Err(Status::unavailable(
+                "The mock collector is rejecting requests",
+            )),
+            Behaviour::Delay(delay) => {
+                tokio::time::sleep(delay).await;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl MockCollector {
+    /// Start listening on a random local port.
+    ///
+    /// It must be called from within a `tokio` runtime, since the server is spawned as a
+    /// background task on it.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind(SocketAddr::new(Ipv4Addr::LOCALHOST.into(), 0))
+            .await
+            .expect("Failed to bind the mock collector to a local port");
+        let addr = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        let received = Received::default();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+
+        let server = Server::builder()
+            .add_service(TraceServiceServer::new(received.clone()))
+            .add_service(MetricsServiceServer::new(received.clone()))
+            .add_service(LogsServiceServer::new(received.clone()))
+            .serve_with_incoming_shutdown(incoming, async {
+                let _ = shutdown_rx.await;
+            });
+        tokio::spawn(async move {
+            server.await.expect("The mock collector crashed");
+        });
+
+        Self {
+            addr,
+            received,
+            _shutdown: shutdown_tx,
+        }
+    }
+
+    /// Change how the following export requests are answered.
+    pub fn set_behaviour(&self, behaviour: Behaviour) {
+        *self.received.behaviour.lock().unwrap() = behaviour;
+    }
+
+    /// The URL to point an OTLP/gRPC exporter at.
+    pub fn endpoint(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// All the batches of spans received so far, grouped by resource.
+    pub fn resource_spans(&self) -> Vec<ResourceSpans> {
+        self.received.resource_spans.lock().unwrap().clone()
+    }
+
+    /// All the spans received so far, regardless of their resource and scope.
+    pub fn spans(&self) -> Vec<Span> {
+        self.resource_spans()
+            .into_iter()
+            .flat_map(|r| r.scope_spans)
+            .flat_map(|s| s.spans)
+            .collect()
+    }
+
+    /// All the batches of metrics received so far, grouped by resource.
+    pub fn resource_metrics(&self) -> Vec<ResourceMetrics> {
+        self.received.resource_metrics.lock().unwrap().clone()
+    }
+
+    /// All the metrics received so far, regardless of their resource and scope.
+    ///
+    /// A metric shows up once per export: with the default (cumulative) temporality, the last
+    /// one with a given name holds its final value.
+    pub fn metrics(&self) -> Vec<Metric> {
+        self.resource_metrics()
+            .into_iter()
+            .flat_map(|r| r.scope_metrics)
+            .flat_map(|s| s.metrics)
+            .collect()
+    }
+
+    /// All the batches of log records received so far, grouped by resource.
+    pub fn resource_logs(&self) -> Vec<ResourceLogs> {
+        self.received.resource_logs.lock().unwrap().clone()
+    }
+
+    /// All the log records received so far, regardless of their resource and scope.
+    pub fn log_records(&self) -> Vec<LogRecord> {
+        self.resource_logs()
+            .into_iter()
+            .flat_map(|r| r.scope_logs)
+            .flat_map(|s| s.log_records)
+            .collect()
+    }
+}
+
+#[tonic::async_trait]
+impl TraceService for Received {
+    async fn export(
+        &self,
+        request: Request<ExportTraceServiceRequest>,
+    ) -> Result<Response<ExportTraceServiceResponse>, Status> {
+        self.respond().await?;
+        self.resource_spans
+            .lock()
+            .unwrap()
+            .extend(request.into_inner().resource_spans);
+        Ok(Response::new(ExportTraceServiceResponse {
+            partial_success: None,
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl MetricsService for Received {
+    async fn export(
+        &self,
+        request: Request<ExportMetricsServiceRequest>,
+    ) -> Result<Response<ExportMetricsServiceResponse>, Status> {
+        self.respond().await?;
+        self.resource_metrics
+            .lock()
+            .unwrap()
+            .extend(request.into_inner().resource_metrics);
+        Ok(Response::new(ExportMetricsServiceResponse {
+            partial_success: None,
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl LogsService for Received {
+    async fn export(
+        &self,
+        request: Request<ExportLogsServiceRequest>,
+    ) -> Result<Response<ExportLogsServiceResponse>, Status> {
+        self.respond().await?;
+        self.resource_logs
+            .lock()
+            .unwrap()
+            .extend(request.into_inner().resource_logs);
+        Ok(Response::new(ExportLogsServiceResponse {
+            partial_success: None,
+        }))
+    }
+}
+
+/// Look up an attribute by key and render its value as a string.
+///
+/// Returns `None` if there is no attribute with that key.
+pub fn attribute(attributes: &[KeyValue], key: &str) -> Option<String> {
+    let value = attributes
+        .iter()
+        .find(|kv| kv.key == key)?
+        .value
+        .as_ref()?
+        .value
+        .as_ref()?;
+    Some(render(value))
+}
+
+fn render(value: &Value) -> String {
+    match value {
+        Value::StringValue(s) => s.clone(),
+        Value::BoolValue(b) => b.to_string(),
+        Value::IntValue(i) => i.to_string(),
+        Value::DoubleValue(d) => d.to_string(),
+        Value::ArrayValue(a) => {
+            let values: Vec<_> = a
+                .values
+                .iter()
+                .filter_map(|v| v.value.as_ref().map(render))
+                .collect();
+            format!("[{}]", values.join(","))
+        }
+        Value::KvlistValue(kv) => {
+            let values: Vec<_> = kv
+                .values
+                .iter()
+                .map(|kv| {
+                    let value = kv.value.as_ref().and_then(|v| v.value.as_ref());
+                    format!("{}={}", kv.key, value.map(render).unwrap_or_default())
+                })
+                .collect();
+            format!("{{{}}}", values.join(","))
+        }
+        Value::BytesValue(bytes) => bytes.iter().map(|b| format!("{b:02x}")).collect(),
+    }
+}
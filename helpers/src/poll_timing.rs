@@ -0,0 +1,237 @@
+//! Measure how async tasks spend their time, poll by poll.
+//!
+//! `tracing::Instrument` enters a span every time the future is polled, but nothing tells you
+//! how many polls there were, how long each of them took or how long the task waited to be
+//! polled after being woken up.
+//! [`PollTimed`] wraps a future, enters its span around each poll (like `Instrumented`) and
+//! measures exactly that. The numbers are reported to [`PollTimingLayer`], which:
+//!
+//! - records per-poll `metrics` histograms, labelled with the name of the task span;
+//! - warns about polls that take longer than a threshold: an `async` function shouldn't block
+//!   the executor thread, a slow poll usually means a blocking call hiding somewhere;
+//! - once the task is done, records a summary as span fields (`poll.count`, `poll.busy_ns`,
+//!   `poll.longest_ns`, `poll.max_scheduling_delay_ns`) and as per-task histograms.
+//!
+//! The span must declare the summary fields for them to be recorded, e.g.
+//! `info_span!("task", poll.count = Empty, poll.busy_ns = Empty, ...)`.
+//! If no [`PollTimingLayer`] is installed, [`PollTimed`] behaves like `Instrumented`.
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::{Duration, Instant};
+use tracing::Span;
+
+/// The target of the warnings emitted for slow polls.
+pub const SLOW_POLL_TARGET: &str = module_path!();
+
+/// Histogram of the duration of each poll, in seconds, labelled by `task`.
+pub const POLL_DURATION_HISTOGRAM: &str = "async_task_poll_duration_seconds";
+/// Histogram of the time between a wake-up and the following poll, in seconds, labelled by
+/// `task`.
+pub const SCHEDULING_DELAY_HISTOGRAM: &str = "async_task_scheduling_delay_seconds";
+/// Histogram of the number of polls it took each task to complete, labelled by `task`.
+pub const POLL_COUNT_HISTOGRAM: &str = "async_task_polls";
+/// Histogram of the total time spent polling each task, in seconds, labelled by `task`.
+pub const BUSY_TIME_HISTOGRAM: &str = "async_task_busy_seconds";
+
+/// A `tracing` layer collecting the measurements taken by [`PollTimed`] futures.
+///
+/// [`PollTimed`] finds it by downcasting the subscriber its span belongs to, so it doesn't
+/// need to be the outermost layer.
+pub struct PollTimingLayer {
+    slow_poll_threshold: Duration,
+}
+
+impl Default for PollTimingLayer {
+    fn default() -> Self {
+        Self {
+            slow_poll_threshold: Duration::from_millis(10),
+        }
+    }
+}
+
+impl PollTimingLayer {
+    /// Warn about polls taking longer than 10 milliseconds.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_slow_poll_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_poll_threshold = threshold;
+        self
+    }
+
+    fn on_poll(&self, span: &Span, busy: Duration, scheduling_delay: Option<Duration>) {
+        let task = task_name(span);
+        metrics::histogram!(POLL_DURATION_HISTOGRAM, "task" => task).record(busy);
+        if let Some(delay) = scheduling_delay {
+            metrics::histogram!(SCHEDULING_DELAY_HISTOGRAM, "task" => task).record(delay);
+        }
+        if busy > self.slow_poll_threshold {
+            tracing::warn!(
+                target: SLOW_POLL_TARGET,
+                parent: span,
+                task,
+                poll_ms = busy.as_millis() as u64,
+                threshold_ms = self.slow_poll_threshold.as_millis() as u64,
+                "slow poll, is something blocking the executor?"
+            );
+        }
+    }
+
+    fn on_done(&self, span: &Span, stats: &PollStats) {
+        let task = task_name(span);
+        metrics::histogram!(POLL_COUNT_HISTOGRAM, "task" => task).record(stats.count as f64);
+        metrics::histogram!(BUSY_TIME_HISTOGRAM, "task" => task).record(stats.busy);
+        span.record("poll.count", stats.count);
+        span.record("poll.busy_ns", stats.busy.as_nanos() as u64);
+        span.record("poll.longest_ns", stats.longest.as_nanos() as u64);
+        span.record(
+            "poll.max_scheduling_delay_ns",
+            stats.max_scheduling_delay.as_nanos() as u64,
+        );
+    }
+}
+
+impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for PollTimingLayer {}
+
+fn task_name(span: &Span) -> &'static str {
+    span.metadata()
+        .map_or("<disabled>", |metadata| metadata.name())
+}
+
+/// Extension trait to wrap a future in [`PollTimed`].
+pub trait PollTimingExt: Future + Sized {
+    /// Poll the future inside `span`, measuring each poll.
+    fn instrument_polls(self, span: Span) -> PollTimed<Self> {
+        PollTimed {
+            inner: Some(Box::pin(self)),
+            span,
+            stats: PollStats::default(),
+            wake_tracker: None,
+        }
+    }
+}
+
+impl<F: Future> PollTimingExt for F {}
+
+/// A future measuring how long each of its polls takes.
+///
+/// Created with [`PollTimingExt::instrument_polls`].
+pub struct PollTimed<F> {
+    /// `None` once the future has completed.
+    inner: Option<Pin<Box<F>>>,
+    span: Span,
+    stats: PollStats,
+    /// The waker handed to the inner future, set on the first poll.
+    wake_tracker: Option<Arc<WakeTracker>>,
+}
+
+#[derive(Default)]
+struct PollStats {
+    count: u64,
+    busy: Duration,
+    longest: Duration,
+    max_scheduling_delay: Duration,
+}
+
+/// Wraps the executor's waker, to remember when the task was woken up.
+struct WakeTracker {
+    waker: Mutex<Waker>,
+    woken_at: Mutex<Option<Instant>>,
+}
+
+impl Wake for WakeTracker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        // The delay runs from the first wake-up.
+        self.woken_at
+            .lock()
+            .unwrap()
+            .get_or_insert_with(Instant::now);
+        self.waker.lock().unwrap().wake_by_ref();
+    }
+}
+
+impl<F> PollTimed<F> {
+    fn layer<R>(&self, f: impl FnOnce(&PollTimingLayer) -> R) -> Option<R> {
+        self.span
+            .with_subscriber(|(_, dispatch)| dispatch.downcast_ref::<PollTimingLayer>().map(f))
+            .flatten()
+    }
+
+    fn finish(&mut self) {
+        let stats = std::mem::take(&mut self.stats);
+        self.layer(|layer| layer.on_done(&self.span, &stats));
+    }
+}
+
+impl<F: Future> Future for PollTimed<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // All our fields are `Unpin`: the inner future is pinned in its own box.
+        let this = self.get_mut();
+        let started_at = Instant::now();
+
+        let tracker = this.wake_tracker.get_or_insert_with(|| {
+            Arc::new(WakeTracker {
+                waker: Mutex::new(cx.waker().clone()),
+                woken_at: Mutex::new(None),
+            })
+        });
+        {
+            let mut waker = tracker.waker.lock().unwrap();
+            if !waker.will_wake(cx.waker()) {
+                waker.clone_from(cx.waker());
+            }
+        }
+        let scheduling_delay = tracker
+            .woken_at
+            .lock()
+            .unwrap()
+            .take()
+            .map(|woken_at| started_at - woken_at);
+        let waker = Waker::from(tracker.clone());
+
+        let inner = this
+            .inner
+            .as_mut()
+            .expect("`PollTimed` polled after completion");
+        let poll = this
+            .span
+            .in_scope(|| inner.as_mut().poll(&mut Context::from_waker(&waker)));
+        let busy = started_at.elapsed();
+
+        let stats = &mut this.stats;
+        stats.count += 1;
+        stats.busy += busy;
+        stats.longest = stats.longest.max(busy);
+        if let Some(delay) = scheduling_delay {
+            stats.max_scheduling_delay = stats.max_scheduling_delay.max(delay);
+        }
+        this.layer(|layer| layer.on_poll(&this.span, busy, scheduling_delay));
+
+        if poll.is_ready() {
+            this.inner = None;
+            this.finish();
+        }
+        poll
+    }
+}
+
+impl<F> Drop for PollTimed<F> {
+    fn drop(&mut self) {
+        // The task was cancelled before completion: report what we measured so far.
+        if let Some(inner) = self.inner.take() {
+            self.span.in_scope(|| drop(inner));
+            if self.stats.count > 0 {
+                self.finish();
+            }
+        }
+    }
+}
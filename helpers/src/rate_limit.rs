@@ -0,0 +1,581 @@
+//! A layer to rate-limit events on a per-callsite basis.
+//!
+//! A hot loop that keeps failing (e.g. `get_total` while the database is down) can easily
+//! flood the logging pipeline with thousands of identical events.
+//! [`RateLimitLayer`] wraps the layer writing to that pipeline, keeps a token bucket for each
+//! callsite (optionally refined by the values of a few fields) and stops forwarding events once
+//! the bucket is empty.
+//! A single summary event then reports how many events were suppressed, in the span the last
+//! of them belonged to. It is emitted as soon as the bucket refills enough to let an event
+//! through again, or when that span closes, whichever comes first.
+//!
+//! A layer only gets to run when something happens, so "as soon as" means the next event (from
+//! any callsite) or the next span closing once the bucket has refilled.
+//!
+//! Buckets that have been idle for a while are evicted, so that key fields with many different
+//! values don't make the layer grow without bound.
+use std::any::TypeId;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::callsite::{Callsite, DefaultCallsite, Identifier};
+use tracing::field::{Field, FieldSet, Value, Visit};
+use tracing::metadata::{Kind, LevelFilter};
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Dispatch, Event, Level, Metadata, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::Layer;
+
+/// The target used for the summary events emitted by [`RateLimitLayer`].
+pub const SUMMARY_TARGET: &str = module_path!();
+
+/// How many events are allowed through a given callsite.
+///
+/// Up to `burst` events are let through straight away, after which the bucket refills at a rate
+/// of `burst` events every `period`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    burst: u32,
+    period: Duration,
+}
+
+impl RateLimit {
+    pub fn new(burst: u32, period: Duration) -> Self {
+        assert!(burst > 0, "A rate limit must allow at least one event");
+        assert!(!period.is_zero(), "A rate limit period can't be zero");
+        Self { burst, period }
+    }
+
+    /// How many tokens are added back to the bucket every second.
+    fn refill_rate(&self) -> f64 {
+        self.burst as f64 / self.period.as_secs_f64()
+    }
+}
+
+/// A rule that assigns a [`RateLimit`] to all events coming from a target (or one of its
+/// sub-modules), optionally restricted to a single level.
+#[derive(Debug)]
+struct Rule {
+    target: String,
+    level: Option<Level>,
+    limit: RateLimit,
+}
+
+impl Rule {
+    fn matches(&self, target: &str, level: &Level) -> bool {
+        let target_matches = target == self.target
+            || target
+                .strip_prefix(self.target.as_str())
+                .is_some_and(|rest| rest.starts_with("::"));
+        target_matches && self.level.as_ref().is_none_or(|l| l == level)
+    }
+
+    /// Rules on longer targets are more specific; level-specific rules beat level-agnostic ones.
+    fn specificity(&self) -> (usize, bool) {
+        (self.target.len(), self.level.is_some())
+    }
+}
+
+/// Events are bucketed by callsite and by the values of the configured key fields.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BucketKey {
+    callsite: Identifier,
+    field_values: Vec<Option<String>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+    suppressed: u64,
+    /// The metadata of the events going through this bucket, used to build their summary.
+    metadata: &'static Metadata<'static>,
+    /// The span the last suppressed event belonged to, if any.
+    suppressed_in: Option<Id>,
+    /// When the bucket will have refilled enough to let an event through, if some of its
+    /// events have been suppressed.
+    reopens_at: Option<Instant>,
+}
+
+impl Bucket {
+    fn new(limit: RateLimit, metadata: &'static Metadata<'static>, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: now,
+            suppressed: 0,
+            metadata,
+            suppressed_in: None,
+            reopens_at: None,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.limit.refill_rate())
+            .min(self.limit.burst as f64);
+        self.last_refill = now;
+    }
+
+    /// A bucket that hasn't seen an event for a whole period is full again: it can be
+    /// dropped and recreated from scratch when the next event comes in.
+    fn is_idle(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last_refill) >= self.limit.period
+    }
+
+    /// Reset the count of suppressed events, returning the summary to report (if any).
+    fn take_summary(&mut self) -> Option<Summary> {
+        let parent = self.suppressed_in.take();
+        self.reopens_at = None;
+        match std::mem::take(&mut self.suppressed) {
+            0 => None,
+            suppressed => Some(Summary {
+                metadata: self.metadata,
+                suppressed,
+                parent,
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<BucketKey, Bucket>,
+    /// The buckets with suppressed events, by the instant they reopen.
+    reopening: BTreeMap<Instant, Vec<BucketKey>>,
+    /// The buckets with suppressed events, by the span the last of them belonged to.
+    suppressed_in: HashMap<Id, HashSet<BucketKey>>,
+    last_eviction: Instant,
+}
+
+impl Buckets {
+    /// Take the summary of a bucket, if it has one, and forget about it in the indexes.
+    fn take_summary(&mut self, key: &BucketKey) -> Option<Summary> {
+        let summary = self.buckets.get_mut(key)?.take_summary()?;
+        if let Some(parent) = &summary.parent {
+            self.unindex(parent, key);
+        }
+        Some(summary)
+    }
+
+    fn unindex(&mut self, span: &Id, key: &BucketKey) {
+        if let Some(keys) = self.suppressed_in.get_mut(span) {
+            keys.remove(key);
+            if keys.is_empty() {
+                self.suppressed_in.remove(span);
+            }
+        }
+    }
+}
+
+/// How many events were suppressed for a callsite, and where to report it.
+struct Summary {
+    metadata: &'static Metadata<'static>,
+    suppressed: u64,
+    parent: Option<Id>,
+}
+
+/// A `tracing` layer that rate-limits the events forwarded to the layer it wraps, using a token
+/// bucket per callsite.
+///
+/// Events that don't match any configured rule (if there is no default limit) are left alone.
+/// Spans and every other notification are forwarded as they are.
+///
+/// Only the wrapped layer is affected: other layers in the same subscriber (e.g. the one
+/// exporting to OpenTelemetry) keep seeing every event.
+/// If you want to filter what reaches the wrapped layer, apply the filter to the
+/// [`RateLimitLayer`] rather than to the inner layer, to avoid spending tokens on events that
+/// would be discarded anyway.
+///
+/// ```rust
+/// use helpers::rate_limit::{RateLimit, RateLimitLayer};
+/// use std::time::Duration;
+/// use tracing::Level;
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let layer = RateLimitLayer::new(tracing_subscriber::fmt::layer())
+///     .with_default_limit(RateLimit::new(100, Duration::from_secs(1)))
+///     .with_limit("kv", Level::ERROR, RateLimit::new(5, Duration::from_secs(10)))
+///     .with_key_fields(["order_number"]);
+/// let subscriber = tracing_subscriber::Registry::default().with(layer);
+/// ```
+#[derive(Debug)]
+pub struct RateLimitLayer<L> {
+    inner: L,
+    default_limit: Option<RateLimit>,
+    rules: Vec<Rule>,
+    key_fields: Vec<String>,
+    eviction_interval: Duration,
+    buckets: Mutex<Buckets>,
+}
+
+impl<L> RateLimitLayer<L> {
+    pub fn new(inner: L) -> Self {
+        Self {
+            inner,
+            default_limit: None,
+            rules: Vec::new(),
+            key_fields: Vec::new(),
+            eviction_interval: Duration::from_secs(60),
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                reopening: BTreeMap::new(),
+                suppressed_in: HashMap::new(),
+                last_eviction: Instant::now(),
+            }),
+        }
+    }
+
+    /// The limit applied to events that don't match any of the more specific rules.
+    pub fn with_default_limit(mut self, limit: RateLimit) -> Self {
+        self.default_limit = Some(limit);
+        self
+    }
+
+    /// Limit all events with the given target (or coming from one of its sub-modules) and level.
+    pub fn with_limit(mut self, target: impl Into<String>, level: Level, limit: RateLimit) -> Self {
+        self.rules.push(Rule {
+            target: target.into(),
+            level: Some(level),
+            limit,
+        });
+        self
+    }
+
+    /// Limit all events with the given target (or coming from one of its sub-modules),
+    /// regardless of their level.
+    pub fn with_target_limit(mut self, target: impl Into<String>, limit: RateLimit) -> Self {
+        self.rules.push(Rule {
+            target: target.into(),
+            level: None,
+            limit,
+        });
+        self
+    }
+
+    /// Keep a separate bucket for each combination of values of these fields.
+    ///
+    /// E.g. using `order_number` as key field means that a flood of failures for one order
+    /// won't hide the failures for a different one.
+    pub fn with_key_fields<I, F>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = F>,
+        F: Into<String>,
+    {
+        self.key_fields = fields.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// How often to look for idle buckets to evict (every minute, by default).
+    ///
+    /// A bucket is idle if no event went through it for a whole period of its rate limit.
+    pub fn with_eviction_interval(mut self, interval: Duration) -> Self {
+        self.eviction_interval = interval;
+        self
+    }
+
+    fn limit_for(&self, target: &str, level: &Level) -> Option<RateLimit> {
+        self.rules
+            .iter()
+            .filter(|rule| rule.matches(target, level))
+            .max_by_key(|rule| rule.specificity())
+            .map(|rule| rule.limit)
+            .or(self.default_limit)
+    }
+
+    fn bucket_key(&self, event: &Event<'_>) -> BucketKey {
+        let mut visitor = KeyFieldsVisitor {
+            names: &self.key_fields,
+            values: vec![None; self.key_fields.len()],
+        };
+        if !self.key_fields.is_empty() {
+            event.record(&mut visitor);
+        }
+        BucketKey {
+            callsite: event.metadata().callsite(),
+            field_values: visitor.values,
+        }
+    }
+
+    /// Try to take a token for an event, returning `false` if it must be suppressed.
+    fn admit(
+        &self,
+        key: BucketKey,
+        limit: RateLimit,
+        metadata: &'static Metadata<'static>,
+        parent: Option<Id>,
+        now: Instant,
+    ) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        let buckets = &mut *buckets;
+        let bucket = buckets
+            .buckets
+            .entry(key.clone())
+            .or_insert_with(|| Bucket::new(limit, metadata, now));
+        bucket.refill(now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return true;
+        }
+
+        bucket.suppressed += 1;
+        if bucket.reopens_at.is_none() {
+            let missing = Duration::from_secs_f64((1.0 - bucket.tokens) / limit.refill_rate());
+            bucket.reopens_at = Some(now + missing);
+            buckets
+                .reopening
+                .entry(now + missing)
+                .or_default()
+                .push(key.clone());
+        }
+        if bucket.suppressed_in != parent {
+            let previous = std::mem::replace(&mut bucket.suppressed_in, parent.clone());
+            if let Some(previous) = previous {
+                buckets.unindex(&previous, &key);
+            }
+            if let Some(parent) = parent {
+                buckets.suppressed_in.entry(parent).or_default().insert(key);
+            }
+        }
+        false
+    }
+
+    /// Take the summaries of the buckets that have reopened by `now`.
+    fn take_reopened(&self, now: Instant) -> Vec<Summary> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut summaries = Vec::new();
+        while let Some(entry) = buckets.reopening.first_entry() {
+            if *entry.key() > now {
+                break;
+            }
+            let (reopens_at, keys) = entry.remove_entry();
+            for key in keys {
+                // The bucket might have been evicted, or its summary emitted for another reason.
+                let current = buckets.buckets.get(&key).and_then(|b| b.reopens_at);
+                if current == Some(reopens_at) {
+                    summaries.extend(buckets.take_summary(&key));
+                }
+            }
+        }
+        summaries
+    }
+
+    /// Take the summaries of the events that were suppressed while inside the span `id`.
+    fn take_summaries_for(&self, id: &Id) -> Vec<Summary> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let keys = buckets.suppressed_in.remove(id).unwrap_or_default();
+        keys.iter()
+            .filter_map(|key| buckets.take_summary(key))
+            .collect()
+    }
+
+    /// Drop the buckets that have been idle for a while.
+    fn evict_idle_buckets(&self, now: Instant) {
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.saturating_duration_since(buckets.last_eviction) < self.eviction_interval {
+            return;
+        }
+        buckets.last_eviction = now;
+        // An idle bucket has refilled long ago: its summary (if any) has already been taken by
+        // `take_reopened`.
+        buckets.buckets.retain(|_, bucket| !bucket.is_idle(now));
+    }
+}
+
+impl<L> RateLimitLayer<L> {
+    /// Report to the wrapped layer how many events were suppressed for a callsite.
+    fn emit_summary<S>(&self, summary: Summary, ctx: Context<'_, S>)
+    where
+        S: Subscriber,
+        L: Layer<S>,
+    {
+        let Summary {
+            metadata: suppressed_metadata,
+            suppressed,
+            parent,
+        } = summary;
+        let metadata = summary_metadata(suppressed_metadata.level());
+        let fields = metadata.fields();
+        let message = format!("suppressed {suppressed} similar events");
+        let callsite = suppressed_metadata.name();
+        let values = [
+            (
+                &fields.field("message").unwrap(),
+                Some(&message.as_str() as &dyn Value),
+            ),
+            (
+                &fields.field("suppressed").unwrap(),
+                Some(&suppressed as &dyn Value),
+            ),
+            (
+                &fields.field("callsite").unwrap(),
+                Some(&callsite as &dyn Value),
+            ),
+        ];
+        let values = fields.value_set(&values);
+        self.inner
+            .on_event(&Event::new_child_of(parent, metadata, &values), ctx);
+    }
+}
+
+/// The span an event belongs to, whether it was set explicitly or taken from the context.
+fn event_parent<S: Subscriber>(event: &Event<'_>, ctx: &Context<'_, S>) -> Option<Id> {
+    if event.is_contextual() {
+        ctx.current_span().id().cloned()
+    } else {
+        event.parent().cloned()
+    }
+}
+
+impl<S, L> Layer<S> for RateLimitLayer<L>
+where
+    S: Subscriber,
+    L: Layer<S>,
+{
+    fn on_register_dispatch(&self, subscriber: &Dispatch) {
+        self.inner.on_register_dispatch(subscriber);
+    }
+
+    fn on_layer(&mut self, subscriber: &mut S) {
+        self.inner.on_layer(subscriber);
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.enabled(metadata, ctx)
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_new_span(attrs, id, ctx);
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.inner.max_level_hint()
+    }
+
+    fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        self.inner.on_record(span, values, ctx);
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        self.inner.on_follows_from(span, follows, ctx);
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, ctx: Context<'_, S>) -> bool {
+        self.inner.event_enabled(event, ctx)
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let now = Instant::now();
+        for summary in self.take_reopened(now) {
+            self.emit_summary(summary, ctx.clone());
+        }
+        self.evict_idle_buckets(now);
+
+        let metadata = event.metadata();
+        let Some(limit) = self.limit_for(metadata.target(), metadata.level()) else {
+            self.inner.on_event(event, ctx);
+            return;
+        };
+        let parent = event_parent(event, &ctx);
+        if self.admit(self.bucket_key(event), limit, metadata, parent, now) {
+            self.inner.on_event(event, ctx);
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_enter(id, ctx);
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.inner.on_exit(id, ctx);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        // Don't keep the summaries of the events suppressed in this span waiting for the next
+        // event: report them while the span is still around.
+        let mut summaries = self.take_summaries_for(&id);
+        summaries.extend(self.take_reopened(Instant::now()));
+        for summary in summaries {
+            self.emit_summary(summary, ctx.clone());
+        }
+        self.inner.on_close(id, ctx);
+    }
+
+    fn on_id_change(&self, old: &Id, new: &Id, ctx: Context<'_, S>) {
+        self.inner.on_id_change(old, new, ctx);
+    }
+
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        // Forward to the inner layer as well, otherwise `tracing-subscriber` can't tell if it
+        // has a per-layer filter attached to it.
+        if id == TypeId::of::<Self>() {
+            Some(self as *const _ as *const ())
+        } else {
+            self.inner.downcast_raw(id)
+        }
+    }
+}
+
+const SUMMARY_FIELDS: &[&str] = &["message", "suppressed", "callsite"];
+
+/// Summary events are emitted directly to the wrapped layer, bypassing the dispatcher: we need
+/// a static callsite for each level to build them.
+macro_rules! summary_callsite {
+    ($callsite:ident, $metadata:ident, $level:expr) => {
+        static $callsite: DefaultCallsite = DefaultCallsite::new(&$metadata);
+        static $metadata: Metadata<'static> = Metadata::new(
+            "rate limit summary",
+            SUMMARY_TARGET,
+            $level,
+            Some(file!()),
+            Some(line!()),
+            Some(module_path!()),
+            FieldSet::new(SUMMARY_FIELDS, Identifier(&$callsite)),
+            Kind::EVENT,
+        );
+    };
+}
+
+summary_callsite!(ERROR_CALLSITE, ERROR_METADATA, Level::ERROR);
+summary_callsite!(WARN_CALLSITE, WARN_METADATA, Level::WARN);
+summary_callsite!(INFO_CALLSITE, INFO_METADATA, Level::INFO);
+summary_callsite!(DEBUG_CALLSITE, DEBUG_METADATA, Level::DEBUG);
+summary_callsite!(TRACE_CALLSITE, TRACE_METADATA, Level::TRACE);
+
+/// Summary events are emitted at the same level of the events that were suppressed.
+fn summary_metadata(level: &Level) -> &'static Metadata<'static> {
+    match *level {
+        Level::ERROR => ERROR_CALLSITE.metadata(),
+        Level::WARN => WARN_CALLSITE.metadata(),
+        Level::INFO => INFO_CALLSITE.metadata(),
+        Level::DEBUG => DEBUG_CALLSITE.metadata(),
+        Level::TRACE => TRACE_CALLSITE.metadata(),
+    }
+}
+
+/// Extract the values of the key fields from an event, using their debug representation.
+struct KeyFieldsVisitor<'a> {
+    names: &'a [String],
+    values: Vec<Option<String>>,
+}
+
+impl Visit for KeyFieldsVisitor<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if let Some(i) = self.names.iter().position(|n| n == field.name()) {
+            self.values[i] = Some(value.to_owned());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if let Some(i) = self.names.iter().position(|n| n == field.name()) {
+            self.values[i] = Some(format!("{value:?}"));
+        }
+    }
+}
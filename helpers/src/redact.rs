@@ -0,0 +1,465 @@
+//! Redaction of sensitive fields, before they reach any output.
+//!
+//! Order numbers, customer emails and the like have no business being stored verbatim in our
+//! logs or in our tracing backend.
+//! [`Redactor`] wraps a fully assembled subscriber (e.g. a `Registry` with a JSON layer and
+//! a `tracing-opentelemetry` layer on top) and rewrites the fields of every span and event
+//! before forwarding them, so that no layer ever gets to see the original values.
+//! `log` records bridged via `tracing-log` go through the same subscriber, so they are covered
+//! as well.
+//!
+//! Fields can be marked as sensitive in two ways:
+//!
+//! - by name, using a pattern (e.g. `order_number` or `customer.*`);
+//! - by value, wrapping them in [`Sensitive`] when recording them.
+use crate::event_fields::{with_value_set, FieldValue};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::any::TypeId;
+use std::cell::RefCell;
+use std::fmt::{Debug, Display, Formatter};
+use std::sync::Arc;
+use tracing::field::{Field, FieldSet, Visit};
+use tracing::metadata::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::subscriber::Interest;
+use tracing::{Dispatch, Event, Metadata, Subscriber};
+use tracing_core::span::Current;
+use tracing_subscriber::registry::{LookupSpan, SpanData};
+
+/// What gets written out in place of a masked value.
+pub const MASK: &str = "[REDACTED]";
+
+/// How to get rid of a sensitive value.
+#[derive(Clone, Debug)]
+pub enum Redaction {
+    /// Replace the value with [`MASK`].
+    Mask,
+    /// Remove the field altogether.
+    Drop,
+    /// Replace the value with a keyed hash (HMAC-SHA256) of its textual representation.
+    ///
+    /// Equal values get the same hash, so you can still correlate events that refer to
+    /// the same entity without knowing what the entity is.
+    Hash(HashKey),
+}
+
+impl Redaction {
+    /// A shorthand for `Redaction::Hash(HashKey::new(key))`.
+    pub fn keyed_hash(key: impl AsRef<[u8]>) -> Self {
+        Self::Hash(HashKey::new(key))
+    }
+
+    /// The replacement for `raw`, or `None` if the value should be dropped.
+    fn apply(&self, raw: &str) -> Option<String> {
+        match self {
+            Redaction::Mask => Some(MASK.to_owned()),
+            Redaction::Drop => None,
+            Redaction::Hash(key) => Some(key.hash(raw)),
+        }
+    }
+}
+
+/// The secret key used to compute [`Redaction::Hash`]es.
+#[derive(Clone)]
+pub struct HashKey(Arc<[u8]>);
+
+impl HashKey {
+    pub fn new(key: impl AsRef<[u8]>) -> Self {
+        Self(key.as_ref().into())
+    }
+
+    fn hash(&self, raw: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.0).expect("HMAC accepts keys of any size");
+        mac.update(raw.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        // 128 bits are more than enough to avoid collisions in our telemetry data.
+        let hex: String = digest[..16].iter().map(|b| format!("{b:02x}")).collect();
+        format!("hmac-sha256:{hex}")
+    }
+}
+
+impl Debug for HashKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // Don't leak the key in our own debug output.
+        f.write_str("HashKey(..)")
+    }
+}
+
+/// A field name pattern, where `*` matches any sequence of characters.
+#[derive(Clone, Debug)]
+struct FieldPattern(String);
+
+impl FieldPattern {
+    fn matches(&self, name: &str) -> bool {
+        glob_match(self.0.as_bytes(), name.as_bytes())
+    }
+}
+
+fn glob_match(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|i| glob_match(rest, &name[i..])),
+        Some((c, rest)) => name
+            .split_first()
+            .is_some_and(|(n, name)| n == c && glob_match(rest, name)),
+    }
+}
+
+/// Wrap a value to mark it as sensitive, regardless of the name of the field it's recorded in.
+///
+/// It can be recorded using either its `Display` (`%Sensitive(value)`) or its `Debug`
+/// (`?Sensitive(value)`) representation, and it can be used in format strings too
+/// (e.g. in the message of a `log` record).
+///
+/// When formatted outside of a [`Redactor`], it always renders as [`MASK`]: forgetting to
+/// install the redactor doesn't leak anything.
+#[derive(Clone, Copy)]
+pub struct Sensitive<T>(pub T);
+
+thread_local! {
+    /// The redaction to apply to [`Sensitive`] values formatted on this thread, if a [`Redactor`]
+    /// is currently processing a field.
+    static ACTIVE_REDACTION: RefCell<Option<ActiveRedaction>> = const { RefCell::new(None) };
+}
+
+struct ActiveRedaction {
+    redaction: Redaction,
+    /// Set to `true` as soon as a [`Sensitive`] value is formatted.
+    sensitive: bool,
+}
+
+impl<T> Sensitive<T> {
+    fn write(&self, f: &mut Formatter<'_>, raw: impl FnOnce() -> String) -> std::fmt::Result {
+        let redaction = ACTIVE_REDACTION.with(|active| {
+            let mut active = active.borrow_mut();
+            let active = active.as_mut()?;
+            active.sensitive = true;
+            Some(active.redaction.clone())
+        });
+        let replacement = redaction.and_then(|r| r.apply(&raw()));
+        f.write_str(replacement.as_deref().unwrap_or(MASK))
+    }
+}
+
+impl<T: Display> Display for Sensitive<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.write(f, || self.0.to_string())
+    }
+}
+
+impl<T: Debug> Debug for Sensitive<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.write(f, || format!("{:?}", self.0))
+    }
+}
+
+/// Redacts sensitive fields before they reach the subscriber it wraps.
+///
+/// ```rust
+/// use helpers::redact::{Redaction, Redactor};
+/// use tracing_subscriber::layer::SubscriberExt;
+/// use tracing_subscriber::util::SubscriberInitExt;
+///
+/// let subscriber = tracing_subscriber::Registry::default()
+///     .with(tracing_subscriber::fmt::layer().json());
+/// Redactor::new()
+///     .redact("order_number", Redaction::Mask)
+///     .redact("customer.*", Redaction::keyed_hash("a very secret key"))
+///     .wrap(subscriber)
+///     .init();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Redactor {
+    rules: Vec<(FieldPattern, Redaction)>,
+    sensitive_values: Redaction,
+}
+
+impl Default for Redactor {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            sensitive_values: Redaction::Mask,
+        }
+    }
+}
+
+impl Redactor {
+    /// A redactor that masks [`Sensitive`] values and leaves every other field alone.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Redact all fields whose name matches `pattern`.
+    ///
+    /// If a field matches more than one pattern, the first one that was registered wins.
+    pub fn redact(mut self, pattern: impl Into<String>, redaction: Redaction) -> Self {
+        self.rules.push((FieldPattern(pattern.into()), redaction));
+        self
+    }
+
+    /// How to redact values wrapped in [`Sensitive`]. They are masked by default.
+    ///
+    /// [`Redaction::Drop`] drops the whole field the sensitive value is part of.
+    pub fn redact_sensitive_values(mut self, redaction: Redaction) -> Self {
+        self.sensitive_values = redaction;
+        self
+    }
+
+    /// Redact all the spans and events flowing into `subscriber`.
+    ///
+    /// Make sure to wrap the subscriber once it has been fully assembled, i.e. after all its
+    /// layers have been added.
+    pub fn wrap<S>(self, subscriber: S) -> Redacted<S>
+    where
+        S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+    {
+        Redacted {
+            inner: subscriber,
+            redactor: self,
+        }
+    }
+
+    fn rule_for(&self, field: &Field) -> Option<&Redaction> {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches(field.name()))
+            .map(|(_, redaction)| redaction)
+    }
+
+    /// Collect the values in `values`, redacting them as needed.
+    ///
+    /// Returns `None` if nothing had to be redacted, so that the original values can be
+    /// forwarded untouched.
+    fn redact_values(
+        &self,
+        fields: &FieldSet,
+        values: impl FnOnce(&mut dyn Visit),
+    ) -> Option<Vec<Option<FieldValue>>> {
+        let mut visitor = RedactingVisitor {
+            redactor: self,
+            fields: fields.iter().collect(),
+            values: fields.iter().map(|_| None).collect(),
+            redacted: false,
+        };
+        values(&mut visitor);
+        visitor.redacted.then_some(visitor.values)
+    }
+}
+
+struct RedactingVisitor<'a> {
+    redactor: &'a Redactor,
+    fields: Vec<Field>,
+    values: Vec<Option<FieldValue>>,
+    redacted: bool,
+}
+
+impl RedactingVisitor<'_> {
+    /// Store the value of a field, unless a name-based rule applies to it.
+    fn store(&mut self, field: &Field, value: FieldValue, raw: impl FnOnce() -> String) {
+        let value = match self.redactor.rule_for(field) {
+            Some(redaction) => {
+                self.redacted = true;
+                redaction.apply(&raw()).map(FieldValue::Debug)
+            }
+            None => Some(value),
+        };
+        self.set(field, value);
+    }
+
+    fn set(&mut self, field: &Field, value: Option<FieldValue>) {
+        if let Some(i) = self.fields.iter().position(|f| f == field) {
+            self.values[i] = value;
+        }
+    }
+}
+
+impl Visit for RedactingVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.store(field, FieldValue::F64(value), || value.to_string());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.store(field, FieldValue::I64(value), || value.to_string());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.store(field, FieldValue::U64(value), || value.to_string());
+    }
+
+    fn record_i128(&mut self, field: &Field, value: i128) {
+        self.store(field, FieldValue::I128(value), || value.to_string());
+    }
+
+    fn record_u128(&mut self, field: &Field, value: u128) {
+        self.store(field, FieldValue::U128(value), || value.to_string());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.store(field, FieldValue::Bool(value), || value.to_string());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.store(field, FieldValue::Str(value.to_owned()), || {
+            value.to_owned()
+        });
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        // Formatting the value is the only way to find out if there is a `Sensitive` value
+        // hiding inside it.
+        ACTIVE_REDACTION.with(|active| {
+            *active.borrow_mut() = Some(ActiveRedaction {
+                redaction: self.redactor.sensitive_values.clone(),
+                sensitive: false,
+            })
+        });
+        let rendered = format!("{value:?}");
+        let sensitive = ACTIVE_REDACTION
+            .with(|active| active.borrow_mut().take())
+            .is_some_and(|active| active.sensitive);
+
+        if sensitive {
+            self.redacted = true;
+            if matches!(self.redactor.sensitive_values, Redaction::Drop) {
+                self.set(field, None);
+                return;
+            }
+        }
+        let raw = rendered.clone();
+        self.store(field, FieldValue::Debug(rendered), || raw);
+    }
+}
+
+/// A subscriber that redacts sensitive fields before forwarding them to the one it wraps.
+///
+/// Built via [`Redactor::wrap`].
+#[derive(Debug)]
+pub struct Redacted<S> {
+    inner: S,
+    redactor: Redactor,
+}
+
+impl<S> Subscriber for Redacted<S>
+where
+    S: Subscriber + for<'lookup> LookupSpan<'lookup>,
+{
+    fn on_register_dispatch(&self, subscriber: &Dispatch) {
+        self.inner.on_register_dispatch(subscriber)
+    }
+
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        self.inner.register_callsite(metadata)
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        self.inner.max_level_hint()
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let metadata = span.metadata();
+        let Some(values) = self
+            .redactor
+            .redact_values(metadata.fields(), |visitor| span.record(visitor))
+        else {
+            return self.inner.new_span(span);
+        };
+        with_value_set(metadata.fields(), &values, |values| {
+            let attributes = if span.is_root() {
+                Attributes::new_root(metadata, values)
+            } else if let Some(parent) = span.parent() {
+                Attributes::child_of(parent.clone(), metadata, values)
+            } else {
+                Attributes::new(metadata, values)
+            };
+            self.inner.new_span(&attributes)
+        })
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let Some(fields) = self.inner.span_data(span).map(|s| s.metadata().fields()) else {
+            return self.inner.record(span, values);
+        };
+        let Some(redacted) = self
+            .redactor
+            .redact_values(fields, |visitor| values.record(visitor))
+        else {
+            return self.inner.record(span, values);
+        };
+        with_value_set(fields, &redacted, |values| {
+            self.inner.record(span, &Record::new(values))
+        })
+    }
+
+    fn record_follows_from(&self, span: &Id, follows: &Id) {
+        self.inner.record_follows_from(span, follows)
+    }
+
+    fn event_enabled(&self, event: &Event<'_>) -> bool {
+        self.inner.event_enabled(event)
+    }
+
+    fn event(&self, event: &Event<'_>) {
+        let metadata = event.metadata();
+        let Some(values) = self
+            .redactor
+            .redact_values(metadata.fields(), |visitor| event.record(visitor))
+        else {
+            return self.inner.event(event);
+        };
+        with_value_set(metadata.fields(), &values, |values| {
+            let event = if event.is_contextual() {
+                Event::new(metadata, values)
+            } else {
+                Event::new_child_of(event.parent().cloned(), metadata, values)
+            };
+            self.inner.event(&event)
+        })
+    }
+
+    fn enter(&self, span: &Id) {
+        self.inner.enter(span)
+    }
+
+    fn exit(&self, span: &Id) {
+        self.inner.exit(span)
+    }
+
+    fn clone_span(&self, id: &Id) -> Id {
+        self.inner.clone_span(id)
+    }
+
+    fn try_close(&self, id: Id) -> bool {
+        self.inner.try_close(id)
+    }
+
+    fn current_span(&self) -> Current {
+        self.inner.current_span()
+    }
+
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        // `tracing-opentelemetry` relies on downcasting to find its own layer, so we must
+        // forward these requests to the inner subscriber.
+        if id == TypeId::of::<Self>() {
+            Some(self as *const Self as *const ())
+        } else {
+            self.inner.downcast_raw(id)
+        }
+    }
+}
+
+impl<'lookup, S> LookupSpan<'lookup> for Redacted<S>
+where
+    S: LookupSpan<'lookup>,
+{
+    type Data = S::Data;
+
+    fn span_data(&'lookup self, id: &Id) -> Option<Self::Data> {
+        self.inner.span_data(id)
+    }
+}
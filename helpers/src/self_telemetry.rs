@@ -0,0 +1,206 @@
+//! Telemetry about the telemetry pipeline itself.
+//!
+//! When the backend is unreachable or slow, the batch span processor keeps going: exports fail
+//! or time out, the queue fills up and new spans are dropped. All we get is an opaque message
+//! on stderr, for every single failure.
+//! This module turns those failures into `metrics`:
+//!
+//! - [`MeteredSpanExporter`] wraps an exporter and records the size of each batch, how long
+//!   exporting it took and whether it failed (or was abandoned because it took too long);
+//! - [`install_error_handler`] replaces the global OpenTelemetry error handler to count the
+//!   spans and log records dropped because the queue was full.
+//!
+//! Both also write what went wrong to a [`FallbackLog`]: a local log that doesn't repeat the
+//! same message over and over when the backend stays down.
+use opentelemetry::global::Error;
+use opentelemetry::logs::LogError;
+use opentelemetry::trace::TraceError;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry_sdk::runtime::TrySendError;
+use opentelemetry_sdk::Resource;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
+
+/// Counter of the span exports that didn't succeed, labelled by `reason`: `error` if the
+/// exporter reported a failure, `timeout` if the export was abandoned by the processor.
+pub const EXPORT_FAILURES_COUNTER: &str = "otel_span_export_failures";
+/// Counter of the spans dropped because the export queue was full.
+pub const DROPPED_SPANS_COUNTER: &str = "otel_dropped_spans";
+/// Counter of the log records dropped because the export queue was full.
+pub const DROPPED_LOG_RECORDS_COUNTER: &str = "otel_dropped_log_records";
+/// Histogram of the time spent exporting each batch of spans, in seconds, failures included.
+pub const EXPORT_DURATION_HISTOGRAM: &str = "otel_span_export_duration_seconds";
+/// Histogram of the number of spans in each exported batch.
+pub const BATCH_SIZE_HISTOGRAM: &str = "otel_span_export_batch_size";
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+/// A [`SpanExporter`] recording metrics about the exports of the exporter it wraps.
+pub struct MeteredSpanExporter<E> {
+    inner: E,
+    fallback: FallbackLog,
+}
+
+impl<E: SpanExporter> MeteredSpanExporter<E> {
+    /// Export failures are reported to `fallback`, on top of being counted.
+    pub fn new(inner: E, fallback: FallbackLog) -> Self {
+        Self { inner, fallback }
+    }
+}
+
+impl<E: Debug> Debug for MeteredSpanExporter<E> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("MeteredSpanExporter")
+            .field(&self.inner)
+            .finish()
+    }
+}
+
+impl<E: SpanExporter> SpanExporter for MeteredSpanExporter<E> {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<ExportResult> {
+        metrics::histogram!(BATCH_SIZE_HISTOGRAM).record(batch.len() as f64);
+        let export = self.inner.export(batch);
+        let mut attempt = ExportAttempt {
+            start: Instant::now(),
+            fallback: self.fallback.clone(),
+            done: false,
+        };
+        Box::pin(async move {
+            let result = export.await;
+            attempt.finish(&result);
+            result
+        })
+    }
+
+    fn shutdown(&mut self) {
+        self.inner.shutdown()
+    }
+
+    fn force_flush(&mut self) -> BoxFuture<ExportResult> {
+        self.inner.force_flush()
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.inner.set_resource(resource)
+    }
+}
+
+/// Records the outcome of an export, including the ones that never complete: the batch
+/// processor drops the export future when it times out.
+struct ExportAttempt {
+    start: Instant,
+    fallback: FallbackLog,
+    done: bool,
+}
+
+impl ExportAttempt {
+    fn finish(&mut self, result: &ExportResult) {
+        self.done = true;
+        metrics::histogram!(EXPORT_DURATION_HISTOGRAM).record(self.start.elapsed());
+        if let Err(e) = result {
+            metrics::counter!(EXPORT_FAILURES_COUNTER, "reason" => "error").increment(1);
+            self.fallback
+                .report(&format!("Failed to export spans: {e}"));
+        }
+    }
+}
+
+impl Drop for ExportAttempt {
+    fn drop(&mut self) {
+        if !self.done {
+            metrics::histogram!(EXPORT_DURATION_HISTOGRAM).record(self.start.elapsed());
+            metrics::counter!(EXPORT_FAILURES_COUNTER, "reason" => "timeout").increment(1);
+            self.fallback.report("Failed to export spans: timed out");
+        }
+    }
+}
+
+/// Replace the global OpenTelemetry error handler: dropped spans and log records are counted,
+/// and every error is written to `fallback`.
+///
+/// Span export failures are left to [`MeteredSpanExporter`], which has already reported them.
+pub fn install_error_handler(fallback: FallbackLog) {
+    let handler = move |error: Error| match &error {
+        Error::Trace(TraceError::ExportFailed(_) | TraceError::ExportTimedOut(_)) => {}
+        Error::Trace(TraceError::Other(e)) if is_queue_full(e.as_ref()) => {
+            metrics::counter!(DROPPED_SPANS_COUNTER).increment(1);
+            fallback.report("Dropped a span: the export queue is full");
+        }
+        Error::Log(LogError::Other(e)) if is_queue_full(e.as_ref()) => {
+            metrics::counter!(DROPPED_LOG_RECORDS_COUNTER).increment(1);
+            fallback.report("Dropped a log record: the export queue is full");
+        }
+        _ => fallback.report(&format!("OpenTelemetry error: {error}")),
+    };
+    if let Err(e) = opentelemetry::global::set_error_handler(handler) {
+        eprintln!("Failed to install the OpenTelemetry error handler: {e}");
+    }
+}
+
+fn is_queue_full(error: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    matches!(
+        error.downcast_ref::<TrySendError>(),
+        Some(TrySendError::ChannelFull)
+    )
+}
+
+/// A local log for the problems of the telemetry pipeline, for when the backend can't hear
+/// about them.
+///
+/// Messages are deduplicated: a message is written the first time it's reported, then again
+/// with a count after 10, 100, 1000... occurrences.
+#[derive(Clone)]
+pub struct FallbackLog {
+    state: Arc<Mutex<FallbackState>>,
+}
+
+struct FallbackState {
+    writer: Box<dyn Write + Send>,
+    seen: HashMap<String, u64>,
+}
+
+impl FallbackLog {
+    pub fn new(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(FallbackState {
+                writer: Box::new(writer),
+                seen: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Write to stderr.
+    ///
+    /// All the logs returned by this function share their deduplication state.
+    pub fn stderr() -> Self {
+        static STDERR: OnceLock<FallbackLog> = OnceLock::new();
+        STDERR.get_or_init(|| Self::new(std::io::stderr())).clone()
+    }
+
+    pub fn report(&self, message: &str) {
+        // Don't panic in the middle of an export because another thread did.
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let count = state.seen.entry(message.to_owned()).or_default();
+        *count += 1;
+        let line = match *count {
+            1 => format!("{message}\n"),
+            n if is_power_of_ten(n) => format!("{message} ({n} times so far)\n"),
+            _ => return,
+        };
+        // There's nowhere left to report a failure to write to the fallback log.
+        let _ = state.writer.write_all(line.as_bytes());
+        let _ = state.writer.flush();
+    }
+}
+
+fn is_power_of_ten(mut n: u64) -> bool {
+    while n >= 10 && n.is_multiple_of(10) {
+        n /= 10;
+    }
+    n == 1
+}
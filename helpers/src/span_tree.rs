@@ -0,0 +1,282 @@
+//! Render the causal graph of a group of spans, once they are all closed.
+//!
+//! Parent-child relationships only tell half of the story when work crosses thread boundaries:
+//! a span created on a worker thread may have no parent at all, and only be linked to the span
+//! that caused it through `follows_from`.
+//! [`SpanTreeLayer`] keeps track of both kinds of links. When the last span of a connected
+//! group closes, it renders the whole group as an indented text tree and/or as a
+//! [Graphviz](https://graphviz.org/) DOT graph, with the duration of each span and the threads
+//! it ran on:
+//!
+//! ```text
+//! spawner [main] 1.2ms
+//!     spawned1 [worker-1] 150.3µs
+//!     ~> spawned2 [worker-2] 98.1µs
+//! ```
+//!
+//! Children are listed under their parent; spans that have no parent are listed, with a `~>`
+//! marker, under the first span they follow from. Any other `follows_from` link is mentioned
+//! next to the span.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+/// A `tracing` layer that renders groups of linked spans when they are done.
+#[derive(Default)]
+pub struct SpanTreeLayer {
+    graph: Mutex<Graph>,
+    text_output: Option<Mutex<Box<dyn Write + Send>>>,
+    dot_output: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+#[derive(Default)]
+struct Graph {
+    nodes: BTreeMap<usize, Node>,
+    next_id: usize,
+}
+
+struct Node {
+    name: &'static str,
+    parent: Option<usize>,
+    follows_from: Vec<usize>,
+    created_on: String,
+    /// The threads the span was entered on, in order of first appearance.
+    entered_on: Vec<String>,
+    created_at: Instant,
+    closed_at: Option<Instant>,
+}
+
+/// Our own span id, stored in the span extensions.
+///
+/// Unlike `tracing::Id`s, they are never reused: a `follows_from` link to a closed span stays
+/// valid.
+struct NodeId(usize);
+
+impl SpanTreeLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write the indented text rendering of each group of spans to `writer`.
+    pub fn with_text_output(mut self, writer: impl Write + Send + 'static) -> Self {
+        self.text_output = Some(Mutex::new(Box::new(writer)));
+        self
+    }
+
+    /// Write the DOT rendering of each group of spans to `writer`, one `digraph` per group.
+    pub fn with_dot_output(mut self, writer: impl Write + Send + 'static) -> Self {
+        self.dot_output = Some(Mutex::new(Box::new(writer)));
+        self
+    }
+
+    fn write(output: &Option<Mutex<Box<dyn Write + Send>>>, rendered: impl FnOnce() -> String) {
+        if let Some(output) = output {
+            let mut output = output.lock().unwrap();
+            // A test helper has nowhere to report I/O errors to.
+            let _ = output.write_all(rendered().as_bytes());
+            let _ = output.flush();
+        }
+    }
+}
+
+fn node_id<S>(id: &Id, ctx: &Context<'_, S>) -> Option<usize>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let span = ctx.span(id)?;
+    let extensions = span.extensions();
+    extensions.get::<NodeId>().map(|id| id.0)
+}
+
+fn thread_name() -> String {
+    std::thread::current()
+        .name()
+        .unwrap_or("<unnamed>")
+        .to_owned()
+}
+
+impl<S> tracing_subscriber::Layer<S> for SpanTreeLayer
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let parent = span.parent().and_then(|p| node_id(&p.id(), &ctx));
+        let mut graph = self.graph.lock().unwrap();
+        let node_id = graph.next_id;
+        graph.next_id += 1;
+        graph.nodes.insert(
+            node_id,
+            Node {
+                name: span.name(),
+                parent,
+                follows_from: Vec::new(),
+                created_on: thread_name(),
+                entered_on: Vec::new(),
+                created_at: Instant::now(),
+                closed_at: None,
+            },
+        );
+        drop(graph);
+        span.extensions_mut().insert(NodeId(node_id));
+    }
+
+    fn on_follows_from(&self, span: &Id, follows: &Id, ctx: Context<'_, S>) {
+        let (Some(span), Some(follows)) = (node_id(span, &ctx), node_id(follows, &ctx)) else {
+            return;
+        };
+        if let Some(node) = self.graph.lock().unwrap().nodes.get_mut(&span) {
+            node.follows_from.push(follows);
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let Some(id) = node_id(id, &ctx) else {
+            return;
+        };
+        if let Some(node) = self.graph.lock().unwrap().nodes.get_mut(&id) {
+            let thread = thread_name();
+            if !node.entered_on.contains(&thread) {
+                node.entered_on.push(thread);
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(id) = node_id(&id, &ctx) else {
+            return;
+        };
+        let mut graph = self.graph.lock().unwrap();
+        let Some(node) = graph.nodes.get_mut(&id) else {
+            return;
+        };
+        node.closed_at = Some(Instant::now());
+
+        let group = graph.group(id);
+        if group.iter().any(|id| graph.nodes[id].closed_at.is_none()) {
+            return;
+        }
+        let group = Graph {
+            nodes: group
+                .into_iter()
+                .map(|id| (id, graph.nodes.remove(&id).unwrap()))
+                .collect(),
+            next_id: 0,
+        };
+        drop(graph);
+        Self::write(&self.text_output, || group.text());
+        Self::write(&self.dot_output, || group.dot());
+    }
+}
+
+impl Graph {
+    /// The ids of all the spans linked to `id`, directly or indirectly, in either direction.
+    fn group(&self, id: usize) -> BTreeSet<usize> {
+        let mut group = BTreeSet::from([id]);
+        let mut to_visit = vec![id];
+        while let Some(id) = to_visit.pop() {
+            let node = &self.nodes[&id];
+            let linked_from = self
+                .nodes
+                .iter()
+                .filter(|(_, n)| n.parent == Some(id) || n.follows_from.contains(&id))
+                .map(|(id, _)| *id);
+            let linked_to = node.parent.iter().chain(&node.follows_from).copied();
+            for linked in linked_from.chain(linked_to).collect::<Vec<_>>() {
+                if self.nodes.contains_key(&linked) && group.insert(linked) {
+                    to_visit.push(linked);
+                }
+            }
+        }
+        group
+    }
+
+    /// The span a node is listed under in the text rendering, if any.
+    fn tree_parent(&self, node: &Node) -> Option<usize> {
+        node.parent.or_else(|| node.follows_from.first().copied())
+    }
+
+    fn text(&self) -> String {
+        let mut rendered = String::new();
+        for (id, node) in &self.nodes {
+            if self.tree_parent(node).is_none() {
+                self.render_text(*id, 0, &mut rendered);
+            }
+        }
+        rendered
+    }
+
+    fn render_text(&self, id: usize, depth: usize, rendered: &mut String) {
+        let node = &self.nodes[&id];
+        let (marker, other_links) = match node.parent {
+            Some(_) => ("", &node.follows_from[..]),
+            None if node.follows_from.is_empty() => ("", &[][..]),
+            None => ("~> ", &node.follows_from[1..]),
+        };
+        let _ = write!(
+            rendered,
+            "{:indent$}{marker}{} [{}] {:.1?}",
+            "",
+            node.name,
+            node.threads().join(", "),
+            node.duration(),
+            indent = depth * 4
+        );
+        if !other_links.is_empty() {
+            let names: Vec<_> = other_links.iter().map(|id| self.nodes[id].name).collect();
+            let _ = write!(rendered, " (follows from: {})", names.join(", "));
+        }
+        rendered.push('\n');
+        for (child, child_node) in &self.nodes {
+            if self.tree_parent(child_node) == Some(id) {
+                self.render_text(*child, depth + 1, rendered);
+            }
+        }
+    }
+
+    fn dot(&self) -> String {
+        let mut rendered = String::from("digraph spans {\n");
+        for (id, node) in &self.nodes {
+            let _ = writeln!(
+                rendered,
+                "    span{id} [label=\"{}\\n{}\\n{:.1?}\"];",
+                node.name.escape_default(),
+                node.threads().join(", ").escape_default(),
+                node.duration()
+            );
+        }
+        for (id, node) in &self.nodes {
+            if let Some(parent) = node.parent {
+                let _ = writeln!(rendered, "    span{parent} -> span{id};");
+            }
+            for follows in &node.follows_from {
+                let _ = writeln!(
+                    rendered,
+                    "    span{follows} -> span{id} [style=dashed, label=\"follows_from\"];"
+                );
+            }
+        }
+        rendered.push_str("}\n");
+        rendered
+    }
+}
+
+impl Node {
+    fn threads(&self) -> &[String] {
+        if self.entered_on.is_empty() {
+            std::slice::from_ref(&self.created_on)
+        } else {
+            &self.entered_on
+        }
+    }
+
+    fn duration(&self) -> std::time::Duration {
+        self.closed_at.unwrap_or(self.created_at) - self.created_at
+    }
+}
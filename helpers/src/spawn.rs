@@ -0,0 +1,137 @@
+//! Spawn helpers that carry the current span over to the new task or thread.
+//!
+//! Spans don't cross `std::thread::spawn` or `tokio::spawn` on their own: the new work starts
+//! with no current span, so whatever it records ends up disconnected from the code that
+//! spawned it.
+//! The wrappers in this module capture the current span (and the current subscriber) when
+//! they are called. [`spawn`], [`spawn_blocking`], [`spawn_in_set`] and [`spawn_thread`] take
+//! the same arguments as the functions they wrap, and run the new work inside the current
+//! span. Their `_with` variants take a [`Link`], to run it inside a new span that follows from
+//! the current one instead.
+use std::future::Future;
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::instrument::{Instrument, WithSubscriber};
+use tracing::{Dispatch, Span};
+
+/// How the spawned work relates to the span that was current when it was spawned.
+#[derive(Debug, Clone, Default)]
+pub enum Link {
+    /// Run the new work inside the current span.
+    /// Spans created by the new work are children of the current span.
+    #[default]
+    Parent,
+    /// Run the new work inside the given span, marked as following from the current one.
+    ///
+    /// Use it when the new work outlives (or is otherwise independent from) the code that
+    /// spawned it. The span is usually a root span, i.e. created with `parent: None`.
+    FollowsFrom(Span),
+}
+
+impl Link {
+    /// The span the spawned work should run in.
+    fn into_span(self) -> Span {
+        let current = Span::current();
+        match self {
+            Link::Parent => current,
+            Link::FollowsFrom(span) => {
+                span.follows_from(&current);
+                span
+            }
+        }
+    }
+}
+
+fn current_dispatch() -> Dispatch {
+    tracing::dispatcher::get_default(Dispatch::clone)
+}
+
+/// Wraps `f` so that it runs inside the span picked by `link`, with the current subscriber as
+/// its default.
+fn in_span<F, R>(link: Link, f: F) -> impl FnOnce() -> R
+where
+    F: FnOnce() -> R,
+{
+    let span = link.into_span();
+    let dispatch = current_dispatch();
+    move || tracing::dispatcher::with_default(&dispatch, || span.in_scope(f))
+}
+
+/// [`tokio::spawn`], running `future` inside the current span.
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn_with(Link::Parent, future)
+}
+
+/// [`tokio::spawn`], carrying over the current span as specified by `link`.
+pub fn spawn_with<F>(link: Link, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(
+        future
+            .instrument(link.into_span())
+            .with_subscriber(current_dispatch()),
+    )
+}
+
+/// [`tokio::task::spawn_blocking`], running `f` inside the current span.
+pub fn spawn_blocking<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    spawn_blocking_with(Link::Parent, f)
+}
+
+/// [`tokio::task::spawn_blocking`], carrying over the current span as specified by `link`.
+pub fn spawn_blocking_with<F, R>(link: Link, f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    tokio::task::spawn_blocking(in_span(link, f))
+}
+
+/// [`JoinSet::spawn`], running `future` inside the current span.
+pub fn spawn_in_set<F>(join_set: &mut JoinSet<F::Output>, future: F)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn_in_set_with(join_set, Link::Parent, future)
+}
+
+/// [`JoinSet::spawn`], carrying over the current span as specified by `link`.
+pub fn spawn_in_set_with<F>(join_set: &mut JoinSet<F::Output>, link: Link, future: F)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    join_set.spawn(
+        future
+            .instrument(link.into_span())
+            .with_subscriber(current_dispatch()),
+    );
+}
+
+/// [`std::thread::spawn`], running `f` inside the current span.
+pub fn spawn_thread<F, T>(f: F) -> std::thread::JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_thread_with(Link::Parent, f)
+}
+
+/// [`std::thread::spawn`], carrying over the current span as specified by `link`.
+pub fn spawn_thread_with<F, T>(link: Link, f: F) -> std::thread::JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    std::thread::spawn(in_span(link, f))
+}
@@ -0,0 +1,320 @@
+//! Record raw span and event data in memory, to assert on it in tests.
+//!
+//! Asserting on formatted output couples tests to the formatter: a different timestamp format,
+//! field order or span separator and the test breaks, even though the instrumentation is
+//! unchanged.
+//! [`SpanStore`] keeps everything a layer gets to see (names, levels, fields and how they
+//! change over time, parents, `follows_from` links, enter/exit/close timestamps, events and the
+//! spans they were emitted in) and exposes it through a small query API.
+use crate::event_fields::{FieldValue, FieldVisitor};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::field::Field;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+/// A handle to the recorded spans and events.
+///
+/// It's cheap to clone: all clones share the same records.
+#[derive(Clone, Default)]
+pub struct SpanStore {
+    records: Arc<Mutex<Records>>,
+}
+
+/// The layer feeding a [`SpanStore`], created with [`SpanStore::layer`].
+pub struct StoreLayer {
+    store: SpanStore,
+}
+
+#[derive(Default)]
+struct Records {
+    spans: Vec<SpanRecord>,
+    events: Vec<EventRecord>,
+}
+
+/// Everything we know about a span.
+#[derive(Debug, Clone)]
+pub struct SpanRecord {
+    /// A unique identifier for the span, assigned in creation order.
+    ///
+    /// Unlike `tracing::Id`s, they are never reused once the span is closed.
+    pub id: usize,
+    pub name: &'static str,
+    pub target: &'static str,
+    pub level: Level,
+    pub parent: Option<usize>,
+    pub follows_from: Vec<usize>,
+    /// Every value recorded on the span, in the order they were recorded.
+    pub field_updates: Vec<(Instant, &'static str, FieldValue)>,
+    pub created_at: Instant,
+    pub entered_at: Vec<Instant>,
+    pub exited_at: Vec<Instant>,
+    pub closed_at: Option<Instant>,
+}
+
+/// Everything we know about an event.
+#[derive(Debug, Clone)]
+pub struct EventRecord {
+    pub name: &'static str,
+    pub target: &'static str,
+    pub level: Level,
+    pub fields: Vec<(&'static str, FieldValue)>,
+    /// The ids of the spans the event was emitted in, from the root.
+    pub ancestry: Vec<usize>,
+    pub timestamp: Instant,
+}
+
+impl SpanRecord {
+    /// The latest value recorded for the field, if any.
+    pub fn field(&self, name: &str) -> Option<&FieldValue> {
+        self.field_history(name).last().copied()
+    }
+
+    /// All the values recorded for the field, oldest first.
+    pub fn field_history(&self, name: &str) -> Vec<&FieldValue> {
+        self.field_updates
+            .iter()
+            .filter(|(_, field, _)| *field == name)
+            .map(|(_, _, value)| value)
+            .collect()
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed_at.is_some()
+    }
+}
+
+impl EventRecord {
+    pub fn field(&self, name: &str) -> Option<&FieldValue> {
+        self.fields
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn message(&self) -> Option<String> {
+        self.field("message").map(ToString::to_string)
+    }
+}
+
+impl SpanStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A layer recording into this store.
+    pub fn layer(&self) -> StoreLayer {
+        StoreLayer {
+            store: self.clone(),
+        }
+    }
+
+    /// All the spans recorded so far, in creation order.
+    pub fn spans(&self) -> Vec<SpanRecord> {
+        self.records.lock().unwrap().spans.clone()
+    }
+
+    /// All the spans with the given name, in creation order.
+    pub fn spans_named(&self, name: &str) -> Vec<SpanRecord> {
+        self.spans_where(|span| span.name == name)
+    }
+
+    /// All the spans matching `predicate`, in creation order.
+    pub fn spans_where(&self, predicate: impl Fn(&SpanRecord) -> bool) -> Vec<SpanRecord> {
+        let records = self.records.lock().unwrap();
+        records
+            .spans
+            .iter()
+            .filter(|span| predicate(span))
+            .cloned()
+            .collect()
+    }
+
+    /// The only span with the given name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there isn't exactly one span with that name.
+    pub fn span(&self, name: &str) -> SpanRecord {
+        let mut spans = self.spans_named(name);
+        assert_eq!(
+            spans.len(),
+            1,
+            "Expected exactly one `{name}` span, found {}",
+            spans.len()
+        );
+        spans.remove(0)
+    }
+
+    /// Look up a span by its [`SpanRecord::id`].
+    pub fn span_by_id(&self, id: usize) -> SpanRecord {
+        self.records.lock().unwrap().spans[id].clone()
+    }
+
+    /// The spans whose parent is `span`, in creation order.
+    pub fn children(&self, span: &SpanRecord) -> Vec<SpanRecord> {
+        self.spans_where(|s| s.parent == Some(span.id))
+    }
+
+    /// The spans from the root down to `span`'s parent.
+    pub fn ancestors(&self, span: &SpanRecord) -> Vec<SpanRecord> {
+        let records = self.records.lock().unwrap();
+        let mut ancestors = Vec::new();
+        let mut parent = span.parent;
+        while let Some(id) = parent {
+            let span = &records.spans[id];
+            ancestors.push(span.clone());
+            parent = span.parent;
+        }
+        ancestors.reverse();
+        ancestors
+    }
+
+    /// All the events recorded so far, in emission order.
+    pub fn events(&self) -> Vec<EventRecord> {
+        self.records.lock().unwrap().events.clone()
+    }
+
+    /// All the events matching `predicate`, in emission order.
+    pub fn events_where(&self, predicate: impl Fn(&EventRecord) -> bool) -> Vec<EventRecord> {
+        let records = self.records.lock().unwrap();
+        records
+            .events
+            .iter()
+            .filter(|event| predicate(event))
+            .cloned()
+            .collect()
+    }
+
+    /// The events emitted while `span` was the current span (or one of its ancestors).
+    pub fn events_in(&self, span: &SpanRecord) -> Vec<EventRecord> {
+        self.events_where(|event| event.ancestry.contains(&span.id))
+    }
+
+    fn update<S, F>(&self, id: &Id, ctx: &Context<'_, S>, f: F)
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+        F: FnOnce(&mut SpanRecord),
+    {
+        let Some(index) = record_id(id, ctx) else {
+            return;
+        };
+        f(&mut self.records.lock().unwrap().spans[index]);
+    }
+}
+
+/// Our own span id, stored in the span extensions.
+struct RecordId(usize);
+
+fn record_id<S>(id: &Id, ctx: &Context<'_, S>) -> Option<usize>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    let span = ctx.span(id)?;
+    let extensions = span.extensions();
+    extensions.get::<RecordId>().map(|id| id.0)
+}
+
+impl<S> tracing_subscriber::Layer<S> for StoreLayer
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let parent = span.parent().and_then(|p| record_id(&p.id(), &ctx));
+        let now = Instant::now();
+        let mut fields = Vec::new();
+        attrs.record(&mut field_updates(now, &mut fields));
+
+        let metadata: &'static Metadata<'static> = attrs.metadata();
+        let mut records = self.store.records.lock().unwrap();
+        let index = records.spans.len();
+        records.spans.push(SpanRecord {
+            id: index,
+            name: metadata.name(),
+            target: metadata.target(),
+            level: *metadata.level(),
+            parent,
+            follows_from: Vec::new(),
+            field_updates: fields,
+            created_at: now,
+            entered_at: Vec::new(),
+            exited_at: Vec::new(),
+            closed_at: None,
+        });
+        drop(records);
+        span.extensions_mut().insert(RecordId(index));
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let mut fields = Vec::new();
+        values.record(&mut field_updates(Instant::now(), &mut fields));
+        self.store
+            .update(id, &ctx, |span| span.field_updates.extend(fields));
+    }
+
+    fn on_follows_from(&self, id: &Id, follows: &Id, ctx: Context<'_, S>) {
+        if let Some(follows) = record_id(follows, &ctx) {
+            self.store
+                .update(id, &ctx, |span| span.follows_from.push(follows));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let now = Instant::now();
+        let ancestry = ctx
+            .event_scope(event)
+            .map(|scope| {
+                scope
+                    .from_root()
+                    .filter_map(|span| record_id(&span.id(), &ctx))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut fields = Vec::new();
+        event.record(&mut field_updates(now, &mut fields));
+
+        let metadata = event.metadata();
+        self.store.records.lock().unwrap().events.push(EventRecord {
+            name: metadata.name(),
+            target: metadata.target(),
+            level: *metadata.level(),
+            fields: fields
+                .into_iter()
+                .map(|(_, name, value)| (name, value))
+                .collect(),
+            ancestry,
+            timestamp: now,
+        });
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let now = Instant::now();
+        self.store
+            .update(id, &ctx, |span| span.entered_at.push(now));
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        let now = Instant::now();
+        self.store.update(id, &ctx, |span| span.exited_at.push(now));
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let now = Instant::now();
+        self.store
+            .update(&id, &ctx, |span| span.closed_at = Some(now));
+    }
+}
+
+/// Record fields as updates made at `timestamp`, in the order they're visited.
+fn field_updates<'a>(
+    timestamp: Instant,
+    updates: &'a mut Vec<(Instant, &'static str, FieldValue)>,
+) -> FieldVisitor<impl FnMut(&Field, FieldValue) + 'a> {
+    FieldVisitor(move |field: &Field, value: FieldValue| {
+        updates.push((timestamp, field.name(), value))
+    })
+}
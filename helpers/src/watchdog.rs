@@ -0,0 +1,243 @@
+//! Warn about spans that have been open for too long.
+//!
+//! When a `retrieve order` call hangs, the span never closes, so the layers that report on
+//! span completion (e.g. `FmtSpan::CLOSE`, or an OTLP exporter) have nothing to report.
+//! [`WatchdogLayer`] keeps track of the spans that are currently open, and a background thread
+//! emits a `WARN` event for each span that has been open for longer than its threshold,
+//! repeating it with an exponential backoff for as long as the span stays open.
+//!
+//! It also keeps an `open_spans` gauge, labelled with the span name, up to date.
+use crate::event_fields::{FieldValue, FieldVisitor};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tracing::field::Field;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Dispatch, Subscriber};
+use tracing_core::dispatcher::WeakDispatch;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+
+/// The target used for the warnings emitted by the watchdog.
+pub const WARNING_TARGET: &str = module_path!();
+
+/// The name of the gauge tracking the number of open spans, labelled by `span_name`.
+pub const OPEN_SPANS_GAUGE: &str = "open_spans";
+
+/// A `tracing` layer that keeps an eye on spans that stay open for too long.
+///
+/// The background thread is stopped when the [`WatchdogGuard`] returned by
+/// [`WatchdogLayer::new`] is dropped.
+pub struct WatchdogLayer {
+    state: Arc<State>,
+    default_threshold: Duration,
+    thresholds: HashMap<&'static str, Duration>,
+}
+
+/// Stops the watchdog thread when dropped.
+///
+/// Warnings are dispatched to the global default subscriber, unless told otherwise with
+/// [`WatchdogGuard::dispatch_to`].
+#[must_use = "The watchdog thread is stopped when the guard is dropped"]
+pub struct WatchdogGuard {
+    state: Arc<State>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct State {
+    open_spans: Mutex<HashMap<Id, OpenSpan>>,
+    /// The subscriber to dispatch the warnings to, if it isn't the global default.
+    /// It's a weak reference: the subscriber owns the layer, which owns the state.
+    dispatch: Mutex<Option<WeakDispatch>>,
+    max_backoff: Mutex<Duration>,
+    stopped: AtomicBool,
+}
+
+struct OpenSpan {
+    name: &'static str,
+    /// The names of the ancestors of the span, from the root, followed by its own.
+    ancestry: String,
+    fields: String,
+    opened_at: Instant,
+    next_warning_at: Instant,
+    /// How long to wait before the next warning, after the upcoming one.
+    backoff: Duration,
+}
+
+impl WatchdogLayer {
+    /// Warn about spans that have been open for longer than `default_threshold`, checking
+    /// every `check_interval`.
+    pub fn new(default_threshold: Duration, check_interval: Duration) -> (Self, WatchdogGuard) {
+        let state = Arc::new(State {
+            max_backoff: Mutex::new(Duration::from_secs(60)),
+            ..Default::default()
+        });
+        let state2 = state.clone();
+        let thread = std::thread::Builder::new()
+            .name("span-watchdog".into())
+            .spawn(move || {
+                while !state2.stopped.load(Ordering::Relaxed) {
+                    std::thread::park_timeout(check_interval);
+                    state2.check(Instant::now());
+                }
+            })
+            .expect("Failed to spawn the watchdog thread");
+        let layer = Self {
+            state: state.clone(),
+            default_threshold,
+            thresholds: HashMap::new(),
+        };
+        let guard = WatchdogGuard {
+            state,
+            thread: Some(thread),
+        };
+        (layer, guard)
+    }
+
+    /// Use a different threshold for spans with the given name.
+    pub fn with_threshold(mut self, span_name: &'static str, threshold: Duration) -> Self {
+        self.thresholds.insert(span_name, threshold);
+        self
+    }
+
+    /// The interval between two warnings about the same span starts at the span threshold and
+    /// doubles every time, up to `max_backoff` (one minute by default).
+    pub fn with_max_backoff(self, max_backoff: Duration) -> Self {
+        *self.state.max_backoff.lock().unwrap() = max_backoff;
+        self
+    }
+}
+
+impl WatchdogGuard {
+    /// Dispatch the warnings to `dispatch` rather than to the global default subscriber.
+    ///
+    /// That's necessary when the subscriber the layer belongs to is only set as the default
+    /// for some threads (e.g. with `tracing::subscriber::with_default`), since the warnings are
+    /// emitted from a background thread.
+    pub fn dispatch_to(&self, dispatch: &Dispatch) {
+        *self.state.dispatch.lock().unwrap() = Some(dispatch.downgrade());
+    }
+}
+
+impl Drop for WatchdogGuard {
+    fn drop(&mut self) {
+        self.state.stopped.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+impl State {
+    /// Emit a warning for every span that is past its deadline.
+    fn check(&self, now: Instant) {
+        let dispatch = match self.dispatch.lock().unwrap().as_ref() {
+            Some(weak) => match weak.upgrade() {
+                Some(dispatch) => dispatch,
+                // The subscriber is gone, there is no one to warn.
+                None => return,
+            },
+            None => tracing::dispatcher::get_default(Dispatch::clone),
+        };
+        let max_backoff = *self.max_backoff.lock().unwrap();
+        let mut overdue = Vec::new();
+        for span in self.open_spans.lock().unwrap().values_mut() {
+            if span.next_warning_at > now {
+                continue;
+            }
+            overdue.push((
+                span.name,
+                span.ancestry.clone(),
+                span.fields.clone(),
+                now - span.opened_at,
+            ));
+            span.next_warning_at = now + span.backoff;
+            span.backoff = (span.backoff * 2).min(max_backoff);
+        }
+        // The lock is released before dispatching, in case another layer opens or closes spans
+        // while handling the warnings.
+        tracing::dispatcher::with_default(&dispatch, || {
+            for (name, ancestry, fields, open_for) in overdue {
+                tracing::warn!(
+                    target: WARNING_TARGET,
+                    parent: None,
+                    span_name = name,
+                    ancestry,
+                    fields,
+                    open_for_ms = open_for.as_millis() as u64,
+                    "span has been open for too long"
+                );
+            }
+        });
+    }
+}
+
+impl<S> tracing_subscriber::Layer<S> for WatchdogLayer
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).unwrap();
+        let ancestry: Vec<_> = span.scope().from_root().map(|s| s.name()).collect();
+        let mut fields = String::new();
+        attrs.record(&mut fields_visitor(&mut fields));
+
+        let name = span.name();
+        let threshold = self
+            .thresholds
+            .get(name)
+            .copied()
+            .unwrap_or(self.default_threshold);
+        let now = Instant::now();
+        self.state.open_spans.lock().unwrap().insert(
+            id.clone(),
+            OpenSpan {
+                name,
+                ancestry: ancestry.join(" > "),
+                fields,
+                opened_at: now,
+                next_warning_at: now + threshold,
+                backoff: threshold,
+            },
+        );
+        metrics::gauge!(OPEN_SPANS_GAUGE, "span_name" => name).increment(1);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+        if let Some(span) = self.state.open_spans.lock().unwrap().get_mut(id) {
+            values.record(&mut fields_visitor(&mut span.fields));
+        }
+    }
+
+    fn on_close(&self, id: Id, _ctx: Context<'_, S>) {
+        if let Some(span) = self.state.open_spans.lock().unwrap().remove(&id) {
+            metrics::gauge!(OPEN_SPANS_GAUGE, "span_name" => span.name).decrement(1);
+        }
+    }
+}
+
+/// Render fields as `key=value` pairs, separated by spaces, appending them to `rendered`.
+///
+/// Values are rendered the way `tracing-subscriber` does, e.g. strings are quoted.
+fn fields_visitor(rendered: &mut String) -> FieldVisitor<impl FnMut(&Field, FieldValue) + '_> {
+    FieldVisitor(move |field: &Field, value: FieldValue| {
+        if !rendered.is_empty() {
+            rendered.push(' ');
+        }
+        let _ = match value {
+            FieldValue::Bool(v) => write!(rendered, "{}={v:?}", field.name()),
+            FieldValue::I64(v) => write!(rendered, "{}={v:?}", field.name()),
+            FieldValue::U64(v) => write!(rendered, "{}={v:?}", field.name()),
+            FieldValue::I128(v) => write!(rendered, "{}={v:?}", field.name()),
+            FieldValue::U128(v) => write!(rendered, "{}={v:?}", field.name()),
+            FieldValue::F64(v) => write!(rendered, "{}={v:?}", field.name()),
+            FieldValue::Str(v) => write!(rendered, "{}={v:?}", field.name()),
+            FieldValue::Debug(v) => write!(rendered, "{}={v}", field.name()),
+        };
+    })
+}
@@ -0,0 +1,60 @@
+use helpers::admin::{AdminServer, ReloadableFilter, INVALID_DIRECTIVE};
+use helpers::MockWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+fn get(url: &str) -> String {
+    ureq::get(url).call().unwrap().into_string().unwrap()
+}
+
+#[test]
+fn filters_can_be_changed_through_the_admin_server() {
+    let writer = MockWriter::new();
+    let writer2 = writer.clone();
+    let (filter, handle) = reload::Layer::new(EnvFilter::new("info"));
+    let subscriber = Registry::default().with(
+        tracing_subscriber::fmt::layer()
+            .without_time()
+            .with_ansi(false)
+            .with_target(false)
+            .with_writer(move || writer2.clone())
+            .with_filter(filter),
+    );
+    let admin = AdminServer::start([("fmt", ReloadableFilter::new(handle))]).unwrap();
+    let fmt_filter = format!("{}/filters/fmt", admin.url());
+
+    tracing::subscriber::with_default(subscriber, || {
+        assert_eq!(get(&fmt_filter), "info");
+        tracing::debug!("Retrieving order");
+
+        let response = ureq::put(&fmt_filter).send_string("debug").unwrap();
+        assert_eq!(response.into_string().unwrap(), "debug");
+        assert_eq!(get(&fmt_filter), "debug");
+        tracing::debug!("Retrieving order again");
+
+        // Invalid directives are rejected, with the parse error (whose wording depends on the
+        // version of `tracing-subscriber`).
+        let Err(ureq::Error::Status(400, response)) =
+            ureq::put(&fmt_filter).send_string("helpers=loud")
+        else {
+            panic!("An invalid directive should be rejected with a 400");
+        };
+        let error = response.into_string().unwrap();
+        assert!(error.starts_with(INVALID_DIRECTIVE), "{error}");
+        // The previous directive is still in place.
+        assert_eq!(get(&fmt_filter), "debug");
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    let mut log_lines = logging_output.lines();
+    log_lines
+        .next_some()
+        .assert_eq("DEBUG Retrieving order again");
+    log_lines.end();
+
+    let Err(ureq::Error::Status(404, _)) =
+        ureq::get(&format!("{}/filters/json", admin.url())).call()
+    else {
+        panic!("Unknown filters should return a 404");
+    };
+}
@@ -0,0 +1,156 @@
+use helpers::chrome::ChromeLayer;
+use helpers::MockWriter;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use tokio::task::yield_now;
+use tracing::{Dispatch, Instrument, Span};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+async fn do_something(id: u16) {
+    yield_now().await;
+    Span::current().record("caller_id", id);
+    yield_now().await;
+}
+
+fn trace_events(writer: &MockWriter) -> Vec<Value> {
+    let output = writer.log_output().unwrap();
+    let trace: Value = serde_json::from_str(output.text()).unwrap();
+    trace["traceEvents"].as_array().unwrap().clone()
+}
+
+fn phase<'a>(events: &'a [Value], ph: &'a str) -> impl Iterator<Item = &'a Value> {
+    events.iter().filter(move |e| e["ph"] == ph)
+}
+
+#[test]
+fn interleaved_futures() {
+    let writer = MockWriter::new();
+    let (layer, guard) = ChromeLayer::new(writer.clone());
+    let dispatch = Dispatch::new(Registry::default().with(layer.with_task_tracks(true)));
+    let n_futures = 10;
+
+    // The registry releases spans through the default dispatcher of the thread they are exited
+    // on, so every worker thread must have it installed, not just the one spawning the tasks.
+    let worker_dispatch = dispatch.clone();
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .on_thread_start(move || {
+            std::mem::forget(tracing::dispatcher::set_default(&worker_dispatch));
+        })
+        .build()
+        .unwrap();
+    tracing::dispatcher::with_default(&dispatch, || {
+        runtime.block_on(async {
+            let mut join_set = tokio::task::JoinSet::new();
+            for i in 0..n_futures {
+                let span = tracing::info_span!("Task", caller_id = tracing::field::Empty);
+                join_set.spawn(do_something(i).instrument(span));
+            }
+            while join_set.join_next().await.is_some() {}
+        })
+    });
+    drop(runtime);
+    guard.finish().unwrap();
+
+    let events = trace_events(&writer);
+
+    // Each future is polled three times, once for each `yield_now().await` plus the final one:
+    // we expect (at least) three slices per task, on whatever thread polled it.
+    // `Instrumented` also enters its span when it is dropped.
+    let begins: Vec<_> = phase(&events, "B").collect();
+    let ends: Vec<_> = phase(&events, "E").collect();
+    assert!(begins.len() >= 3 * n_futures as usize);
+    assert_eq!(ends.len(), begins.len());
+    // Slices must be balanced on every thread track.
+    let mut open_per_thread: HashMap<u64, i64> = HashMap::new();
+    for event in events.iter().filter(|e| e["ph"] == "B" || e["ph"] == "E") {
+        let open = open_per_thread
+            .entry(event["tid"].as_u64().unwrap())
+            .or_default();
+        *open += if event["ph"] == "B" { 1 } else { -1 };
+        assert!(*open >= 0, "Unbalanced slice: {event}");
+    }
+    assert!(open_per_thread.values().all(|open| *open == 0));
+    // Every thread track has a name.
+    let named_threads: HashSet<_> = phase(&events, "M")
+        .map(|e| e["tid"].as_u64().unwrap())
+        .collect();
+    assert_eq!(named_threads, open_per_thread.keys().copied().collect());
+
+    // Each task gets its own track, and the `caller_id` shows up once the task is done.
+    let task_ids: HashSet<_> = phase(&events, "b").map(|e| e["id"].clone()).collect();
+    assert_eq!(task_ids.len(), n_futures as usize);
+    let mut caller_ids: Vec<_> = phase(&events, "e")
+        .map(|e| e["args"]["caller_id"].as_u64().unwrap())
+        .collect();
+    caller_ids.sort();
+    assert_eq!(caller_ids, (0..n_futures as u64).collect::<Vec<_>>());
+}
+
+#[test]
+fn follows_from_becomes_a_flow_arrow() {
+    let writer = MockWriter::new();
+    let (layer, guard) = ChromeLayer::new(writer.clone());
+    let subscriber = Registry::default().with(layer);
+
+    tracing::subscriber::with_default(subscriber, || {
+        let spawner = tracing::info_span!("spawner");
+        let spawned = spawner.in_scope(|| {
+            let spawned = tracing::info_span!(parent: None, "spawned");
+            spawned.follows_from(Span::current());
+            tracing::info!("Spawning");
+            spawned
+        });
+        std::thread::scope(|s| {
+            s.spawn(|| spawned.in_scope(|| {}));
+        });
+    });
+    drop(guard);
+
+    let events = trace_events(&writer);
+    let start = phase(&events, "s").next().unwrap();
+    let finish = phase(&events, "f").next().unwrap();
+    assert_eq!(start["id"], finish["id"]);
+    assert_eq!(start["name"], "spawner");
+    assert_eq!(finish["name"], "spawned");
+    assert_eq!(finish["bp"], "e");
+    // The arrow goes across threads.
+    assert_ne!(start["tid"], finish["tid"]);
+
+    let instant = phase(&events, "i").next().unwrap();
+    assert_eq!(instant["name"], "Spawning");
+}
+
+#[test]
+fn task_tracks_are_not_reused() {
+    let writer = MockWriter::new();
+    let (layer, guard) = ChromeLayer::new(writer.clone());
+    let subscriber = Registry::default().with(layer.with_task_tracks(true));
+    // The registry reuses the slot of a closed span for the next one, bumping a generation
+    // counter in its id. The counter wraps around after 8192 reuses, giving us the same id.
+    let n_tasks = 8193;
+
+    tracing::subscriber::with_default(subscriber, || {
+        for _ in 0..n_tasks {
+            tracing::info_span!("Task").in_scope(|| {
+                tracing::info_span!("step").in_scope(|| tracing::info!("Working"));
+            });
+        }
+    });
+    drop(guard);
+
+    let events = trace_events(&writer);
+    let mut tracks: HashMap<String, Vec<&str>> = HashMap::new();
+    for event in events.iter().filter(|e| e["id"].is_string()) {
+        tracks
+            .entry(event["id"].as_str().unwrap().to_owned())
+            .or_default()
+            .push(event["ph"].as_str().unwrap());
+    }
+    assert_eq!(tracks.len(), n_tasks);
+    // Each task track holds its root, its descendants and their events.
+    for phases in tracks.values() {
+        assert_eq!(phases, &["b", "b", "n", "e", "e"]);
+    }
+}
@@ -0,0 +1,88 @@
+use helpers::completeness::FieldCompletenessLayer;
+use helpers::MockWriter;
+use tracing::field::Empty;
+use tracing::{instrument, Span};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+#[instrument("retrieve order", skip_all, fields(outcome, error.msg = Empty))]
+fn get_order_details(order_number: u64) -> Result<u64, String> {
+    if order_number.is_multiple_of(4) {
+        // Oops, we forgot to record `outcome` on this code path.
+        let error = "Failed to talk to the database".to_string();
+        Span::current().record("error.msg", &error);
+        Err(error)
+    } else {
+        Span::current().record("outcome", "success");
+        Ok(999)
+    }
+}
+
+fn fmt_writer() -> (MockWriter, impl tracing_subscriber::Layer<Registry>) {
+    let writer = MockWriter::new();
+    let writer2 = writer.clone();
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(move || writer2.clone())
+        .compact()
+        .with_ansi(false)
+        .without_time();
+    (writer, layer)
+}
+
+#[test]
+fn unrecorded_fields_are_reported() {
+    let (writer, fmt_layer) = fmt_writer();
+    let subscriber = Registry::default()
+        .with(fmt_layer)
+        .with(FieldCompletenessLayer::new());
+
+    tracing::subscriber::with_default(subscriber, || {
+        get_order_details(1).unwrap();
+        get_order_details(4).unwrap_err();
+    });
+
+    let output = writer.log_output().unwrap();
+    let mut lines = output.lines();
+    // The first call never records `error.msg`...
+    let diagnostic = lines.next_some();
+    helpers::assert_regex!(
+        r#"WARN helpers::completeness: declared field was never recorded span="retrieve order" field="error.msg" callsite="helpers/tests/completeness.rs:\d+""#,
+        diagnostic.text()
+    );
+    // ...and the second one never records `outcome`.
+    let diagnostic = lines.next_some();
+    helpers::assert_regex!(
+        r#"span="retrieve order" field="outcome" callsite="helpers/tests/completeness.rs:\d+""#,
+        diagnostic.text()
+    );
+    lines.end();
+}
+
+#[test]
+fn fields_can_be_allowed_to_stay_empty() {
+    let (writer, fmt_layer) = fmt_writer();
+    let subscriber = Registry::default()
+        .with(fmt_layer)
+        .with(FieldCompletenessLayer::new().allow_unrecorded("error.*"));
+
+    tracing::subscriber::with_default(subscriber, || {
+        get_order_details(1).unwrap();
+    });
+
+    let output = writer.log_output().unwrap();
+    assert_eq!(output.text(), "");
+}
+
+#[test]
+#[should_panic(expected = "The `outcome` field of the `retrieve order` span")]
+fn strict_mode_panics() {
+    let subscriber = Registry::default().with(
+        FieldCompletenessLayer::new()
+            .strict()
+            .allow_unrecorded("error.*"),
+    );
+
+    tracing::subscriber::with_default(subscriber, || {
+        get_order_details(4).unwrap_err();
+    });
+}
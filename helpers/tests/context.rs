@@ -0,0 +1,176 @@
+use helpers::context::ContextLayer;
+use helpers::log_bridge::TracingLogger;
+use helpers::otlp::{attribute, MockCollector};
+use helpers::MockWriter;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::runtime;
+use serde_json::json;
+use tracing::instrument;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{Layer, Registry};
+
+#[instrument(
+    "process total price",
+    skip_all,
+    fields(request_id = "req-1", customer_id = 7)
+)]
+fn get_total(order_numbers: &[u64]) -> u64 {
+    let total = order_numbers.iter().map(|n| get_order_details(*n)).sum();
+    tracing::info!(total, "Computed total");
+    total
+}
+
+#[instrument("retrieve order")]
+fn get_order_details(order_number: u64) -> u64 {
+    tracing::debug!("Retrieved order");
+    if order_number == 2 {
+        // A child span can override an inherited field, for its own descendants.
+        let _span = tracing::info_span!("retry", customer_id = 8).entered();
+        tracing::warn!("Retrying");
+    }
+    order_number * 100
+}
+
+fn context_layer<L>(inner: L) -> ContextLayer<L> {
+    ContextLayer::new(inner)
+        .inherit("request_id")
+        .inherit("customer_id")
+}
+
+#[test]
+fn compact_output_includes_inherited_fields() {
+    let writer = MockWriter::new();
+    let writer2 = writer.clone();
+    let fmt = tracing_subscriber::fmt::layer()
+        .compact()
+        .without_time()
+        .with_ansi(false)
+        .with_target(false)
+        .with_writer(move || writer2.clone());
+    let subscriber = Registry::default().with(context_layer(fmt));
+
+    tracing::subscriber::with_default(subscriber, || {
+        get_total(&[1, 2]);
+        tracing::info!("Done");
+    });
+
+    let output = writer.log_output().unwrap();
+    let mut lines = output.lines();
+    lines.next_some().assert_eq(
+        "DEBUG process total price:retrieve order: Retrieved order request_id=\"req-1\" customer_id=7 \
+        request_id=\"req-1\" customer_id=7 order_number=1 request_id=\"req-1\" customer_id=7",
+    );
+    lines.next_some();
+    lines
+        .next_some()
+        .assert_regex_match(r#"^ WARN .*:retry: Retrying request_id="req-1" customer_id=8 "#);
+    lines.next_some().assert_regex_match(
+        r#"^ INFO process total price: Computed total total=300 request_id="req-1" customer_id=7"#,
+    );
+    lines.next_some().assert_eq(" INFO Done");
+    lines.end();
+}
+
+#[test]
+fn json_events_include_inherited_fields() {
+    let writer = MockWriter::new();
+    let writer2 = writer.clone();
+    let fmt = tracing_subscriber::fmt::layer()
+        .json()
+        .with_writer(move || writer2.clone());
+    let subscriber = Registry::default().with(context_layer(fmt));
+
+    tracing::subscriber::with_default(subscriber, || {
+        get_total(&[2]);
+    });
+
+    let output = writer.log_output().unwrap();
+    let mut lines = output.lines();
+    lines.next_some().assert_json_include(json!({
+        "fields": {"message": "Retrieved order", "request_id": "req-1", "customer_id": 7},
+        "span": {"name": "retrieve order", "order_number": 2, "request_id": "req-1"},
+    }));
+    lines.next_some().assert_json_include(json!({
+        "fields": {"message": "Retrying", "request_id": "req-1", "customer_id": 8},
+        "span": {"name": "retry", "request_id": "req-1", "customer_id": 8},
+    }));
+    lines.next_some().assert_json_include(json!({
+        "fields": {"message": "Computed total", "request_id": "req-1", "customer_id": 7},
+    }));
+    lines.end();
+}
+
+#[test]
+fn bridged_log_records_include_inherited_fields() {
+    TracingLogger::new().init().unwrap();
+    let writer = MockWriter::new();
+    let writer2 = writer.clone();
+    let fmt = tracing_subscriber::fmt::layer()
+        .json()
+        .with_writer(move || writer2.clone());
+    let subscriber = Registry::default().with(context_layer(fmt));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let _span = tracing::info_span!("process total price", request_id = "req-2").entered();
+        log::info!(order_number = 3; "Retrieved order");
+    });
+
+    let output = writer.log_output().unwrap();
+    let mut lines = output.lines();
+    lines.next_some().assert_json_include(json!({
+        "fields": {"message": "Retrieved order", "order_number": 3, "request_id": "req-2"},
+    }));
+    lines.end();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn otlp_spans_and_events_include_inherited_fields() {
+    let collector = MockCollector::start().await;
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(collector.endpoint()),
+        )
+        .install_batch(runtime::Tokio)
+        .unwrap();
+    let otel = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer("context"))
+        .boxed();
+    let subscriber = Registry::default().with(context_layer(otel));
+
+    tracing::subscriber::with_default(subscriber, || {
+        get_total(&[2]);
+    });
+    tokio::task::spawn_blocking(move || provider.shutdown())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let spans = collector.spans();
+    let span = |name: &str| spans.iter().find(|s| s.name == name).unwrap();
+    let order = span("retrieve order");
+    assert_eq!(
+        attribute(&order.attributes, "request_id").as_deref(),
+        Some("req-1")
+    );
+    assert_eq!(
+        attribute(&order.attributes, "customer_id").as_deref(),
+        Some("7")
+    );
+    assert_eq!(
+        attribute(&order.events[0].attributes, "request_id").as_deref(),
+        Some("req-1")
+    );
+    let retry = span("retry");
+    assert_eq!(
+        attribute(&retry.attributes, "customer_id").as_deref(),
+        Some("8")
+    );
+    assert_eq!(
+        attribute(&retry.events[0].attributes, "customer_id").as_deref(),
+        Some("8")
+    );
+}
@@ -0,0 +1,111 @@
+use helpers::ecs::EcsFormat;
+use helpers::event_fields::SpanStartLayer;
+use helpers::MockWriter;
+use serde_json::json;
+use std::time::Duration;
+use tracing::field::Empty;
+use tracing_subscriber::fmt::format::{FmtSpan, JsonFields};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+/// Record errors the way `01_error_trait` does.
+#[tracing::instrument(
+    "my_task",
+    fields(error.msg = Empty, error.source_chain = Empty)
+)]
+fn telemetry_wrapper(order_number: u64) -> Result<(), String> {
+    tracing::info!("Retrieving order");
+    let span = tracing::Span::current();
+    span.record(
+        "error.msg",
+        "The service is temporarily unavailable, please try again later",
+    );
+    span.record(
+        "error.source_chain",
+        "Failed to execute: `SELECT * FROM table`\nFailed to connect to 127.0.0.1:4236",
+    );
+    Err("Failed to talk to the database".into())
+}
+
+#[test]
+fn events_and_span_closures_are_ecs_documents() {
+    let writer = MockWriter::new();
+    let writer2 = writer.clone();
+    let fmt = tracing_subscriber::fmt::layer()
+        .with_span_events(FmtSpan::CLOSE)
+        .fmt_fields(JsonFields::new())
+        .event_format(EcsFormat::new().with_service_name("orders-api"))
+        .with_writer(move || writer2.clone());
+
+    tracing::subscriber::with_default(Registry::default().with(SpanStartLayer).with(fmt), || {
+        telemetry_wrapper(4).unwrap_err();
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    let mut log_lines = logging_output.lines();
+    let event = log_lines.next_some();
+    event.assert_json_include(json!({
+        "log.level": "info",
+        "message": "Retrieving order",
+        "ecs.version": "8.11.0",
+        "log.logger": "ecs",
+        "log.origin.file.name": "helpers/tests/ecs.rs",
+        "service.name": "orders-api",
+        "order_number": 4,
+    }));
+    let event: serde_json::Value = serde_json::from_str(event.text()).unwrap();
+    helpers::assert_regex!(
+        r"^\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}\.\d{3}Z$",
+        event["@timestamp"].as_str().unwrap()
+    );
+    assert!(event.get("event.duration").is_none());
+
+    let close = log_lines.next_some();
+    close.assert_json_include(json!({
+        "log.level": "info",
+        "message": "close",
+        "order_number": 4,
+        "error.message": "The service is temporarily unavailable, please try again later",
+        "error.stack_trace": "Failed to execute: `SELECT * FROM table`\nFailed to connect to 127.0.0.1:4236",
+    }));
+    let close: serde_json::Value = serde_json::from_str(close.text()).unwrap();
+    assert!(close["event.duration"].as_u64().unwrap() > 0);
+    assert!(close.get("error.msg").is_none());
+    assert!(close.get("error.source_chain").is_none());
+    log_lines.end();
+}
+
+#[test]
+fn span_durations_are_measured_by_the_span_start_layer() {
+    let writer = MockWriter::new();
+    let writer2 = writer.clone();
+    let fmt = tracing_subscriber::fmt::layer()
+        .with_span_events(FmtSpan::CLOSE)
+        .fmt_fields(JsonFields::new())
+        .event_format(EcsFormat::new())
+        .with_writer(move || writer2.clone());
+
+    tracing::subscriber::with_default(Registry::default().with(SpanStartLayer).with(fmt), || {
+        let _span = tracing::info_span!("slow").entered();
+        std::thread::sleep(Duration::from_millis(15));
+    });
+    // Without it, there is nothing to compute the duration from.
+    let other_writer = MockWriter::new();
+    let other_writer2 = other_writer.clone();
+    let fmt = tracing_subscriber::fmt::layer()
+        .with_span_events(FmtSpan::CLOSE)
+        .fmt_fields(JsonFields::new())
+        .event_format(EcsFormat::new())
+        .with_writer(move || other_writer2.clone());
+    tracing::subscriber::with_default(Registry::default().with(fmt), || {
+        let _span = tracing::info_span!("slow").entered();
+    });
+
+    let output = writer.log_output().unwrap();
+    let close: serde_json::Value = serde_json::from_str(output.lines().next_some().text()).unwrap();
+    assert!(close["event.duration"].as_u64().unwrap() >= 15_000_000);
+    let output = other_writer.log_output().unwrap();
+    let close: serde_json::Value = serde_json::from_str(output.lines().next_some().text()).unwrap();
+    assert!(close.get("event.duration").is_none());
+    assert!(close.get("time.busy").is_none());
+}
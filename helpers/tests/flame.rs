@@ -0,0 +1,130 @@
+use helpers::flame::{FlameLayer, Weight};
+use helpers::MockWriter;
+use std::time::Duration;
+use tracing::instrument;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+#[instrument("process total price", skip_all)]
+fn get_total(order_numbers: &[u64]) -> u64 {
+    order_numbers.iter().map(|n| get_order_details(*n)).sum()
+}
+
+#[instrument("retrieve order", skip_all)]
+fn get_order_details(order_number: u64) -> u64 {
+    std::thread::sleep(Duration::from_micros(100));
+    order_number % 1000
+}
+
+/// Run `get_total` `n_calls` times and return the output written at shutdown.
+fn run(n_calls: usize, weight: Weight) -> String {
+    let writer = MockWriter::new();
+    let (layer, guard) = FlameLayer::new(writer.clone());
+    let subscriber = Registry::default().with(layer.with_weight(weight));
+
+    tracing::subscriber::with_default(subscriber, || {
+        for _ in 0..n_calls {
+            get_total(&[1, 2, 3]);
+        }
+    });
+    guard.finish().unwrap();
+    writer.log_output().unwrap().text().to_owned()
+}
+
+/// Run `get_total` `n_calls` times and return the folded stacks, weighted by busy time.
+fn folded_stacks(n_calls: usize) -> Vec<(String, u64)> {
+    run(n_calls, Weight::BusyTime)
+        .lines()
+        .map(|line| {
+            let (stack, weight) = line.rsplit_once(' ').unwrap();
+            (stack.to_owned(), weight.parse().unwrap())
+        })
+        .collect()
+}
+
+#[test]
+fn busy_time_is_attributed_to_the_innermost_span() {
+    let n_calls = 20;
+    let stacks = folded_stacks(n_calls);
+
+    let stack_names: Vec<_> = stacks.iter().map(|(stack, _)| stack.as_str()).collect();
+    assert_eq!(
+        stack_names,
+        ["process total price", "process total price;retrieve order"]
+    );
+    let (_, parent_time) = stacks[0];
+    let (_, child_time) = stacks[1];
+    // Each call to `get_total` sleeps for 3x100µs inside `retrieve order`.
+    let min_child_time = Duration::from_micros(300).as_nanos() as u64 * n_calls as u64;
+    assert!(child_time >= min_child_time, "{child_time}ns");
+    assert!(
+        parent_time < child_time,
+        "The time spent in `retrieve order` was counted twice: {parent_time}ns vs {child_time}ns"
+    );
+}
+
+#[test]
+fn output_is_stable_across_runs() {
+    let output = run(5, Weight::Enters);
+    assert_eq!(
+        output,
+        "process total price 5\nprocess total price;retrieve order 15\n"
+    );
+    assert_eq!(output, run(5, Weight::Enters));
+}
+
+#[test]
+fn concurrent_enters_are_all_accounted_for() {
+    let writer = MockWriter::new();
+    let (layer, guard) = FlameLayer::new(writer.clone());
+    let subscriber = Registry::default().with(layer);
+
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("retrieve order");
+        let barrier = std::sync::Barrier::new(2);
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                let dispatch = tracing::dispatcher::get_default(|d| d.clone());
+                let (span, barrier) = (&span, &barrier);
+                s.spawn(move || {
+                    tracing::dispatcher::with_default(&dispatch, || {
+                        // Both threads are inside the span at the same time.
+                        span.in_scope(|| {
+                            barrier.wait();
+                            std::thread::sleep(Duration::from_millis(50));
+                        });
+                    })
+                });
+            }
+        });
+    });
+    guard.finish().unwrap();
+
+    let output = writer.log_output().unwrap();
+    let (_, busy) = output.text().trim_end().rsplit_once(' ').unwrap();
+    let busy: u64 = busy.parse().unwrap();
+    assert!(
+        busy >= Duration::from_millis(100).as_nanos() as u64,
+        "{busy}ns"
+    );
+}
+
+#[test]
+fn stacks_can_be_written_on_demand() {
+    let (layer, guard) = FlameLayer::new(std::io::sink());
+    let subscriber = Registry::default().with(layer);
+
+    tracing::subscriber::with_default(subscriber, || {
+        let outer = tracing::info_span!("process total price");
+        outer.in_scope(|| get_order_details(1));
+        // `process total price` is still open: only its child shows up.
+        assert_eq!(guard.folded().lines().count(), 1);
+        assert!(guard
+            .folded()
+            .starts_with("process total price;retrieve order "));
+    });
+
+    let mut snapshot = Vec::new();
+    guard.write_to(&mut snapshot).unwrap();
+    assert_eq!(String::from_utf8(snapshot).unwrap().lines().count(), 2);
+}
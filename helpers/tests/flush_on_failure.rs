@@ -0,0 +1,183 @@
+use helpers::flush_on_failure::FlushOnFailureLayer;
+use helpers::MockWriter;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::Level;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{Layer, Registry};
+
+/// A clock ticking once per formatted event, to tell when each event was formatted.
+#[derive(Default)]
+struct Ticks(AtomicU64);
+
+impl FormatTime for Ticks {
+    fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+        write!(w, "t{}", self.0.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+}
+
+fn layer(writer: MockWriter) -> FlushOnFailureLayer<impl Layer<Registry>, impl Fn() -> MockWriter> {
+    FlushOnFailureLayer::new(
+        move || writer.clone(),
+        |writer| {
+            tracing_subscriber::fmt::layer()
+                .with_timer(Ticks::default())
+                .with_writer(writer)
+                .with_ansi(false)
+                .with_target(false)
+                .compact()
+        },
+    )
+}
+
+#[tracing::instrument(fields(outcome = tracing::field::Empty))]
+fn get_total(order_numbers: &[u64]) -> Result<u64, String> {
+    let mut total = 0;
+    for &order_number in order_numbers {
+        tracing::debug!(order_number, "Retrieving order");
+        match get_order_details(order_number) {
+            Ok(amount) => total += amount,
+            Err(e) => {
+                tracing::Span::current().record("outcome", "failure");
+                return Err(e);
+            }
+        }
+    }
+    tracing::Span::current().record("outcome", "success");
+    tracing::info!(total, "Computed total");
+    Ok(total)
+}
+
+#[tracing::instrument]
+fn get_order_details(order_number: u64) -> Result<u64, String> {
+    if order_number.is_multiple_of(4) {
+        tracing::debug!("Order not found");
+        Err(format!("Order {order_number} not found"))
+    } else {
+        tracing::debug!(amount = order_number * 100, "Found order");
+        Ok(order_number * 100)
+    }
+}
+
+fn run(layer: impl Layer<Registry> + Send + Sync, f: impl FnOnce()) {
+    tracing::subscriber::with_default(
+        Registry::default().with(layer.with_filter(LevelFilter::DEBUG)),
+        f,
+    );
+}
+
+#[test]
+fn verbose_events_of_successful_requests_are_discarded() {
+    let writer = MockWriter::new();
+    run(layer(writer.clone()), || {
+        get_total(&[1, 2]).unwrap();
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    let mut log_lines = logging_output.lines();
+    log_lines.next_some().assert_eq(
+        r#"t5  INFO get_total: Computed total total=300 order_numbers=[1, 2] outcome="success""#,
+    );
+    log_lines.end();
+}
+
+#[test]
+fn verbose_events_of_failed_requests_are_flushed_in_order() {
+    let writer = MockWriter::new();
+    run(layer(writer.clone()), || {
+        get_total(&[1, 4, 5]).unwrap_err();
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    let mut log_lines = logging_output.lines();
+    // Events are written as they were formatted when emitted: in their own spans, with the
+    // span fields recorded at the time.
+    log_lines
+        .next_some()
+        .assert_eq("t1 DEBUG get_total: Retrieving order order_number=1 order_numbers=[1, 4, 5]");
+    log_lines.next_some().assert_eq(
+        "t2 DEBUG get_total:get_order_details: Found order amount=100 order_numbers=[1, 4, 5] order_number=1",
+    );
+    log_lines
+        .next_some()
+        .assert_eq("t3 DEBUG get_total: Retrieving order order_number=4 order_numbers=[1, 4, 5]");
+    log_lines.next_some().assert_eq(
+        "t4 DEBUG get_total:get_order_details: Order not found order_numbers=[1, 4, 5] order_number=4",
+    );
+    log_lines.end();
+}
+
+#[test]
+fn an_error_event_triggers_a_flush() {
+    let writer = MockWriter::new();
+    run(layer(writer.clone()), || {
+        let _span = tracing::info_span!("get_total").entered();
+        tracing::debug!("Retrieving order");
+        tracing::error!("Failed to talk to the database");
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    let mut log_lines = logging_output.lines();
+    // Events above the threshold aren't held back, so the error comes first: the held back
+    // event keeps the time it was emitted at.
+    log_lines
+        .next_some()
+        .assert_eq("t2 ERROR get_total: Failed to talk to the database");
+    log_lines
+        .next_some()
+        .assert_eq("t1 DEBUG get_total: Retrieving order");
+    log_lines.end();
+}
+
+#[test]
+fn the_oldest_events_are_dropped_when_the_buffer_is_full() {
+    let writer = MockWriter::new();
+    let layer = layer(writer.clone())
+        .with_failure_field("status", "KO")
+        .with_max_buffered_events(2);
+    run(layer, || {
+        let span = tracing::info_span!("get_total", status = tracing::field::Empty).entered();
+        for order_number in 1..=5 {
+            tracing::debug!(order_number, "Retrieving order");
+        }
+        span.record("status", "KO");
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    let mut log_lines = logging_output.lines();
+    log_lines
+        .next_some()
+        .assert_eq(r#"t6  WARN get_total: dropped 3 buffered events dropped=3 status="KO""#);
+    log_lines
+        .next_some()
+        .assert_eq("t4 DEBUG get_total: Retrieving order order_number=4");
+    log_lines
+        .next_some()
+        .assert_eq("t5 DEBUG get_total: Retrieving order order_number=5");
+    log_lines.end();
+}
+
+#[test]
+fn only_the_last_recorded_outcome_counts() {
+    let writer = MockWriter::new();
+    run(layer(writer.clone()), || {
+        let span = tracing::info_span!("get_total", outcome = "failure").entered();
+        tracing::debug!("Retrying");
+        span.record("outcome", "success");
+    });
+
+    assert!(writer.log_output().unwrap().text().is_empty());
+}
+
+#[test]
+fn the_threshold_is_configurable() {
+    let writer = MockWriter::new();
+    let layer = layer(writer.clone()).with_threshold(Level::WARN);
+    run(layer, || {
+        get_total(&[1]).unwrap();
+    });
+
+    assert!(writer.log_output().unwrap().text().is_empty());
+}
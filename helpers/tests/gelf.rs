@@ -0,0 +1,152 @@
+use helpers::event_fields::SpanStartLayer;
+use helpers::gelf::{GelfFormat, GelfSink};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader};
+use std::net::{TcpListener, UdpSocket};
+use std::time::Duration;
+use tracing::field::Empty;
+use tracing_subscriber::fmt::format::{FmtSpan, JsonFields};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+/// Record errors the way `01_error_trait` does.
+#[tracing::instrument(
+    "my_task",
+    fields(error.msg = Empty, error.source_chain = Empty)
+)]
+fn telemetry_wrapper(order_number: u64) -> Result<(), String> {
+    tracing::info!("Retrieving order");
+    let span = tracing::Span::current();
+    span.record(
+        "error.msg",
+        "The service is temporarily unavailable, please try again later",
+    );
+    span.record(
+        "error.source_chain",
+        "Failed to execute: `SELECT * FROM table`\nFailed to connect to 127.0.0.1:4236",
+    );
+    Err("Failed to talk to the database".into())
+}
+
+fn run(sink: GelfSink, f: impl FnOnce()) {
+    let fmt = tracing_subscriber::fmt::layer()
+        .with_span_events(FmtSpan::CLOSE)
+        .fmt_fields(JsonFields::new())
+        .event_format(GelfFormat::new("orders-api-1"))
+        .with_writer(sink);
+    tracing::subscriber::with_default(Registry::default().with(SpanStartLayer).with(fmt), f);
+}
+
+fn assert_json_include(actual: &Value, expected: Value) {
+    assert_json_diff::assert_json_include!(actual: actual, expected: expected);
+}
+
+fn assert_task_messages(messages: &[Value]) {
+    assert_eq!(messages.len(), 2, "{messages:#?}");
+    assert_json_include(
+        &messages[0],
+        json!({
+            "version": "1.1",
+            "host": "orders-api-1",
+            "short_message": "Retrieving order",
+            "level": 6,
+            "_target": "gelf",
+            "_file": "helpers/tests/gelf.rs",
+            "_order_number": 4,
+        }),
+    );
+    assert!(messages[0]["timestamp"].as_f64().unwrap() > 1e9);
+    assert!(messages[0].get("full_message").is_none());
+
+    assert_json_include(
+        &messages[1],
+        json!({
+            "short_message": "close",
+            "full_message": "The service is temporarily unavailable, please try again later\n\
+                Failed to execute: `SELECT * FROM table`\n\
+                Failed to connect to 127.0.0.1:4236",
+            "_order_number": 4,
+        }),
+    );
+    assert!(messages[1]["_duration_ns"].as_u64().unwrap() > 0);
+    assert!(messages[1].get("_error.msg").is_none());
+}
+
+#[test]
+fn messages_are_sent_over_udp() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let sink = GelfSink::udp(server.local_addr().unwrap()).unwrap();
+
+    run(sink, || {
+        telemetry_wrapper(4).unwrap_err();
+    });
+
+    let mut buffer = [0; 8192];
+    let messages: Vec<Value> = (0..2)
+        .map(|_| {
+            let len = server.recv(&mut buffer).unwrap();
+            serde_json::from_slice(&buffer[..len]).unwrap()
+        })
+        .collect();
+    assert_task_messages(&messages);
+}
+
+#[test]
+fn big_messages_are_chunked_over_udp() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let sink = GelfSink::udp(server.local_addr().unwrap()).unwrap();
+    let payload = "x".repeat(20_000);
+
+    run(sink, || {
+        tracing::info!(payload, "Big");
+    });
+
+    let mut buffer = [0; 8192];
+    let mut chunks = Vec::new();
+    loop {
+        let len = server.recv(&mut buffer).unwrap();
+        let datagram = &buffer[..len];
+        assert_eq!(&datagram[..2], [0x1e, 0x0f]);
+        let (sequence_number, sequence_count) = (datagram[10], datagram[11]);
+        assert_eq!(sequence_count, 3);
+        assert_eq!(sequence_number as usize, chunks.len());
+        chunks.push(datagram[12..].to_vec());
+        if chunks.len() == sequence_count as usize {
+            break;
+        }
+    }
+    let message: Value = serde_json::from_slice(&chunks.concat()).unwrap();
+    assert_eq!(message["short_message"], "Big");
+    assert_eq!(message["_payload"], payload.as_str());
+}
+
+#[test]
+fn messages_are_sent_over_tcp() {
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let sink = GelfSink::tcp(server.local_addr().unwrap()).unwrap();
+
+    run(sink, || {
+        telemetry_wrapper(4).unwrap_err();
+    });
+
+    let (connection, _) = server.accept().unwrap();
+    connection
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut reader = BufReader::new(connection);
+    let messages: Vec<Value> = (0..2)
+        .map(|_| {
+            let mut frame = Vec::new();
+            reader.read_until(b'\0', &mut frame).unwrap();
+            assert_eq!(frame.pop(), Some(b'\0'));
+            serde_json::from_slice(&frame).unwrap()
+        })
+        .collect();
+    assert_task_messages(&messages);
+}
@@ -0,0 +1,174 @@
+use helpers::guard::TelemetryGuard;
+use helpers::otlp::MockCollector;
+use opentelemetry::metrics::MeterProvider;
+use opentelemetry::trace::{Tracer, TracerProvider};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::runtime;
+use opentelemetry_sdk::trace::TracerProvider as SdkTracerProvider;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+fn tracer_provider(collector: &MockCollector) -> SdkTracerProvider {
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(collector.endpoint()),
+        )
+        .install_batch(runtime::Tokio)
+        .unwrap()
+}
+
+fn meter_provider(collector: &MockCollector) -> SdkMeterProvider {
+    opentelemetry_otlp::new_pipeline()
+        .metrics(runtime::Tokio)
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(collector.endpoint()),
+        )
+        .build()
+        .unwrap()
+}
+
+/// A writer remembering whether it was flushed, failing or taking its time if asked to.
+#[derive(Clone, Default)]
+struct Writer {
+    flushed: Arc<AtomicBool>,
+    delay: Duration,
+    fail: bool,
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::thread::sleep(self.delay);
+        if self.fail {
+            return Err(std::io::Error::other("disk full"));
+        }
+        self.flushed.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn shutdown_exports_everything_that_is_buffered() {
+    let collector = MockCollector::start().await;
+    let tracer_provider = tracer_provider(&collector);
+    let meter_provider = meter_provider(&collector);
+    let writer = Writer::default();
+    let guard = TelemetryGuard::new()
+        .with_tracer_provider(tracer_provider.clone())
+        .with_meter_provider(meter_provider.clone())
+        .with_writer("logs", writer.clone());
+
+    tracer_provider
+        .tracer("guard")
+        .in_span("process total price", |_| {});
+    meter_provider
+        .meter("guard")
+        .u64_counter("orders_processed")
+        .init()
+        .add(3, &[]);
+    let report = guard.shutdown().await;
+
+    assert!(report.is_ok(), "{report}");
+    let span_names: Vec<_> = collector.spans().into_iter().map(|s| s.name).collect();
+    assert_eq!(span_names, ["process total price"]);
+    assert!(collector
+        .metrics()
+        .iter()
+        .any(|m| m.name == "orders_processed"));
+    assert!(writer.flushed.load(Ordering::SeqCst));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn dropping_the_guard_inside_a_runtime_exports_spans() {
+    let collector = MockCollector::start().await;
+    let provider = tracer_provider(&collector);
+    let guard = TelemetryGuard::new().with_tracer_provider(provider.clone());
+
+    provider.tracer("guard").in_span("retrieve order", |_| {});
+    drop(guard);
+
+    let span_names: Vec<_> = collector.spans().into_iter().map(|s| s.name).collect();
+    assert_eq!(span_names, ["retrieve order"]);
+}
+
+#[test]
+fn dropping_the_guard_outside_a_runtime_exports_spans() {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let collector = runtime.block_on(MockCollector::start());
+    // The batch processor needs a runtime to run on, but the guard is dropped outside of it.
+    let provider = runtime.block_on(async { tracer_provider(&collector) });
+    let guard = TelemetryGuard::new().with_tracer_provider(provider.clone());
+
+    provider.tracer("guard").in_span("retrieve order", |_| {});
+    drop(guard);
+
+    let span_names: Vec<_> = collector.spans().into_iter().map(|s| s.name).collect();
+    assert_eq!(span_names, ["retrieve order"]);
+}
+
+#[tokio::test]
+async fn dropping_the_guard_on_a_current_thread_runtime_exports_spans() {
+    // This runtime is blocked while the guard is dropped: the export task, and the collector
+    // standing for the backend, run on another one.
+    let telemetry_runtime = tokio::runtime::Runtime::new().unwrap();
+    let collector = telemetry_runtime
+        .spawn(MockCollector::start())
+        .await
+        .unwrap();
+    let provider = {
+        let _entered = telemetry_runtime.enter();
+        tracer_provider(&collector)
+    };
+    let guard = TelemetryGuard::new()
+        .with_runtime(telemetry_runtime)
+        .with_tracer_provider(provider.clone());
+
+    provider.tracer("guard").in_span("retrieve order", |_| {});
+    let start = Instant::now();
+    drop(guard);
+
+    assert!(start.elapsed() < Duration::from_secs(2));
+    let span_names: Vec<_> = collector.spans().into_iter().map(|s| s.name).collect();
+    assert_eq!(span_names, ["retrieve order"]);
+}
+
+#[tokio::test]
+async fn failures_and_timeouts_are_reported() {
+    let slow = Writer {
+        delay: Duration::from_secs(5),
+        ..Default::default()
+    };
+    let guard = TelemetryGuard::new()
+        .with_deadline(Duration::from_millis(200))
+        .with_writer(
+            "errors",
+            Writer {
+                fail: true,
+                ..Default::default()
+            },
+        )
+        .with_writer("logs", slow.clone());
+
+    let start = Instant::now();
+    let report = guard.shutdown().await;
+
+    assert!(start.elapsed() < Duration::from_secs(2));
+    assert!(!slow.flushed.load(Ordering::SeqCst));
+    assert_eq!(
+        report.to_string(),
+        "Some telemetry may not have been exported:\n\
+         - writer `errors`: disk full\n\
+         - writer `logs`: not done after 200ms"
+    );
+}
@@ -0,0 +1,176 @@
+use helpers::log_bridge::{LogLayer, TracingLogger};
+use helpers::MockWriter;
+use std::sync::{Arc, Mutex};
+use tracing::Level;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+/// A `log` logger that keeps the records it receives, rendered as text.
+#[derive(Clone, Default)]
+struct RecordingLogger(Arc<Mutex<Vec<String>>>);
+
+impl RecordingLogger {
+    fn records(&self) -> Vec<String> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl log::Log for RecordingLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        struct Render(String);
+
+        impl<'kvs> log::kv::VisitSource<'kvs> for Render {
+            fn visit_pair(
+                &mut self,
+                key: log::kv::Key<'kvs>,
+                value: log::kv::Value<'kvs>,
+            ) -> Result<(), log::kv::Error> {
+                self.0.push_str(&format!(" {key}={value}"));
+                Ok(())
+            }
+        }
+
+        let mut rendered = format!("{} {}: {}", record.level(), record.target(), record.args());
+        let mut kvs = Render(String::new());
+        record.key_values().visit(&mut kvs).unwrap();
+        rendered.push_str(&kvs.0);
+        self.0.lock().unwrap().push(rendered);
+    }
+
+    fn flush(&self) {}
+}
+
+#[test]
+fn events_become_log_records() {
+    let logger = RecordingLogger::default();
+    let subscriber = Registry::default().with(LogLayer::new(logger.clone()));
+
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::info!(target: "orders", order_number = 4, customer = "acme", "Retrieving order");
+        tracing::warn!(target: "orders", retries = 3_u64, "Slow database");
+    });
+
+    assert_eq!(
+        logger.records(),
+        [
+            "INFO orders: Retrieving order order_number=4 customer=acme",
+            "WARN orders: Slow database retries=3",
+        ]
+    );
+}
+
+#[test]
+fn the_level_mapping_is_configurable() {
+    let logger = RecordingLogger::default();
+    let layer = LogLayer::new(logger.clone()).with_level_mapping(|level| match *level {
+        Level::TRACE => None,
+        Level::DEBUG => Some(log::Level::Trace),
+        _ => Some(log::Level::Info),
+    });
+    let subscriber = Registry::default().with(layer);
+
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::error!("Failed to talk to the database");
+        tracing::debug!("Retrying");
+        tracing::trace!("Opening a connection");
+    });
+
+    assert_eq!(
+        logger.records(),
+        [
+            "INFO log_bridge: Failed to talk to the database",
+            "TRACE log_bridge: Retrying",
+        ]
+    );
+}
+
+#[test]
+fn a_panicking_logger_does_not_stop_the_bridge() {
+    /// Panics on the records whose message is `panic`.
+    struct PanickingLogger(RecordingLogger);
+
+    impl log::Log for PanickingLogger {
+        fn enabled(&self, _metadata: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            if record.args().to_string() == "panic" {
+                panic!("the logger panicked");
+            }
+            self.0.log(record);
+        }
+
+        fn flush(&self) {}
+    }
+
+    let logger = RecordingLogger::default();
+    let subscriber = Registry::default().with(LogLayer::new(PanickingLogger(logger.clone())));
+
+    tracing::subscriber::with_default(subscriber, || {
+        std::panic::catch_unwind(|| tracing::info!("panic")).unwrap_err();
+        tracing::info!("Retrieving order");
+    });
+
+    assert_eq!(logger.records(), ["INFO log_bridge: Retrieving order"]);
+}
+
+// `log` only lets us install a global logger once per process: everything that relies on
+// `TracingLogger` lives in this test.
+#[test]
+fn both_bridges_can_be_installed_at_once() {
+    TracingLogger::new()
+        .with_level_mapping(|level| match level {
+            log::Level::Trace => Level::DEBUG,
+            level => level.to_string().parse().unwrap(),
+        })
+        .init()
+        .unwrap();
+
+    let writer = MockWriter::new();
+    let writer2 = writer.clone();
+    let recorded = RecordingLogger::default();
+    let subscriber = Registry::default()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .without_time()
+                .with_ansi(false)
+                .with_writer(move || writer2.clone()),
+        )
+        // Forwarding to the global logger, i.e. back to `tracing`: it must not loop.
+        .with(LogLayer::new(log::logger()))
+        .with(LogLayer::new(recorded.clone()));
+
+    tracing::subscriber::with_default(subscriber, || {
+        log::info!(target: "orders", order_number = 4, customer = "acme"; "Retrieving order");
+        log::trace!(target: "orders", "Opening a connection");
+        log::warn!(target: "orders", message = "timeout"; "Retrying");
+        tracing::info!(target: "orders", total = 300, "Computed total");
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    let mut log_lines = logging_output.lines();
+    log_lines
+        .next_some()
+        .assert_eq(r#" INFO orders: Retrieving order order_number=4 customer="acme""#);
+    log_lines
+        .next_some()
+        .assert_eq("DEBUG orders: Opening a connection");
+    log_lines
+        .next_some()
+        .assert_eq(r#" WARN orders: Retrying kv.message="timeout""#);
+    log_lines
+        .next_some()
+        .assert_eq(" INFO orders: Computed total total=300");
+    log_lines.end();
+
+    // Records that came from `log` are not sent back to `log`.
+    assert_eq!(
+        recorded.records(),
+        ["INFO orders: Computed total total=300"]
+    );
+}
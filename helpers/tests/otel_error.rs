@@ -0,0 +1,154 @@
+use helpers::otel_error::{record_dyn_error, record_error, ExceptionLayer};
+use helpers::otlp::{attribute, MockCollector};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_proto::tonic::trace::v1::status::StatusCode;
+use opentelemetry_proto::tonic::trace::v1::Span;
+use opentelemetry_sdk::runtime;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use tracing::field::{display, Empty};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+#[derive(Debug)]
+struct DatabaseError {
+    source: std::io::Error,
+}
+
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to execute a database query")
+    }
+}
+
+impl Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+fn database_error() -> DatabaseError {
+    DatabaseError {
+        source: std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            "Failed to connect to 127.0.0.1:4236",
+        ),
+    }
+}
+
+const SOURCE_CHAIN: &str =
+    "Custom { kind: ConnectionRefused, error: \"Failed to connect to 127.0.0.1:4236\" }\n";
+
+/// The `exception` events of `span`, as (type, message, stacktrace).
+fn exceptions(span: &Span) -> Vec<(Option<String>, Option<String>, Option<String>)> {
+    span.events
+        .iter()
+        .filter(|e| e.name == "exception")
+        .map(|e| {
+            (
+                attribute(&e.attributes, "exception.type"),
+                attribute(&e.attributes, "exception.message"),
+                attribute(&e.attributes, "exception.stacktrace"),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn errors_set_the_status_and_add_an_exception_event() {
+    let collector = MockCollector::start().await;
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(collector.endpoint()),
+        )
+        .install_batch(runtime::Tokio)
+        .unwrap();
+    let otel = tracing_opentelemetry::layer().with_tracer(provider.tracer("otel_error"));
+    let subscriber = Registry::default().with(otel).with(ExceptionLayer::new());
+
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("with helper");
+        record_error(&span, &database_error());
+
+        // Errors whose concrete type was erased.
+        let span = tracing::info_span!("with anyhow");
+        let error = anyhow::Error::new(database_error());
+        record_dyn_error(&span, error.as_ref());
+        let span = tracing::info_span!("with boxed error");
+        let error: Box<dyn Error> = Box::new(database_error());
+        record_error(&span, &*error);
+
+        // The way `01_error_trait` records errors, one field at a time.
+        let span = tracing::info_span!(
+            "with fields",
+            error.msg = Empty,
+            error.debug = Empty,
+            error.source_chain = Empty
+        );
+        let error = database_error();
+        span.record("error.msg", display(&error));
+        span.record("error.debug", tracing::field::debug(&error));
+        span.record("error.source_chain", SOURCE_CHAIN);
+
+        tracing::info_span!("no error").in_scope(|| {});
+    });
+    tokio::task::spawn_blocking(move || provider.shutdown())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let spans = collector.spans();
+    let span = |name: &str| spans.iter().find(|s| s.name == name).unwrap();
+
+    let with_helper = span("with helper");
+    let status = with_helper.status.clone().unwrap();
+    assert_eq!(status.code, StatusCode::Error as i32);
+    assert_eq!(status.message, "Failed to execute a database query");
+    assert_eq!(
+        exceptions(with_helper),
+        [(
+            Some("otel_error::DatabaseError".into()),
+            Some("Failed to execute a database query".into()),
+            Some(SOURCE_CHAIN.into())
+        )]
+    );
+
+    for name in ["with anyhow", "with boxed error"] {
+        let erased = span(name);
+        let status = erased.status.clone().unwrap();
+        assert_eq!(status.code, StatusCode::Error as i32);
+        assert_eq!(
+            exceptions(erased),
+            [(
+                None,
+                Some("Failed to execute a database query".into()),
+                Some(SOURCE_CHAIN.into())
+            )]
+        );
+    }
+
+    let with_fields = span("with fields");
+    let status = with_fields.status.clone().unwrap();
+    assert_eq!(status.code, StatusCode::Error as i32);
+    assert_eq!(status.message, "Failed to execute a database query");
+    // A single event, holding everything that was recorded.
+    assert_eq!(
+        exceptions(with_fields),
+        [(
+            None,
+            Some("Failed to execute a database query".into()),
+            Some(SOURCE_CHAIN.into())
+        )]
+    );
+
+    let no_error = span("no error");
+    assert_eq!(
+        no_error.status.clone().unwrap_or_default().code,
+        StatusCode::Unset as i32
+    );
+    assert!(exceptions(no_error).is_empty());
+}
@@ -0,0 +1,120 @@
+use helpers::otel_log::OtelLogFormat;
+use helpers::otlp::MockCollector;
+use helpers::MockWriter;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::runtime;
+use serde_json::json;
+use tracing::instrument;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+#[instrument("process total price", skip_all)]
+fn get_total(order_numbers: &[u64]) -> u64 {
+    let total = order_numbers.iter().map(|n| get_order_details(*n)).sum();
+    tracing::info!(total, "Computed total");
+    total
+}
+
+#[instrument("retrieve order", skip_all)]
+fn get_order_details(order_number: u64) -> u64 {
+    tracing::debug!(order_number, cached = false, "Retrieved order");
+    order_number * 100
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn records_follow_the_log_data_model_and_correlate_with_spans() {
+    let collector = MockCollector::start().await;
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(collector.endpoint()),
+        )
+        .install_batch(runtime::Tokio)
+        .unwrap();
+    let otel = tracing_opentelemetry::layer().with_tracer(provider.tracer("otel_log"));
+    let writer = MockWriter::new();
+    let writer2 = writer.clone();
+    let fmt = tracing_subscriber::fmt::layer()
+        .event_format(
+            OtelLogFormat::new().with_resource([KeyValue::new("service.name", "orders-api")]),
+        )
+        .with_writer(move || writer2.clone());
+    let subscriber = Registry::default().with(otel).with(fmt);
+
+    tracing::subscriber::with_default(subscriber, || {
+        get_total(&[1]);
+        // Not the current span, but the span the event belongs to.
+        let ship = tracing::info_span!("ship order");
+        tracing::info!(parent: &ship, "Shipping order");
+        drop(ship);
+        tracing::warn!("Shutting down");
+    });
+    tokio::task::spawn_blocking(move || provider.shutdown())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let spans = collector.spans();
+    let span_ids = |name: &str| {
+        let span = spans.iter().find(|s| s.name == name).unwrap();
+        (hex(&span.trace_id), hex(&span.span_id))
+    };
+    let (trace_id, order_span_id) = span_ids("retrieve order");
+    let (total_trace_id, total_span_id) = span_ids("process total price");
+    assert_eq!(trace_id, total_trace_id);
+    let (ship_trace_id, ship_span_id) = span_ids("ship order");
+
+    let logging_output = writer.log_output().unwrap();
+    let mut log_lines = logging_output.lines();
+    log_lines.next_some().assert_json_include(json!({
+        "SeverityText": "DEBUG",
+        "SeverityNumber": 5,
+        "Body": "Retrieved order",
+        "Attributes": {
+            "order_number": 1,
+            "cached": false,
+            "code.namespace": "otel_log",
+            "code.filepath": "helpers/tests/otel_log.rs",
+        },
+        "Resource": { "service.name": "orders-api" },
+        "InstrumentationScope": { "Name": "otel_log" },
+        "TraceId": trace_id,
+        "SpanId": order_span_id,
+    }));
+    log_lines.next_some().assert_json_include(json!({
+        "SeverityText": "INFO",
+        "SeverityNumber": 9,
+        "Body": "Computed total",
+        "Attributes": { "total": 100 },
+        "TraceId": trace_id,
+        "SpanId": total_span_id,
+    }));
+    log_lines.next_some().assert_json_include(json!({
+        "Body": "Shipping order",
+        "TraceId": ship_trace_id,
+        "SpanId": ship_span_id,
+    }));
+    let outside_of_spans = log_lines.next_some();
+    outside_of_spans.assert_json_include(json!({
+        "SeverityText": "WARN",
+        "SeverityNumber": 13,
+        "Body": "Shutting down",
+    }));
+    let outside_of_spans: serde_json::Value =
+        serde_json::from_str(outside_of_spans.text()).unwrap();
+    assert!(outside_of_spans.get("TraceId").is_none());
+    assert!(outside_of_spans["Timestamp"]
+        .as_str()
+        .unwrap()
+        .parse::<u64>()
+        .is_ok());
+    log_lines.end();
+}
@@ -0,0 +1,104 @@
+use helpers::otel_metrics::OtelRecorder;
+use helpers::otlp::{attribute, MockCollector};
+use metrics::Unit;
+use opentelemetry::metrics::MeterProvider;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_proto::tonic::metrics::v1::metric::Data;
+use opentelemetry_proto::tonic::metrics::v1::number_data_point::Value;
+use opentelemetry_proto::tonic::metrics::v1::{Metric, NumberDataPoint};
+use opentelemetry_sdk::{runtime, Resource};
+
+fn last<'a>(metrics: &'a [Metric], name: &str) -> &'a Metric {
+    metrics
+        .iter()
+        .rev()
+        .find(|m| m.name == name)
+        .unwrap_or_else(|| panic!("`{name}` was not exported"))
+}
+
+fn number(point: &NumberDataPoint) -> f64 {
+    match point.value {
+        Some(Value::AsDouble(v)) => v,
+        Some(Value::AsInt(v)) => v as f64,
+        None => panic!("Empty data point"),
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn metrics_are_exported_as_otlp() {
+    let collector = MockCollector::start().await;
+    let provider = opentelemetry_otlp::new_pipeline()
+        .metrics(runtime::Tokio)
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(collector.endpoint()),
+        )
+        .with_resource(Resource::new([KeyValue::new("service.name", "orders-api")]))
+        .build()
+        .unwrap();
+    let recorder = OtelRecorder::new(provider.meter("otel_metrics"));
+
+    metrics::with_local_recorder(&recorder, || {
+        metrics::describe_counter!("orders_processed", Unit::Count, "Orders processed");
+        metrics::describe_histogram!(
+            "order_processing_duration",
+            Unit::Seconds,
+            "Time spent processing an order"
+        );
+        metrics::describe_gauge!("orders_in_flight", "Orders being processed");
+
+        metrics::counter!("orders_processed", "status" => "ok").increment(2);
+        metrics::counter!("orders_processed", "status" => "ok").increment(1);
+        metrics::counter!("orders_processed", "status" => "failed").absolute(4);
+        metrics::counter!("orders_processed", "status" => "failed").absolute(5);
+        metrics::histogram!("order_processing_duration").record(0.25);
+        metrics::histogram!("order_processing_duration").record(0.75);
+        metrics::gauge!("orders_in_flight").increment(3.0);
+        metrics::gauge!("orders_in_flight").decrement(1.0);
+    });
+    tokio::task::spawn_blocking(move || provider.shutdown())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let resource = collector.resource_metrics()[0].resource.clone().unwrap();
+    assert_eq!(
+        attribute(&resource.attributes, "service.name").as_deref(),
+        Some("orders-api")
+    );
+    let metrics = collector.metrics();
+
+    let counter = last(&metrics, "orders_processed");
+    assert_eq!(counter.unit, "1");
+    assert_eq!(counter.description, "Orders processed");
+    let Some(Data::Sum(sum)) = &counter.data else {
+        panic!("Unexpected data: {:?}", counter.data)
+    };
+    assert!(sum.is_monotonic);
+    let mut points: Vec<_> = sum
+        .data_points
+        .iter()
+        .map(|p| (attribute(&p.attributes, "status").unwrap(), number(p)))
+        .collect();
+    points.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(points, [("failed".into(), 5.), ("ok".into(), 3.)]);
+
+    let histogram = last(&metrics, "order_processing_duration");
+    assert_eq!(histogram.unit, "s");
+    assert_eq!(histogram.description, "Time spent processing an order");
+    let Some(Data::Histogram(histogram)) = &histogram.data else {
+        panic!("Unexpected data: {:?}", histogram.data)
+    };
+    assert_eq!(histogram.data_points[0].count, 2);
+    assert_eq!(histogram.data_points[0].sum, Some(1.));
+
+    let gauge = last(&metrics, "orders_in_flight");
+    assert_eq!(gauge.unit, "");
+    assert_eq!(gauge.description, "Orders being processed");
+    let Some(Data::Gauge(gauge)) = &gauge.data else {
+        panic!("Unexpected data: {:?}", gauge.data)
+    };
+    assert_eq!(number(&gauge.data_points[0]), 2.);
+}
@@ -0,0 +1,85 @@
+use helpers::event_fields::FieldValue;
+use helpers::poll_timing::{
+    PollTimingExt, PollTimingLayer, POLL_COUNT_HISTOGRAM, POLL_DURATION_HISTOGRAM,
+    SCHEDULING_DELAY_HISTOGRAM, SLOW_POLL_TARGET,
+};
+use helpers::store::SpanStore;
+use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+use std::time::Duration;
+use tracing::field::Empty;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+async fn retrieve_order() -> u32 {
+    tokio::task::yield_now().await;
+    // Blocking the executor thread: this poll should be flagged.
+    std::thread::sleep(Duration::from_millis(30));
+    tokio::task::yield_now().await;
+    4
+}
+
+#[test]
+fn polls_are_measured() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let store = SpanStore::new();
+    let subscriber = Registry::default()
+        .with(store.layer())
+        .with(PollTimingLayer::new().with_slow_poll_threshold(Duration::from_millis(20)));
+
+    let order_number = metrics::with_local_recorder(&recorder, || {
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(
+                "retrieve order",
+                poll.count = Empty,
+                poll.busy_ns = Empty,
+                poll.longest_ns = Empty,
+                poll.max_scheduling_delay_ns = Empty,
+            );
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(retrieve_order().instrument_polls(span))
+        })
+    });
+    assert_eq!(order_number, 4);
+
+    let span = store.span("retrieve order");
+    assert_eq!(span.field("poll.count"), Some(&FieldValue::U64(3)));
+    let Some(&FieldValue::U64(busy)) = span.field("poll.busy_ns") else {
+        panic!("`poll.busy_ns` wasn't recorded")
+    };
+    let Some(&FieldValue::U64(longest)) = span.field("poll.longest_ns") else {
+        panic!("`poll.longest_ns` wasn't recorded")
+    };
+    assert!(longest >= 30_000_000, "{longest}");
+    assert!(busy >= longest, "{busy} < {longest}");
+    assert!(span.field("poll.max_scheduling_delay_ns").is_some());
+
+    let warnings = store.events_where(|e| e.target == SLOW_POLL_TARGET);
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].ancestry, [span.id]);
+
+    let histograms: Vec<_> = snapshotter
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .map(|(key, _, _, value)| {
+            let DebugValue::Histogram(values) = value else {
+                panic!("`{}` should be a histogram", key.key().name())
+            };
+            let label = key.key().labels().next().unwrap().value().to_owned();
+            (key.key().name().to_owned(), label, values.len())
+        })
+        .collect();
+    let samples = |name: &str| {
+        histograms
+            .iter()
+            .find(|(n, label, _)| n == name && label == "retrieve order")
+            .map(|(.., len)| *len)
+    };
+    assert_eq!(samples(POLL_DURATION_HISTOGRAM), Some(3));
+    // The first poll doesn't follow a wake-up.
+    assert_eq!(samples(SCHEDULING_DELAY_HISTOGRAM), Some(2));
+    assert_eq!(samples(POLL_COUNT_HISTOGRAM), Some(1));
+}
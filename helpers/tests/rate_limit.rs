@@ -0,0 +1,188 @@
+use helpers::rate_limit::{RateLimit, RateLimitLayer};
+use helpers::MockWriter;
+use std::time::Duration;
+use tracing::Level;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::{Layer, Registry};
+
+fn fmt_layer(writer: MockWriter) -> impl Layer<Registry> {
+    tracing_subscriber::fmt::layer()
+        .without_time()
+        .with_writer(move || writer.clone())
+        .with_ansi(false)
+        .with_target(false)
+        .compact()
+}
+
+fn query_database(order_number: u64) {
+    tracing::error!(order_number, "Failed to talk to the database");
+}
+
+#[test]
+fn suppressed_events_are_summarised() {
+    let writer = MockWriter::new();
+    let layer = RateLimitLayer::new(fmt_layer(writer.clone()))
+        .with_default_limit(RateLimit::new(3, Duration::from_millis(300)));
+
+    tracing::subscriber::with_default(Registry::default().with(layer), || {
+        for _ in 0..10 {
+            query_database(4);
+        }
+        std::thread::sleep(Duration::from_millis(350));
+        query_database(4);
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    let mut log_lines = logging_output.lines();
+    for _ in 0..3 {
+        log_lines
+            .next_some()
+            .assert_eq("ERROR Failed to talk to the database order_number=4");
+    }
+    log_lines.next_some().assert_regex_match(
+        r#"^ERROR suppressed 7 similar events suppressed=7 callsite="event .*tests/rate_limit\.rs:\d+"$"#,
+    );
+    log_lines
+        .next_some()
+        .assert_eq("ERROR Failed to talk to the database order_number=4");
+    log_lines.end();
+}
+
+#[test]
+fn key_fields_get_their_own_bucket() {
+    let writer = MockWriter::new();
+    let layer = RateLimitLayer::new(fmt_layer(writer.clone()))
+        .with_default_limit(RateLimit::new(1, Duration::from_secs(60)))
+        .with_key_fields(["order_number"]);
+
+    tracing::subscriber::with_default(Registry::default().with(layer), || {
+        for order_number in [4, 8, 4, 8, 12] {
+            query_database(order_number);
+        }
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    let mut log_lines = logging_output.lines();
+    for order_number in [4, 8, 12] {
+        log_lines.next_some().assert_eq(&format!(
+            "ERROR Failed to talk to the database order_number={order_number}"
+        ));
+    }
+    log_lines.end();
+}
+
+#[test]
+fn limits_are_configured_per_target_and_level() {
+    let writer = MockWriter::new();
+    let layer = RateLimitLayer::new(fmt_layer(writer.clone()))
+        .with_target_limit("rate_limit", RateLimit::new(2, Duration::from_secs(60)))
+        .with_limit(
+            "rate_limit",
+            Level::INFO,
+            RateLimit::new(1, Duration::from_secs(60)),
+        )
+        .with_target_limit("kv", RateLimit::new(1, Duration::from_secs(60)));
+
+    tracing::subscriber::with_default(Registry::default().with(layer), || {
+        for _ in 0..3 {
+            tracing::info!("Retrieving order");
+            tracing::warn!("Retrying");
+            tracing::debug!(target: "kv::db", "Querying the database");
+            // Targets without a rule are not limited.
+            tracing::debug!(target: "kvstore", "Not limited");
+        }
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    let mut log_lines = logging_output.lines();
+    log_lines.next_some().assert_eq(" INFO Retrieving order");
+    log_lines.next_some().assert_eq(" WARN Retrying");
+    log_lines
+        .next_some()
+        .assert_eq("DEBUG Querying the database");
+    log_lines.next_some().assert_eq("DEBUG Not limited");
+    log_lines.next_some().assert_eq(" WARN Retrying");
+    log_lines.next_some().assert_eq("DEBUG Not limited");
+    log_lines.next_some().assert_eq("DEBUG Not limited");
+    log_lines.end();
+}
+
+#[test]
+fn summaries_are_flushed_when_the_span_closes() {
+    let writer = MockWriter::new();
+    let layer = RateLimitLayer::new(fmt_layer(writer.clone()))
+        .with_default_limit(RateLimit::new(1, Duration::from_secs(60)));
+
+    tracing::subscriber::with_default(Registry::default().with(layer), || {
+        tracing::info_span!("get_total").in_scope(|| {
+            for _ in 0..5 {
+                query_database(4);
+            }
+        });
+        tracing::info!("Done");
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    let mut log_lines = logging_output.lines();
+    log_lines
+        .next_some()
+        .assert_eq("ERROR get_total: Failed to talk to the database order_number=4");
+    log_lines.next_some().assert_regex_match(
+        r#"^ERROR get_total: suppressed 4 similar events suppressed=4 callsite="event .*tests/rate_limit\.rs:\d+"$"#,
+    );
+    log_lines.next_some().assert_eq(" INFO Done");
+    log_lines.end();
+}
+
+#[test]
+fn summaries_are_flushed_once_the_window_reopens() {
+    let writer = MockWriter::new();
+    let layer = RateLimitLayer::new(fmt_layer(writer.clone()))
+        .with_default_limit(RateLimit::new(1, Duration::from_millis(50)));
+
+    tracing::subscriber::with_default(Registry::default().with(layer), || {
+        for _ in 0..3 {
+            query_database(4);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        // `query_database` is not called again, but any event will do.
+        tracing::info!("Done");
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    let mut log_lines = logging_output.lines();
+    log_lines
+        .next_some()
+        .assert_eq("ERROR Failed to talk to the database order_number=4");
+    log_lines.next_some().assert_regex_match(
+        r#"^ERROR suppressed 2 similar events suppressed=2 callsite="event .*tests/rate_limit\.rs:\d+"$"#,
+    );
+    log_lines.next_some().assert_eq(" INFO Done");
+    log_lines.end();
+}
+
+#[test]
+fn summaries_are_flushed_without_further_events() {
+    let writer = MockWriter::new();
+    let layer = RateLimitLayer::new(fmt_layer(writer.clone()))
+        .with_default_limit(RateLimit::new(1, Duration::from_millis(50)));
+
+    tracing::subscriber::with_default(Registry::default().with(layer), || {
+        for _ in 0..3 {
+            query_database(4);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        // No more events, but a span closing gives the layer a chance to flush the summary.
+        tracing::info_span!("ship order").in_scope(|| {});
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    let mut log_lines = logging_output.lines();
+    log_lines
+        .next_some()
+        .assert_eq("ERROR Failed to talk to the database order_number=4");
+    log_lines.next_some().assert_regex_match(
+        r#"^ERROR suppressed 2 similar events suppressed=2 callsite="event .*tests/rate_limit\.rs:\d+"$"#,
+    );
+    log_lines.end();
+}
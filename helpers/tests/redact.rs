@@ -0,0 +1,191 @@
+use helpers::otlp::{attribute, MockCollector};
+use helpers::redact::{Redaction, Redactor, Sensitive};
+use helpers::MockWriter;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::runtime;
+use serde_json::json;
+use tracing::instrument;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+const ORDER_NUMBER: u64 = 987654321;
+const EMAIL: &str = "jane.doe@example.com";
+
+fn redactor() -> Redactor {
+    Redactor::new()
+        .redact("order_number", Redaction::Mask)
+        .redact("customer.*", Redaction::keyed_hash("not-so-secret"))
+        .redact("internal_notes", Redaction::Drop)
+}
+
+#[instrument(
+    "retrieve order",
+    skip_all,
+    fields(order_number, customer.email = %EMAIL, customer.name = %Sensitive("Jane Doe"))
+)]
+fn get_order_details(order_number: u64) {
+    tracing::Span::current().record("order_number", order_number);
+    tracing::info!(
+        internal_notes = "Call before shipping",
+        shipping_address = %Sensitive("1 Main Street"),
+        "Order retrieved"
+    );
+}
+
+fn assert_nothing_sensitive(output: &str) {
+    for secret in [
+        &ORDER_NUMBER.to_string(),
+        EMAIL,
+        "Jane Doe",
+        "1 Main Street",
+        "Call before shipping",
+    ] {
+        assert!(
+            !output.contains(secret),
+            "`{secret}` leaked into the telemetry output:\n{output}"
+        );
+    }
+}
+
+#[test]
+fn json_logs_are_redacted() {
+    let writer = MockWriter::new();
+    let writer2 = writer.clone();
+    let subscriber = Registry::default().with(
+        tracing_subscriber::fmt::layer()
+            .with_writer(move || writer2.clone())
+            .with_span_events(FmtSpan::CLOSE)
+            .json()
+            .flatten_event(true),
+    );
+
+    tracing::subscriber::with_default(redactor().wrap(subscriber), || {
+        get_order_details(ORDER_NUMBER)
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    assert_nothing_sensitive(logging_output.text());
+
+    let mut log_lines = logging_output.lines();
+    let event = log_lines.next_some();
+    event.assert_json_include(json!({
+        "message": "Order retrieved",
+        "shipping_address": "[REDACTED]",
+        "span": {"name": "retrieve order", "order_number": "[REDACTED]"}
+    }));
+    assert!(
+        !event.text().contains("internal_notes"),
+        "Dropped fields should not be there at all:\n{}",
+        event.text()
+    );
+    log_lines.next_some().assert_json_include(json!({
+        "message": "close",
+        "span": {"name": "retrieve order", "order_number": "[REDACTED]"}
+    }));
+    log_lines.end();
+}
+
+#[test]
+fn hashes_are_stable_and_sensitive_values_can_be_hashed() {
+    let writer = MockWriter::new();
+    let writer2 = writer.clone();
+    let subscriber = Registry::default().with(
+        tracing_subscriber::fmt::layer()
+            .with_writer(move || writer2.clone())
+            .json()
+            .flatten_event(true),
+    );
+    let redactor = redactor().redact_sensitive_values(Redaction::keyed_hash("not-so-secret"));
+
+    tracing::subscriber::with_default(redactor.wrap(subscriber), || {
+        tracing::info!(customer.email = EMAIL, "First order");
+        tracing::info!(customer.email = EMAIL, "Second order");
+        tracing::info!(shipping_address = %Sensitive(EMAIL), "Third order");
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    assert_nothing_sensitive(logging_output.text());
+
+    let hashes: Vec<String> = logging_output
+        .lines()
+        .map(|line| {
+            let record: serde_json::Value = serde_json::from_str(line.text()).unwrap();
+            let hash = record
+                .get("customer.email")
+                .or_else(|| record.get("shipping_address"))
+                .unwrap();
+            hash.as_str().unwrap().to_owned()
+        })
+        .collect();
+    assert!(hashes[0].starts_with("hmac-sha256:"));
+    assert!(hashes.iter().all(|h| h == &hashes[0]), "{hashes:?}");
+}
+
+#[test]
+fn log_records_are_redacted() {
+    let _ = tracing_log::LogTracer::init();
+    let writer = MockWriter::new();
+    let writer2 = writer.clone();
+    let subscriber = Registry::default().with(
+        tracing_subscriber::fmt::layer()
+            .with_writer(move || writer2.clone())
+            .with_ansi(false)
+            .compact(),
+    );
+
+    tracing::subscriber::with_default(redactor().wrap(subscriber), || {
+        log::warn!("Failed to ship order to {}", Sensitive(EMAIL));
+    });
+
+    let logging_output = writer.log_output().unwrap();
+    assert_nothing_sensitive(logging_output.text());
+    assert!(
+        logging_output
+            .text()
+            .contains("Failed to ship order to [REDACTED]"),
+        "The `log` record is missing:\n{}",
+        logging_output.text()
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn otlp_attributes_are_redacted() {
+    let collector = MockCollector::start().await;
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(collector.endpoint()),
+        )
+        .install_batch(runtime::Tokio)
+        .unwrap();
+    let otel = tracing_opentelemetry::layer().with_tracer(provider.tracer("redact"));
+    let subscriber = redactor().wrap(Registry::default().with(otel));
+
+    tracing::subscriber::with_default(subscriber, || get_order_details(ORDER_NUMBER));
+    tokio::task::spawn_blocking(move || provider.shutdown())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let spans = collector.spans();
+    assert_eq!(spans.len(), 1);
+    let span = &spans[0];
+    assert_nothing_sensitive(&format!("{span:?}"));
+    assert_eq!(
+        attribute(&span.attributes, "order_number").as_deref(),
+        Some("[REDACTED]")
+    );
+    assert!(attribute(&span.attributes, "customer.email")
+        .unwrap()
+        .starts_with("hmac-sha256:"));
+    let event = &span.events[0];
+    assert_eq!(
+        attribute(&event.attributes, "shipping_address").as_deref(),
+        Some("[REDACTED]")
+    );
+    assert_eq!(attribute(&event.attributes, "internal_notes"), None);
+}
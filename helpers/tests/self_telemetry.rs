@@ -0,0 +1,139 @@
+use helpers::init_test_recorder;
+use helpers::otlp::{Behaviour, MockCollector};
+use helpers::self_telemetry::{
+    install_error_handler, FallbackLog, MeteredSpanExporter, BATCH_SIZE_HISTOGRAM,
+    DROPPED_SPANS_COUNTER, EXPORT_DURATION_HISTOGRAM, EXPORT_FAILURES_COUNTER,
+};
+use helpers::MockWriter;
+use metrics_util::debugging::{DebugValue, Snapshotter};
+use opentelemetry::trace::{Tracer, TracerProvider};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::runtime;
+use opentelemetry_sdk::trace::{BatchConfigBuilder, BatchSpanProcessor};
+use std::time::Duration;
+
+/// The value of every metric, by name and labels, e.g. `otel_span_export_failures{reason=error}`.
+fn metrics(snapshotter: &Snapshotter) -> Vec<(String, DebugValue)> {
+    snapshotter
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .map(|(key, _, _, value)| {
+            let labels: Vec<_> = key
+                .key()
+                .labels()
+                .map(|l| format!("{}={}", l.key(), l.value()))
+                .collect();
+            let name = match labels.is_empty() {
+                true => key.key().name().to_owned(),
+                false => format!("{}{{{}}}", key.key().name(), labels.join(",")),
+            };
+            (name, value)
+        })
+        .collect()
+}
+
+fn metric<'a>(metrics: &'a [(String, DebugValue)], name: &str) -> Option<&'a DebugValue> {
+    metrics
+        .iter()
+        .find_map(|(n, value)| (n == name).then_some(value))
+}
+
+// A single test: the recorder and the error handler are global.
+#[tokio::test]
+async fn export_failures_and_drops_are_reported() {
+    let snapshotter = init_test_recorder();
+    let fallback_output = MockWriter::new();
+    let fallback = FallbackLog::new(fallback_output.clone());
+    install_error_handler(fallback.clone());
+    let collector = MockCollector::start().await;
+    let provider = |queue_size: usize, export_timeout: Duration| {
+        let exporter = opentelemetry_otlp::new_exporter()
+            .tonic()
+            .with_endpoint(collector.endpoint())
+            .with_timeout(Duration::from_secs(5))
+            .build_span_exporter()
+            .unwrap();
+        let config = BatchConfigBuilder::default()
+            .with_max_queue_size(queue_size)
+            .with_max_export_timeout(export_timeout)
+            .build();
+        let processor = BatchSpanProcessor::builder(
+            MeteredSpanExporter::new(exporter, fallback.clone()),
+            runtime::Tokio,
+        )
+        .with_batch_config(config)
+        .build();
+        opentelemetry_sdk::trace::TracerProvider::builder()
+            .with_span_processor(processor)
+            .build()
+    };
+
+    // The collector is down, and the processor doesn't get to run until we yield: the queue
+    // only has room for 3 spans, on top of the resource sent when the provider is built.
+    collector.set_behaviour(Behaviour::Reject);
+    let rejected = provider(4, Duration::from_secs(5));
+    for _ in 0..18 {
+        rejected
+            .tracer("self_telemetry")
+            .in_span("rejected", |_| {});
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    // The export fails either way, whether it's triggered by the timer or by the flush.
+    // Dropping the provider shuts it down.
+    let _ = tokio::task::spawn_blocking(move || rejected.force_flush()).await;
+
+    // The collector is slow: the processor gives up on the export.
+    collector.set_behaviour(Behaviour::Delay(Duration::from_secs(2)));
+    let delayed = provider(4, Duration::from_millis(100));
+    delayed.tracer("self_telemetry").in_span("delayed", |_| {});
+    let flushed = tokio::task::spawn_blocking(move || delayed.force_flush())
+        .await
+        .unwrap();
+    assert!(flushed[0].is_err());
+
+    assert!(collector.spans().is_empty());
+    // Taking a snapshot drains the histograms: we only take one.
+    let metrics = metrics(&snapshotter);
+    assert_eq!(
+        metric(&metrics, DROPPED_SPANS_COUNTER),
+        Some(&DebugValue::Counter(15))
+    );
+    for reason in ["error", "timeout"] {
+        assert_eq!(
+            metric(
+                &metrics,
+                &format!("{EXPORT_FAILURES_COUNTER}{{reason={reason}}}")
+            ),
+            Some(&DebugValue::Counter(1)),
+            "{reason}"
+        );
+    }
+    let Some(DebugValue::Histogram(sizes)) = metric(&metrics, BATCH_SIZE_HISTOGRAM) else {
+        panic!("`{BATCH_SIZE_HISTOGRAM}` wasn't recorded")
+    };
+    let sizes: Vec<f64> = sizes.iter().map(|s| s.into_inner()).collect();
+    assert_eq!(sizes, [3.0, 1.0]);
+    let Some(DebugValue::Histogram(durations)) = metric(&metrics, EXPORT_DURATION_HISTOGRAM) else {
+        panic!("`{EXPORT_DURATION_HISTOGRAM}` wasn't recorded")
+    };
+    assert_eq!(durations.len(), 2);
+    assert!(durations[1].into_inner() >= 0.1);
+
+    // Each message shows up once, with a count for the 10th occurrence.
+    let output = fallback_output.log_output().unwrap();
+    let mut lines = output.lines();
+    lines
+        .next_some()
+        .assert_eq("Dropped a span: the export queue is full");
+    lines
+        .next_some()
+        .assert_eq("Dropped a span: the export queue is full (10 times so far)");
+    lines
+        .next_some()
+        .assert_regex_match("^Failed to export spans: .*The mock collector is rejecting requests");
+    lines
+        .next_some()
+        .assert_eq("Failed to export spans: timed out");
+    lines.end();
+}
@@ -0,0 +1,111 @@
+use helpers::link_recorder::LinkRecorder;
+use helpers::span_tree::SpanTreeLayer;
+use helpers::MockWriter;
+use std::thread::JoinHandle;
+use tracing::Dispatch;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+/// Spawn a named thread, with the current dispatcher as its default.
+fn spawn(name: &str, f: impl FnOnce() + Send + 'static) -> JoinHandle<()> {
+    let dispatch = tracing::dispatcher::get_default(Dispatch::clone);
+    std::thread::Builder::new()
+        .name(name.into())
+        .spawn(move || tracing::dispatcher::with_default(&dispatch, f))
+        .unwrap()
+}
+
+/// The scenario from the `thread_local_state` exercise.
+fn do_something() -> JoinHandle<()> {
+    let spawner_span = tracing::info_span!("spawner");
+
+    let parent = spawner_span.clone();
+    let handle = spawn("worker-1", move || {
+        let _spawned_span = tracing::info_span!(parent: &parent, "spawned1").entered();
+    });
+    handle.join().unwrap();
+
+    spawn("worker-2", move || {
+        let spawned_span = tracing::info_span!(parent: None, "spawned2");
+        spawned_span.follows_from(&spawner_span);
+        let _spawned_span = spawned_span.entered();
+    })
+}
+
+/// Replace durations with a placeholder, so that the output can be compared verbatim.
+fn without_durations(rendered: &str) -> String {
+    regex::Regex::new(r"\d+(\.\d+)?(ns|µs|ms|s)\b")
+        .unwrap()
+        .replace_all(rendered, "<duration>")
+        .into_owned()
+}
+
+#[test]
+fn cross_thread_causality_is_rendered_once_every_span_is_closed() {
+    let text = MockWriter::new();
+    let dot = MockWriter::new();
+    let layer = SpanTreeLayer::new()
+        .with_text_output(text.clone())
+        .with_dot_output(dot.clone());
+    let subscriber = Registry::default().with(layer);
+
+    tracing::subscriber::with_default(subscriber, || {
+        do_something().join().unwrap();
+    });
+
+    let text = text.log_output().unwrap();
+    assert_eq!(
+        without_durations(text.text()),
+        "spawner [cross_thread_causality_is_rendered_once_every_span_is_closed] <duration>\n    \
+        spawned1 [worker-1] <duration>\n    \
+        ~> spawned2 [worker-2] <duration>\n"
+    );
+    let dot = dot.log_output().unwrap();
+    assert_eq!(
+        without_durations(dot.text()),
+        r#"digraph spans {
+    span0 [label="spawner\ncross_thread_causality_is_rendered_once_every_span_is_closed\n<duration>"];
+    span1 [label="spawned1\nworker-1\n<duration>"];
+    span2 [label="spawned2\nworker-2\n<duration>"];
+    span0 -> span1;
+    span0 -> span2 [style=dashed, label="follows_from"];
+}
+"#
+    );
+}
+
+#[test]
+fn every_follows_from_link_is_kept() {
+    let text = MockWriter::new();
+    let mock = MockWriter::new();
+    let subscriber = Registry::default()
+        .with(SpanTreeLayer::new().with_text_output(text.clone()))
+        .with(LinkRecorder::new(mock.clone()));
+
+    tracing::subscriber::with_default(subscriber, || {
+        let root = tracing::info_span!("process order");
+        let _root = root.enter();
+        let first = tracing::info_span!("first attempt");
+        let second = tracing::info_span!("second attempt");
+        let retry = tracing::info_span!("retry");
+        retry.follows_from(&first).follows_from(&second);
+        let _retry = retry.enter();
+    });
+
+    let text = text.log_output().unwrap();
+    assert_eq!(
+        without_durations(text.text()),
+        "process order [every_follows_from_link_is_kept] <duration>\n    \
+        first attempt [every_follows_from_link_is_kept] <duration>\n    \
+        second attempt [every_follows_from_link_is_kept] <duration>\n    \
+        retry [every_follows_from_link_is_kept] <duration> (follows from: first attempt, second attempt)\n"
+    );
+
+    let mock = mock.log_output().unwrap();
+    let mut lines = mock.lines();
+    lines.next_some().assert_eq("process order");
+    lines
+        .next_some()
+        .assert_eq("retry - parent: process order - follows_from: first attempt, second attempt");
+    lines.end();
+}
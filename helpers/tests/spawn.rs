@@ -0,0 +1,115 @@
+use helpers::link_recorder::LinkRecorder;
+use helpers::spawn::{
+    spawn, spawn_blocking, spawn_blocking_with, spawn_in_set, spawn_in_set_with, spawn_thread,
+    spawn_thread_with, spawn_with, Link,
+};
+use helpers::MockWriter;
+use tracing::subscriber::DefaultGuard;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+fn set_test_subscriber() -> (MockWriter, DefaultGuard) {
+    let writer = MockWriter::new();
+    let subscriber = Registry::default().with(LinkRecorder::new(writer.clone()));
+    (writer, tracing::subscriber::set_default(subscriber))
+}
+
+fn spawned() {
+    tracing::info_span!("spawned").in_scope(|| {});
+}
+
+fn assert_logged(writer: &MockWriter, expected: &str) {
+    let output = writer.log_output().unwrap();
+    assert!(
+        output.lines().any(|line| line.text() == expected),
+        "`{expected}` is missing from the output:\n{}",
+        output.text()
+    );
+}
+
+#[test]
+fn threads() {
+    let (writer, _guard) = set_test_subscriber();
+
+    let spawner = tracing::info_span!("spawner");
+    let (child, follower) = spawner.in_scope(|| {
+        let child = spawn_thread(spawned);
+        let follower = spawn_thread_with(
+            Link::FollowsFrom(tracing::info_span!(parent: None, "follower")),
+            || {},
+        );
+        (child, follower)
+    });
+    child.join().unwrap();
+    follower.join().unwrap();
+
+    assert_logged(&writer, "spawned - parent: spawner");
+    assert_logged(&writer, "follower - follows_from: spawner");
+}
+
+#[tokio::test]
+async fn tasks() {
+    let (writer, _guard) = set_test_subscriber();
+
+    let spawner = tracing::info_span!("spawner");
+    let (child, follower) = spawner.in_scope(|| {
+        let child = spawn(async { spawned() });
+        let follower = spawn_with(
+            Link::FollowsFrom(tracing::info_span!(parent: None, "follower")),
+            async {},
+        );
+        (child, follower)
+    });
+    child.await.unwrap();
+    follower.await.unwrap();
+
+    assert_logged(&writer, "spawned - parent: spawner");
+    assert_logged(&writer, "follower - follows_from: spawner");
+}
+
+#[tokio::test]
+async fn blocking_tasks() {
+    let (writer, _guard) = set_test_subscriber();
+
+    let spawner = tracing::info_span!("spawner");
+    let (child, follower) = spawner.in_scope(|| {
+        let child = spawn_blocking(spawned);
+        let follower = spawn_blocking_with(
+            Link::FollowsFrom(tracing::info_span!(parent: None, "follower")),
+            || {},
+        );
+        (child, follower)
+    });
+    child.await.unwrap();
+    follower.await.unwrap();
+
+    assert_logged(&writer, "spawned - parent: spawner");
+    assert_logged(&writer, "follower - follows_from: spawner");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn join_sets() {
+    let (writer, _guard) = set_test_subscriber();
+
+    let mut join_set = tokio::task::JoinSet::new();
+    let spawner = tracing::info_span!("spawner");
+    spawner.in_scope(|| {
+        for _ in 0..5 {
+            spawn_in_set(&mut join_set, async { spawned() });
+        }
+        spawn_in_set_with(
+            &mut join_set,
+            Link::FollowsFrom(tracing::info_span!(parent: None, "follower")),
+            async {},
+        );
+    });
+    while join_set.join_next().await.is_some() {}
+
+    let output = writer.log_output().unwrap();
+    let children = output
+        .lines()
+        .filter(|line| line.text() == "spawned - parent: spawner")
+        .count();
+    assert_eq!(children, 5, "{}", output.text());
+    assert_logged(&writer, "follower - follows_from: spawner");
+}
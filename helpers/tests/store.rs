@@ -0,0 +1,100 @@
+use helpers::event_fields::FieldValue;
+use helpers::store::SpanStore;
+use std::collections::HashSet;
+use tokio::task::yield_now;
+use tracing::field::Empty;
+use tracing::{Instrument, Level, Span};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+async fn do_something(id: u16) {
+    yield_now().await;
+    Span::current().record("caller_id", id);
+    yield_now().await;
+}
+
+#[tokio::test]
+async fn every_task_gets_its_own_caller_id() {
+    let store = SpanStore::new();
+    let _guard = tracing::subscriber::set_default(Registry::default().with(store.layer()));
+
+    let mut join_set = tokio::task::JoinSet::new();
+    for i in 0..10 {
+        let span = tracing::info_span!("Task", caller_id = Empty);
+        join_set.spawn(do_something(i).instrument(span));
+    }
+    while join_set.join_next().await.is_some() {}
+
+    let tasks = store.spans_named("Task");
+    assert_eq!(tasks.len(), 10);
+    let caller_ids: HashSet<_> = tasks
+        .iter()
+        .map(|task| task.field("caller_id").unwrap().to_string())
+        .collect();
+    assert_eq!(caller_ids.len(), 10);
+    for task in &tasks {
+        // Each task is polled (at least) three times, once per `.await` point plus the last one.
+        assert!(task.entered_at.len() >= 3);
+        assert_eq!(task.entered_at.len(), task.exited_at.len());
+        assert!(task.is_closed());
+        assert_eq!(task.level, Level::INFO);
+    }
+}
+
+#[test]
+fn span_tree_and_events() {
+    let store = SpanStore::new();
+    let subscriber = Registry::default().with(store.layer());
+
+    tracing::subscriber::with_default(subscriber, || {
+        let total = tracing::info_span!("process total price", outcome = Empty);
+        total.in_scope(|| {
+            for order_number in [1, 4] {
+                let span = tracing::trace_span!("retrieve order", order_number);
+                let _guard = span.enter();
+                if order_number == 4 {
+                    tracing::error!(order_number, "Failed to talk to the database");
+                }
+            }
+            Span::current().record("outcome", "failure");
+            Span::current().record("outcome", "retried");
+        });
+        let follower = tracing::info_span!("send report");
+        follower.follows_from(&total);
+    });
+
+    let total = store.span("process total price");
+    assert_eq!(total.field_history("outcome").len(), 2);
+    assert_eq!(
+        total.field("outcome"),
+        Some(&FieldValue::Str("retried".into()))
+    );
+    assert!(total.is_closed());
+
+    let orders = store.children(&total);
+    assert_eq!(orders.len(), 2);
+    assert!(orders.iter().all(|o| o.name == "retrieve order"));
+    assert_eq!(orders[1].field("order_number"), Some(&FieldValue::I64(4)));
+    assert_eq!(
+        store
+            .ancestors(&orders[1])
+            .iter()
+            .map(|s| s.name)
+            .collect::<Vec<_>>(),
+        ["process total price"]
+    );
+
+    let errors = store.events_in(&total);
+    assert_eq!(errors.len(), 1);
+    let error = &errors[0];
+    assert_eq!(error.level, Level::ERROR);
+    assert_eq!(
+        error.message().as_deref(),
+        Some("Failed to talk to the database")
+    );
+    assert_eq!(error.ancestry, [total.id, orders[1].id]);
+
+    let follower = store.span("send report");
+    assert_eq!(follower.parent, None);
+    assert_eq!(follower.follows_from, [total.id]);
+}
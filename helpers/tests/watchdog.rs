@@ -0,0 +1,125 @@
+use helpers::watchdog::{WatchdogLayer, OPEN_SPANS_GAUGE};
+use helpers::MockWriter;
+use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+use std::time::Duration;
+use tracing::Dispatch;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+fn fmt_layer(writer: MockWriter) -> impl tracing_subscriber::Layer<Registry> {
+    tracing_subscriber::fmt::layer()
+        .with_writer(move || writer.clone())
+        .compact()
+        .with_ansi(false)
+        .without_time()
+}
+
+#[test]
+fn hanging_spans_are_reported_with_backoff() {
+    let writer = MockWriter::new();
+    let (watchdog, watchdog_guard) =
+        WatchdogLayer::new(Duration::from_secs(60), Duration::from_millis(5));
+    let watchdog = watchdog.with_threshold("retrieve order", Duration::from_millis(50));
+    let subscriber = Registry::default()
+        .with(fmt_layer(writer.clone()))
+        .with(watchdog);
+    let dispatch = Dispatch::new(subscriber);
+    watchdog_guard.dispatch_to(&dispatch);
+
+    tracing::dispatcher::with_default(&dispatch, || {
+        let parent = tracing::info_span!("process total price");
+        let _guard = parent.enter();
+        // It doesn't have a custom threshold: it can stay open for a long time.
+        let _sibling = tracing::info_span!("validate order").entered();
+        let span = tracing::info_span!(
+            "retrieve order",
+            order_number = 3,
+            outcome = tracing::field::Empty
+        );
+        span.record("outcome", "pending");
+        // Warnings are due after 50ms, 100ms and 200ms, but the watchdog thread may run late.
+        span.in_scope(|| std::thread::sleep(Duration::from_millis(300)));
+    });
+
+    let output = writer.log_output().unwrap();
+    let warnings: Vec<_> = output
+        .lines()
+        .filter(|line| line.text().contains("span has been open for too long"))
+        .collect();
+    assert!(!warnings.is_empty(), "{}", output.text());
+    let open_for: Vec<u64> = warnings
+        .iter()
+        .map(|line| {
+            let (_, rest) = line.text().split_once("open_for_ms=").unwrap();
+            rest.split(' ').next().unwrap().parse().unwrap()
+        })
+        .collect();
+    assert!(open_for[0] >= 50, "{open_for:?}");
+    // The interval between two warnings is at least 50ms, then 100ms, and so on.
+    for (i, pair) in open_for.windows(2).enumerate() {
+        assert!(pair[1] - pair[0] >= 50 << i, "{open_for:?}");
+    }
+    let warning = warnings[0].text();
+    assert!(warning.starts_with(" WARN helpers::watchdog"), "{warning}");
+    assert!(
+        warning.contains(r#"span_name="retrieve order""#),
+        "{warning}"
+    );
+    assert!(
+        warning.contains(r#"ancestry="process total price > validate order > retrieve order""#),
+        "{warning}"
+    );
+    assert!(
+        warning.contains(r#"fields="order_number=3 outcome=\"pending\"""#),
+        "{warning}"
+    );
+}
+
+#[test]
+fn open_spans_are_counted() {
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    let (watchdog, _guard) = WatchdogLayer::new(Duration::from_secs(60), Duration::from_secs(1));
+    let subscriber = Registry::default().with(watchdog);
+
+    let open_spans = || -> Vec<(String, f64)> {
+        let mut gauges: Vec<_> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .filter(|(key, ..)| key.key().name() == OPEN_SPANS_GAUGE)
+            .map(|(key, _, _, value)| {
+                let label = key.key().labels().next().unwrap().value().to_owned();
+                let DebugValue::Gauge(value) = value else {
+                    panic!("`{OPEN_SPANS_GAUGE}` should be a gauge")
+                };
+                (label, value.into_inner())
+            })
+            .collect();
+        gauges.sort_by(|a, b| a.0.cmp(&b.0));
+        gauges
+    };
+
+    metrics::with_local_recorder(&recorder, || {
+        tracing::subscriber::with_default(subscriber, || {
+            let _first = tracing::info_span!("retrieve order");
+            let _second = tracing::info_span!("retrieve order");
+            let third = tracing::info_span!("process total price");
+            assert_eq!(
+                open_spans(),
+                [
+                    ("process total price".to_owned(), 1.0),
+                    ("retrieve order".to_owned(), 2.0)
+                ]
+            );
+            drop(third);
+            assert_eq!(
+                open_spans(),
+                [
+                    ("process total price".to_owned(), 0.0),
+                    ("retrieve order".to_owned(), 2.0)
+                ]
+            );
+        })
+    });
+}